@@ -0,0 +1,226 @@
+use crate::PieceTable;
+
+/// Position in the text which moves along with the edits made around it, e.g. a cursor or a
+/// bookmark.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Anchor {
+    slot: usize,
+    generation: u64,
+}
+
+/// Which side of text inserted exactly at an anchor the anchor ends up on.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Bias {
+    /// Stays before the inserted text.
+    #[default]
+    Left,
+    /// Moves after the inserted text, like a cursor does when typing.
+    Right,
+}
+
+/// Anchor positions by slot. Slots of removed anchors are reused, with the generation of the slot
+/// telling the anchors which used it before from the current one.
+#[derive(Debug, Default)]
+pub(crate) struct Anchors {
+    slots: Vec<(u64, Option<(usize, Bias)>)>,
+    free: Vec<usize>,
+}
+
+impl Anchors {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.free.len() == self.slots.len()
+    }
+
+    fn get(&self, anchor: Anchor) -> Option<(usize, Bias)> {
+        match self.slots.get(anchor.slot) {
+            Some((generation, position)) if *generation == anchor.generation => *position,
+            _ => None,
+        }
+    }
+
+    /// Moves the anchors after `removed` bytes at `at` got replaced by `inserted` bytes.
+    pub(crate) fn shift(&mut self, at: usize, removed: usize, inserted: usize) {
+        for (pos, bias) in self
+            .slots
+            .iter_mut()
+            .filter_map(|(_, position)| position.as_mut())
+        {
+            if *pos < at {
+                continue;
+            }
+            *pos = if *pos > at + removed || (*pos == at + removed && removed > 0) {
+                *pos - removed + inserted
+            } else {
+                match bias {
+                    Bias::Left => at,
+                    Bias::Right => at + inserted,
+                }
+            };
+        }
+    }
+}
+
+impl PieceTable<'_> {
+    /// Creates an anchor at byte index `idx`, or returns `None` if it is out of bounds.
+    ///
+    /// The anchor moves with the text around it when it is edited, undone or redone. Anchors
+    /// inside removed text end up where the text was.
+    pub fn anchor(&mut self, idx: usize, bias: Bias) -> Option<Anchor> {
+        if idx > self.len() {
+            return None;
+        }
+        let anchors = &mut self.anchors;
+        let slot = anchors.free.pop().unwrap_or_else(|| {
+            anchors.slots.push((0, None));
+            anchors.slots.len() - 1
+        });
+        let (generation, position) = &mut anchors.slots[slot];
+        *position = Some((idx, bias));
+        Some(Anchor {
+            slot,
+            generation: *generation,
+        })
+    }
+
+    /// Returns the current position of `anchor`, or `None` if it was removed.
+    #[must_use]
+    pub fn anchor_position(&self, anchor: Anchor) -> Option<usize> {
+        self.anchors.get(anchor).map(|(pos, _)| pos)
+    }
+
+    /// Removes `anchor`, so that it stops being moved by edits. Its slot is reused by anchors
+    /// created afterwards, which `anchor` never refers to.
+    pub fn remove_anchor(&mut self, anchor: Anchor) {
+        if self.anchors.get(anchor).is_none() {
+            return;
+        }
+        let (generation, position) = &mut self.anchors.slots[anchor.slot];
+        *generation += 1;
+        *position = None;
+        self.anchors.free.push(anchor.slot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    #[test]
+    fn should_follow_insertions_before_it() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("hello world");
+        let anchor = table.anchor(6, Bias::Left).unwrap();
+
+        // when
+        table.insert_str("big ", 0);
+        table.insert_char('!', table.len());
+
+        // then
+        assert_eq!(table.anchor_position(anchor), Some(10));
+    }
+
+    #[test]
+    fn should_not_move_when_piece_it_is_in_gets_split() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("hello world");
+        let anchor = table.anchor(8, Bias::Left).unwrap();
+
+        // when
+        table.insert_char(',', 5);
+
+        // then
+        assert_eq!(table.anchor_position(anchor), Some(9));
+    }
+
+    #[test]
+    fn should_keep_side_of_insertion_at_it() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("ac");
+        let left = table.anchor(1, Bias::Left).unwrap();
+        let right = table.anchor(1, Bias::Right).unwrap();
+
+        // when
+        table.insert_str("bb", 1);
+
+        // then
+        assert_eq!(table.anchor_position(left), Some(1));
+        assert_eq!(table.anchor_position(right), Some(3));
+    }
+
+    #[test]
+    fn should_move_to_start_of_removed_text() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("one two three");
+        let inside = table.anchor(5, Bias::Left).unwrap();
+        let after = table.anchor(10, Bias::Left).unwrap();
+
+        // when
+        table.remove(4..8);
+
+        // then
+        assert_eq!(table.anchor_position(inside), Some(4));
+        assert_eq!(table.anchor_position(after), Some(6));
+    }
+
+    #[test]
+    fn should_follow_undo_and_redo() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("text");
+        let anchor = table.anchor(4, Bias::Left).unwrap();
+        table.insert_str("more ", 0);
+
+        // when
+        table.undo();
+        let undone = table.anchor_position(anchor);
+        table.redo();
+
+        // then
+        assert_eq!(undone, Some(4));
+        assert_eq!(table.anchor_position(anchor), Some(9));
+    }
+
+    #[test]
+    fn should_forget_removed_anchor() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("text");
+        let anchor = table.anchor(2, Bias::Left).unwrap();
+
+        // when
+        table.remove_anchor(anchor);
+
+        // then
+        assert_eq!(table.anchor_position(anchor), None);
+        assert_eq!(table.anchor(5, Bias::Left), None);
+    }
+
+    #[test]
+    fn should_reuse_slot_of_removed_anchor() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("text");
+        let removed = table.anchor(1, Bias::Left).unwrap();
+        table.remove_anchor(removed);
+
+        // when
+        let anchor = table.anchor(3, Bias::Left).unwrap();
+        table.remove_anchor(removed);
+        table.insert_char('!', 0);
+
+        // then
+        assert_eq!(table.anchors.slots.len(), 1);
+        assert_eq!(table.anchor_position(removed), None);
+        assert_eq!(table.anchor_position(anchor), Some(4));
+        table.remove_anchor(anchor);
+        assert!(table.anchors.is_empty());
+    }
+}
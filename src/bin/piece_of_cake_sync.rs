@@ -0,0 +1,22 @@
+//! Sync server for editing a text together, see [`SyncServer`].
+//!
+//! Usage: `piece_of_cake_sync [ADDRESS] [FILE]`, where `ADDRESS` defaults to `127.0.0.1:7878`
+//! and `FILE` holds the text the clients start from, empty if not given.
+
+use piece_of_cake::SyncServer;
+use std::env;
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+
+fn main() -> io::Result<()> {
+    let mut args = env::args().skip(1);
+    let addr = args.next().unwrap_or_else(|| "127.0.0.1:7878".to_string());
+    let txt = match args.next() {
+        Some(path) => fs::read_to_string(path)?,
+        None => String::new(),
+    };
+    let listener = TcpListener::bind(addr)?;
+    println!("listening on {}", listener.local_addr()?);
+    SyncServer::new(txt).serve(&listener)
+}
@@ -0,0 +1,304 @@
+use crate::{slice_pieces, PieceTable, Source};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Who inserted a piece of text, and when.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Authorship {
+    pub author: u64,
+    pub timestamp: SystemTime,
+    /// Number of the edit which inserted the text, counting all the edits made to the table.
+    pub edit: u64,
+}
+
+/// Part of the text with the same authorship, `None` for the original text, text inserted
+/// without an author set and text pasted from the buffers of another table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Blame {
+    pub range: Range<usize>,
+    pub authorship: Option<Authorship>,
+}
+
+type Spans = [(Range<usize>, Authorship)];
+
+/// Authorship of the ranges of the addition buffer. As the buffer is only appended to, it stays
+/// right however pieces get split, moved or merged.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Authors {
+    author: Option<u64>,
+    edits: u64,
+    spans: Vec<(Range<usize>, Authorship)>,
+    /// Authorship of the shared buffers which are copies of the addition buffer of a table split
+    /// from, by the index of the buffer.
+    shared: Vec<(usize, Arc<Spans>)>,
+}
+
+impl Authors {
+    /// Records that `appended` bytes were added to the buffer by the current author.
+    pub(crate) fn record(&mut self, appended: Range<usize>) {
+        if let Some(author) = self.author {
+            self.record_as(appended, author);
+        }
+    }
+
+    pub(crate) fn record_as(&mut self, appended: Range<usize>, author: u64) {
+        if appended.is_empty() {
+            return;
+        }
+        let authorship = Authorship {
+            author,
+            timestamp: SystemTime::now(),
+            edit: self.edits,
+        };
+        match self.spans.last_mut() {
+            Some((range, last))
+                if range.end == appended.start
+                    && (last.author, last.edit) == (author, self.edits) =>
+            {
+                range.end = appended.end;
+            }
+            _ => self.spans.push((appended, authorship)),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.author.is_some()
+    }
+
+    pub(crate) fn end_edit(&mut self) {
+        self.edits += 1;
+    }
+
+    /// Returns the authorship for a table whose shared buffer `idx` is a copy of the addition
+    /// buffer of this table, and whose other shared buffers are the ones of this table.
+    pub(crate) fn shared_as(&self, idx: usize) -> Self {
+        let mut shared = self.shared.clone();
+        shared.push((idx, self.spans.as_slice().into()));
+        Self {
+            author: self.author,
+            edits: self.edits,
+            spans: Vec::new(),
+            shared,
+        }
+    }
+
+    /// Returns the authorship of the `range` of the buffer of `source`, split where it changes.
+    fn of(
+        &self,
+        source: &Source,
+        range: Range<usize>,
+    ) -> impl Iterator<Item = (Range<usize>, Option<Authorship>)> + '_ {
+        let spans: &Spans = match source {
+            Source::Original => &[],
+            Source::Add => &self.spans,
+            Source::Shared(idx) => self
+                .shared
+                .iter()
+                .find(|(buffer, _)| buffer == idx)
+                .map_or(&[], |(_, spans)| spans),
+        };
+        let first = spans.partition_point(|(span, _)| span.end <= range.start);
+        let mut start = range.start;
+        let mut spans = spans[first..].iter();
+        let mut next = spans.next();
+        std::iter::from_fn(move || {
+            if start >= range.end {
+                return None;
+            }
+            let part = match next {
+                Some((span, authorship)) if span.start <= start => {
+                    let end = span.end.min(range.end);
+                    next = spans.next();
+                    (start..end, Some(*authorship))
+                }
+                Some((span, _)) => (start..span.start.min(range.end), None),
+                None => (start..range.end, None),
+            };
+            start = part.0.end;
+            Some(part)
+        })
+    }
+}
+
+impl PieceTable<'_> {
+    /// Sets who makes the following edits, `None` stops recording authorship.
+    pub fn set_author(&mut self, author: Option<u64>) {
+        self.authors.author = author;
+    }
+
+    #[must_use]
+    pub fn author(&self) -> Option<u64> {
+        self.authors.author
+    }
+
+    /// Returns who inserted the text in `range` and when, or `None` if it is out of bounds.
+    ///
+    /// The authorship of text stays the same when it is moved around by edits, undos and redos.
+    #[must_use]
+    pub fn blame(&self, range: Range<usize>) -> Option<Vec<Blame>> {
+        if range.start > range.end || range.end > self.len() {
+            return None;
+        }
+        let mut blame: Vec<Blame> = Vec::new();
+        let mut start = range.start;
+        for piece in slice_pieces(&self.pieces, range) {
+            for (part, authorship) in self.authors.of(&piece.source, piece.range) {
+                let end = start + part.len();
+                match blame.last_mut() {
+                    Some(last) if last.authorship == authorship => last.range.end = end,
+                    _ => blame.push(Blame {
+                        range: start..end,
+                        authorship,
+                    }),
+                }
+                start = end;
+            }
+        }
+        Some(blame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroU64;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    fn authors(blame: &[Blame]) -> Vec<(Range<usize>, Option<u64>)> {
+        blame
+            .iter()
+            .map(|part| (part.range.clone(), part.authorship.map(|a| a.author)))
+            .collect()
+    }
+
+    #[test]
+    fn should_blame_nobody_for_original_text() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("hello world");
+        table.insert_str("big ", 6);
+
+        // when
+        let blame = table.blame(0..table.len()).unwrap();
+
+        // then
+        assert_eq!(
+            blame,
+            vec![Blame {
+                range: 0..15,
+                authorship: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn should_blame_authors_of_inserted_text() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("hello world");
+        table.set_author(Some(1));
+        table.insert_str("big ", 6);
+        table.set_author(Some(2));
+        table.insert_char('!', 15);
+
+        // when
+        let blame = table.blame(0..table.len()).unwrap();
+
+        // then
+        assert_eq!(
+            authors(&blame),
+            vec![
+                (0..6, None),
+                (6..10, Some(1)),
+                (10..15, None),
+                (15..16, Some(2))
+            ]
+        );
+        assert_eq!(blame[1].authorship.unwrap().edit, 0);
+        assert_eq!(blame[3].authorship.unwrap().edit, 1);
+    }
+
+    #[test]
+    fn should_keep_authorship_of_split_and_moved_text() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("");
+        table.set_author(Some(1));
+        table.insert_str("abcdef", 0);
+        table.set_author(Some(2));
+        table.insert_str("XY", 3);
+        table.remove(1..2);
+        table.undo();
+        table.undo();
+        table.redo();
+
+        // when
+        let blame = table.blame(2..7).unwrap();
+
+        // then
+        assert_eq!(
+            authors(&blame),
+            vec![(2..3, Some(1)), (3..5, Some(2)), (5..7, Some(1))]
+        );
+    }
+
+    #[test]
+    fn should_group_text_of_same_edit() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("one two");
+        table.set_author(Some(7));
+
+        // when
+        table.replace_all("o", "0", None);
+        let blame = table.blame(0..table.len()).unwrap();
+
+        // then
+        let edits: Vec<_> = blame
+            .iter()
+            .filter_map(|part| part.authorship)
+            .map(|a| a.edit)
+            .collect();
+        assert_eq!(edits, vec![0, 0]);
+    }
+
+    #[test]
+    fn should_blame_remote_replica_for_its_text() {
+        init_logger();
+        // given
+        let mut other = PieceTable::from_text("text");
+        other.enable_crdt(NonZeroU64::new(5).unwrap());
+        other.insert_str("new ", 0);
+        let mut table = PieceTable::from_text("text");
+        table.enable_crdt(NonZeroU64::new(1).unwrap());
+        table.set_author(Some(1));
+
+        // when
+        for update in other.take_updates() {
+            table.apply_update(update);
+        }
+
+        // then
+        assert_eq!(
+            authors(&table.blame(0..8).unwrap()),
+            vec![(0..4, Some(5)), (4..8, None)]
+        );
+    }
+
+    #[test]
+    fn should_refuse_range_out_of_bounds() {
+        init_logger();
+        // given
+        let table = PieceTable::from_text("text");
+
+        // when
+        let blame = table.blame(2..5);
+
+        // then
+        assert_eq!(blame, None);
+    }
+}
@@ -0,0 +1,28 @@
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// Read-only text pieces point into, either borrowed from the caller or reference counted so
+/// that tables can share it without copying.
+#[derive(Debug, Clone)]
+pub(crate) enum Buffer<'a> {
+    Borrowed(&'a str),
+    Shared(Arc<str>),
+}
+
+impl Buffer<'_> {
+    /// Tells if both are the very same text in memory, not just equal text.
+    pub(crate) fn is_same(&self, other: &Buffer<'_>) -> bool {
+        self.as_ptr() == other.as_ptr() && self.len() == other.len()
+    }
+}
+
+impl Deref for Buffer<'_> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        match self {
+            Self::Borrowed(txt) => txt,
+            Self::Shared(txt) => txt,
+        }
+    }
+}
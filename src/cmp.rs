@@ -0,0 +1,219 @@
+//! Comparisons of tables by the text they represent, regardless of how it is split into pieces
+//! and what is in the undo history.
+
+use crate::PieceTable;
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+impl PartialEq<PieceTable<'_>> for PieceTable<'_> {
+    fn eq(&self, other: &PieceTable<'_>) -> bool {
+        self.len() == other.len() && self.cmp_text(other.chunks()) == Ordering::Equal
+    }
+}
+
+impl Eq for PieceTable<'_> {}
+
+impl PartialOrd<PieceTable<'_>> for PieceTable<'_> {
+    fn partial_cmp(&self, other: &PieceTable<'_>) -> Option<Ordering> {
+        Some(self.cmp_text(other.chunks()))
+    }
+}
+
+impl Ord for PieceTable<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cmp_text(other.chunks())
+    }
+}
+
+impl Hash for PieceTable<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // `Hasher::write` is not guaranteed to give the same result for differently split input,
+        // so the text is fed in blocks of the same size no matter what the pieces look like
+        let mut block = [0; 64];
+        let mut filled = 0;
+        for chunk in self.chunks() {
+            for &byte in chunk.as_bytes() {
+                block[filled] = byte;
+                filled += 1;
+                if filled == block.len() {
+                    state.write(&block);
+                    filled = 0;
+                }
+            }
+        }
+        state.write(&block[..filled]);
+        // terminator like the one `str` uses, so that a table followed by other data in a
+        // composite key can't collide with a longer table
+        state.write_u8(0xff);
+    }
+}
+
+impl PartialEq<str> for PieceTable<'_> {
+    fn eq(&self, other: &str) -> bool {
+        self.len() == other.len() && self.cmp_text(std::iter::once(other)) == Ordering::Equal
+    }
+}
+
+impl PartialEq<&str> for PieceTable<'_> {
+    fn eq(&self, other: &&str) -> bool {
+        self == *other
+    }
+}
+
+impl PartialEq<String> for PieceTable<'_> {
+    fn eq(&self, other: &String) -> bool {
+        self == other.as_str()
+    }
+}
+
+impl PieceTable<'_> {
+    /// Compares the text byte-wise, which for UTF-8 is the same as comparing it char by char.
+    fn cmp_text<'o>(&self, other: impl Iterator<Item = &'o str>) -> Ordering {
+        let mut ours = self.chunks().map(str::as_bytes);
+        let mut theirs = other.map(str::as_bytes);
+        let mut left: &[u8] = &[];
+        let mut right: &[u8] = &[];
+        loop {
+            if left.is_empty() {
+                left = ours.next().unwrap_or_default();
+            }
+            if right.is_empty() {
+                right = theirs.next().unwrap_or_default();
+            }
+            match (left.is_empty(), right.is_empty()) {
+                (true, true) => return Ordering::Equal,
+                (true, false) => return Ordering::Less,
+                (false, true) => return Ordering::Greater,
+                (false, false) => {}
+            }
+            let common = left.len().min(right.len());
+            match left[..common].cmp(&right[..common]) {
+                Ordering::Equal => {
+                    left = &left[common..];
+                    right = &right[common..];
+                }
+                unequal => return unequal,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    fn hash_of<T: Hash + ?Sized>(value: &T) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn tables_with_same_text_and_different_pieces_are_equal() {
+        init_logger();
+        // given
+        let first = PieceTable::from_text("some text");
+        let mut second = PieceTable::from_text("some text");
+        second.insert_char('!', 4);
+        second.remove_char(4);
+
+        // when
+        let equal = first == second;
+
+        // then
+        assert!(second.pieces.len() > 1);
+        assert!(equal);
+    }
+
+    #[test]
+    fn undo_history_does_not_affect_equality() {
+        init_logger();
+        // given
+        let first = PieceTable::from_text("text");
+        let mut second = PieceTable::from_text("text");
+        second.insert_char('s', 4);
+        second.undo();
+
+        // when
+        let equal = first == second;
+
+        // then
+        assert!(equal);
+    }
+
+    #[test]
+    fn tables_with_different_text_are_not_equal() {
+        init_logger();
+        // given
+        let first = PieceTable::from_text("text");
+        let second = PieceTable::from_text("texts");
+
+        // when
+        let equal = first == second;
+
+        // then
+        assert!(!equal);
+    }
+
+    #[test]
+    fn tables_are_ordered_like_their_text() {
+        init_logger();
+        // given
+        let mut first = PieceTable::from_text("ab");
+        first.insert_char('c', 2);
+        let mut second = PieceTable::from_text("a");
+        second.insert_str("bd", 1);
+        let third = PieceTable::from_text("abc");
+
+        // when
+        let ordering = first.cmp(&second);
+        let prefix_ordering = PieceTable::from_text("ab").cmp(&third);
+
+        // then
+        assert_eq!(ordering, Ordering::Less);
+        assert_eq!(prefix_ordering, Ordering::Less);
+        assert_eq!(first.cmp(&third), Ordering::Equal);
+    }
+
+    #[test]
+    fn equal_tables_have_equal_hashes() {
+        init_logger();
+        // given
+        let long_txt = "x".repeat(100);
+        let first = PieceTable::from_text(&long_txt);
+        let mut second = PieceTable::from_text(&long_txt[..30]);
+        second.insert_str(&long_txt[30..], 30);
+
+        // when
+        let first_hash = hash_of(&first);
+        let second_hash = hash_of(&second);
+
+        // then
+        assert_eq!(first_hash, second_hash);
+    }
+
+    #[test]
+    fn table_can_be_compared_with_strings() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("some");
+        table.insert_str(" text", 4);
+        let expected = String::from("some text");
+
+        // when
+        let with_str = table == *"some text";
+        let with_str_ref = table == "some text";
+        let with_string = table == expected;
+
+        // then
+        assert!(with_str);
+        assert!(with_str_ref);
+        assert!(with_string);
+        assert!(table != "some tex");
+    }
+}
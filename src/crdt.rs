@@ -0,0 +1,1077 @@
+//! Replicated editing in the style of RGA/YATA, as an alternative to operational transformation.
+//!
+//! Every inserted byte has an id made of the replica which inserted it and a counter of the bytes
+//! that replica inserted. The text is a sequence of items, each holding a piece of the addition
+//! buffer for bytes with consecutive ids. Removed items stay as tombstones so that later inserts
+//! can still refer to them. An insert remembers the bytes it was inserted between, which places
+//! it the same way on every replica whatever order the updates arrive in.
+
+use crate::rebase::{PieceOperation, Rebase};
+use crate::{Piece, PieceTable, Source};
+use log::warn;
+use std::collections::{BTreeMap, HashSet};
+use std::mem;
+use std::num::NonZeroU64;
+
+/// Id of a byte inserted by `replica`. Replica 0 stands for the text the replicas started from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ItemId {
+    pub replica: u64,
+    pub counter: u64,
+}
+
+impl ItemId {
+    /// Returns the id `by` bytes further, which has to be the id of a known byte or right after
+    /// one, as inserts with overflowing ids are refused.
+    fn offset(self, by: usize) -> Self {
+        let by = u64::try_from(by).expect("offsets fit in the ids");
+        Self {
+            replica: self.replica,
+            counter: self.counter + by,
+        }
+    }
+}
+
+/// Change made on one replica, to be applied on the others with [`PieceTable::apply_update`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Update {
+    /// `text` was inserted between the bytes `origin_left` and `origin_right`, its bytes get
+    /// consecutive ids starting with `id`.
+    Insert {
+        id: ItemId,
+        origin_left: Option<ItemId>,
+        origin_right: Option<ItemId>,
+        text: String,
+    },
+    /// The `len` bytes with consecutive ids starting with `id` were removed.
+    Delete { id: ItemId, len: usize },
+}
+
+#[derive(Debug, Clone)]
+struct Item {
+    id: ItemId,
+    origin_left: Option<ItemId>,
+    origin_right: Option<ItemId>,
+    piece: Piece,
+    deleted: bool,
+}
+
+impl Item {
+    fn len(&self) -> usize {
+        self.piece.len()
+    }
+
+    fn contains(&self, id: ItemId) -> bool {
+        id.replica == self.id.replica
+            && self.id.counter <= id.counter
+            && id.counter <= self.last_id().counter
+    }
+
+    fn last_id(&self) -> ItemId {
+        self.id.offset(self.len() - 1)
+    }
+
+    fn visible_len(&self) -> usize {
+        if self.deleted {
+            0
+        } else {
+            self.len()
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Crdt {
+    replica: u64,
+    counter: u64,
+    items: Vec<Item>,
+    /// Local changes not taken yet.
+    outgoing: Vec<Update>,
+    /// Remote updates waiting for the ones they depend on.
+    waiting: Vec<Update>,
+}
+
+/// Replacement of `removed` bytes at `at` by the `inserted` pieces.
+pub(crate) type Shift = (usize, usize, Vec<Piece>);
+
+/// Outcome of integrating a remote update.
+enum Integration {
+    /// The update changed the text this way.
+    Applied(Vec<Shift>),
+    /// The update depends on bytes not known yet.
+    Waiting,
+    /// The update can't come from a replica, e.g. it would split a char.
+    Invalid(&'static str),
+}
+
+impl Crdt {
+    fn new(replica: NonZeroU64, pieces: &[Piece]) -> Self {
+        let mut items = Vec::new();
+        let mut start = 0;
+        for piece in pieces.iter().filter(|piece| piece.len() > 0) {
+            let id = |counter: usize| {
+                let start = ItemId {
+                    replica: 0,
+                    counter: 0,
+                };
+                start.offset(counter)
+            };
+            items.push(Item {
+                id: id(start),
+                origin_left: start.checked_sub(1).map(id),
+                origin_right: None,
+                piece: piece.clone(),
+                deleted: false,
+            });
+            start += piece.len();
+        }
+        Self {
+            replica: replica.get(),
+            counter: 0,
+            items,
+            outgoing: Vec::new(),
+            waiting: Vec::new(),
+        }
+    }
+
+    /// Returns the index of the item containing the byte `id`.
+    fn find(&self, id: ItemId) -> Option<usize> {
+        self.items.iter().position(|item| item.contains(id))
+    }
+
+    /// Splits the item at `idx` after `offset` bytes and returns the index of the item starting
+    /// there.
+    fn split(&mut self, idx: usize, offset: usize) -> usize {
+        let item = &self.items[idx];
+        if offset == 0 {
+            return idx;
+        }
+        if offset == item.len() {
+            return idx + 1;
+        }
+        let (first, second) = item.piece.clone().split_at(offset);
+        let second = Item {
+            id: item.id.offset(offset),
+            origin_left: Some(item.id.offset(offset - 1)),
+            origin_right: item.origin_right,
+            piece: second,
+            deleted: item.deleted,
+        };
+        self.items[idx].piece = first;
+        self.items.insert(idx + 1, second);
+        idx + 1
+    }
+
+    /// Splits the item containing the byte `id` so that an item starts with it.
+    fn split_before(&mut self, id: ItemId) -> Option<usize> {
+        let idx = self.find(id)?;
+        Some(self.split(idx, self.offset_in(idx, id)))
+    }
+
+    /// Splits the item containing the byte `id` so that an item ends with it, returns the index
+    /// of the next one.
+    fn split_after(&mut self, id: ItemId) -> Option<usize> {
+        let idx = self.find(id)?;
+        Some(self.split(idx, self.offset_in(idx, id) + 1))
+    }
+
+    /// Returns the offset of the byte `id` in the item at `idx`, which contains it.
+    fn offset_in(&self, idx: usize, id: ItemId) -> usize {
+        usize::try_from(id.counter - self.items[idx].id.counter).expect("item contains the byte")
+    }
+
+    /// Splits the visible items at byte index `idx` of the text, and returns the index of the
+    /// item starting there. With `after_previous`, it is the item right after the visible byte
+    /// before `idx`, before any tombstones.
+    fn split_visible(&mut self, idx: usize, after_previous: bool) -> usize {
+        if after_previous && idx == 0 {
+            return 0;
+        }
+        let mut start = 0;
+        for item_idx in 0..self.items.len() {
+            let len = self.items[item_idx].visible_len();
+            if idx < start + len || (after_previous && len > 0 && idx == start + len) {
+                return self.split(item_idx, idx - start);
+            }
+            start += len;
+        }
+        self.items.len()
+    }
+
+    /// Returns the byte index in the text of the item at `idx`.
+    fn visible_start(&self, idx: usize) -> usize {
+        self.items[..idx].iter().map(Item::visible_len).sum()
+    }
+
+    /// Records `removed` bytes at `at` replaced with the `inserted` pieces and their text.
+    pub(crate) fn local_change(
+        &mut self,
+        at: usize,
+        removed: usize,
+        inserted: Vec<(Piece, String)>,
+    ) {
+        if removed > 0 {
+            let start = self.split_visible(at, false);
+            let end = self.split_visible(at + removed, false);
+            for item in &mut self.items[start..end] {
+                if !item.deleted {
+                    item.deleted = true;
+                    self.outgoing.push(Update::Delete {
+                        id: item.id,
+                        len: item.len(),
+                    });
+                }
+            }
+        }
+        if inserted.is_empty() {
+            return;
+        }
+
+        // inserted right after the byte before, ahead of the tombstones following it
+        let start = self.split_visible(at, true);
+        let mut origin_left = start
+            .checked_sub(1)
+            .map(|before| self.items[before].last_id());
+        let origin_right = self.items.get(start).map(|item| item.id);
+        for (idx, (piece, text)) in (start..).zip(inserted) {
+            let id = ItemId {
+                replica: self.replica,
+                counter: self.counter,
+            };
+            self.outgoing.push(Update::Insert {
+                id,
+                origin_left,
+                origin_right,
+                text,
+            });
+            let item = Item {
+                id,
+                origin_left,
+                origin_right,
+                piece,
+                deleted: false,
+            };
+            origin_left = Some(item.last_id());
+            self.counter = item.last_id().counter + 1;
+            self.items.insert(idx, item);
+        }
+    }
+
+    /// Tells if a char starts at the byte `id`, or `id` is right after the last byte of an item.
+    fn starts_char(&self, id: ItemId, table: &PieceTable) -> bool {
+        let Some(idx) = id
+            .counter
+            .checked_sub(1)
+            .and_then(|counter| self.find(ItemId { counter, ..id }))
+            .or_else(|| self.find(id))
+        else {
+            return false;
+        };
+        let offset = self.offset_in(idx, id);
+        table
+            .piece_text(&self.items[idx].piece)
+            .is_char_boundary(offset)
+    }
+
+    /// Applies a remote `update` to the items, adding inserted text to the addition buffer of
+    /// `table`.
+    fn integrate(&mut self, update: &Update, table: &mut PieceTable) -> Integration {
+        match update {
+            Update::Insert {
+                id,
+                origin_left,
+                origin_right,
+                text,
+            } => self.integrate_insert(*id, *origin_left, *origin_right, text, table),
+            Update::Delete { id, len } => self.integrate_delete(*id, *len, table),
+        }
+    }
+
+    fn integrate_insert(
+        &mut self,
+        id: ItemId,
+        origin_left: Option<ItemId>,
+        origin_right: Option<ItemId>,
+        text: &str,
+        table: &mut PieceTable,
+    ) -> Integration {
+        if self.find(id).is_some() || text.is_empty() {
+            return Integration::Applied(Vec::new());
+        }
+        let Some(end) = u64::try_from(text.len())
+            .ok()
+            .and_then(|len| id.counter.checked_add(len))
+        else {
+            return Integration::Invalid("ids of inserted bytes overflow");
+        };
+        if self.items.iter().any(|item| {
+            item.id.replica == id.replica
+                && item.id.counter < end
+                && id.counter <= item.last_id().counter
+        }) {
+            return Integration::Invalid("ids of inserted bytes are already taken");
+        }
+        if origin_left.is_some_and(|origin| self.find(origin).is_none())
+            || origin_right.is_some_and(|origin| self.find(origin).is_none())
+        {
+            return Integration::Waiting;
+        }
+        if origin_left.is_some_and(|origin| !self.starts_char(origin.offset(1), table))
+            || origin_right.is_some_and(|origin| !self.starts_char(origin, table))
+        {
+            return Integration::Invalid("insert would split a char");
+        }
+        let left = match origin_left {
+            Some(origin) => self.split_after(origin).expect("origin should be known"),
+            None => 0,
+        };
+        let right = match origin_right {
+            Some(origin) => self.split_before(origin).expect("origin should be known"),
+            None => self.items.len(),
+        };
+
+        // concurrent inserts at the same place are ordered by replica, while keeping
+        // inserts after one of them next to it
+        let mut idx = left;
+        // items passed so far by the id they start with, to find origins among them
+        let mut before_origin = BTreeMap::new();
+        let mut conflicting = HashSet::new();
+        for other_idx in left..right {
+            before_origin.insert(self.items[other_idx].id, other_idx);
+            conflicting.insert(other_idx);
+            let other = &self.items[other_idx];
+            if other.origin_left == origin_left {
+                if other.id.replica < id.replica {
+                    idx = other_idx + 1;
+                    conflicting.clear();
+                } else if other.origin_right == origin_right {
+                    break;
+                }
+            } else if let Some(other_origin) = other.origin_left.and_then(|origin| {
+                let (_, &origin_idx) = before_origin.range(..=origin).next_back()?;
+                self.items[origin_idx]
+                    .contains(origin)
+                    .then_some(origin_idx)
+            }) {
+                if !conflicting.contains(&other_origin) {
+                    idx = other_idx + 1;
+                    conflicting.clear();
+                }
+            } else {
+                break;
+            }
+        }
+
+        let start = table.addition_buffer.len();
+        table.addition_buffer.push_str(text);
+        self.items.insert(
+            idx,
+            Item {
+                id,
+                origin_left,
+                origin_right,
+                piece: Piece::new(start..table.addition_buffer.len(), Source::Add),
+                deleted: false,
+            },
+        );
+        Integration::Applied(vec![(
+            self.visible_start(idx),
+            0,
+            vec![self.items[idx].piece.clone()],
+        )])
+    }
+
+    fn integrate_delete(&mut self, id: ItemId, len: usize, table: &PieceTable) -> Integration {
+        let Some(end) = u64::try_from(len)
+            .ok()
+            .and_then(|len| id.counter.checked_add(len))
+        else {
+            return Integration::Invalid("ids of removed bytes overflow");
+        };
+        let mut counter = id.counter;
+        while counter < end {
+            let Some(idx) = self.find(ItemId { counter, ..id }) else {
+                return Integration::Waiting;
+            };
+            let item = &self.items[idx];
+            counter = item.last_id().counter + 1;
+        }
+        if len > 0
+            && !(self.starts_char(id, table)
+                && self.starts_char(ItemId { counter: end, ..id }, table))
+        {
+            return Integration::Invalid("removal would split a char");
+        }
+
+        let mut shifts = Vec::new();
+        let mut counter = id.counter;
+        while counter < end {
+            let idx = self
+                .split_before(ItemId { counter, ..id })
+                .expect("removed bytes should be known");
+            let len = self.items[idx]
+                .len()
+                .min(usize::try_from(end - counter).unwrap_or(usize::MAX));
+            self.split(idx, len);
+            counter = self.items[idx].last_id().counter + 1;
+            if !self.items[idx].deleted {
+                shifts.push((self.visible_start(idx), len, Vec::new()));
+                self.items[idx].deleted = true;
+            }
+        }
+        Integration::Applied(shifts)
+    }
+}
+
+impl PieceTable<'_> {
+    /// Starts replicating the table as `replica`, which must be unique among the replicas.
+    ///
+    /// All the replicas have to start from the same text. From then on local edits, undos and
+    /// redos are sent as updates by [`PieceTable::take_updates`], and the updates of the other
+    /// replicas are applied with [`PieceTable::apply_update`].
+    pub fn enable_crdt(&mut self, replica: NonZeroU64) {
+        self.crdt = Some(Crdt::new(replica, &self.pieces));
+    }
+
+    /// Returns the updates for the local changes made since the last call, to be sent to the
+    /// other replicas.
+    pub fn take_updates(&mut self) -> Vec<Update> {
+        self.crdt
+            .as_mut()
+            .map(|crdt| mem::take(&mut crdt.outgoing))
+            .unwrap_or_default()
+    }
+
+    /// Applies an update of another replica. Updates can arrive in any order and more than once:
+    /// an update depending on text not received yet waits for it. Updates no replica could have
+    /// sent, e.g. removing half of a char, are dropped with a warning.
+    ///
+    /// Remote changes aren't part of the undo history. The local edits in it are moved past them
+    /// instead, so that undoing an edit keeps the text of the other replicas.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the table is not replicated with [`PieceTable::enable_crdt`].
+    pub fn apply_update(&mut self, update: Update) {
+        if let Err(reason) = self.integrate_update(update, true) {
+            warn!("dropping update: {reason}");
+        }
+    }
+
+    /// Applies an update which can't wait for others, as it comes from a replica which saw all
+    /// it depends on. Nothing changes if it fails.
+    pub(crate) fn apply_update_now(&mut self, update: Update) -> Result<(), &'static str> {
+        self.integrate_update(update, false)
+    }
+
+    fn integrate_update(&mut self, update: Update, can_wait: bool) -> Result<(), &'static str> {
+        let mut crdt = self
+            .crdt
+            .take()
+            .expect("replication should be enabled with enable_crdt");
+        let mut shifts = Vec::new();
+        let mut result = Ok(());
+        match self.integrate_as_author(&mut crdt, &update) {
+            Integration::Applied(applied) => shifts.extend(applied),
+            Integration::Waiting if can_wait => crdt.waiting.push(update),
+            Integration::Waiting => result = Err("update depends on unknown text"),
+            Integration::Invalid(reason) => result = Err(reason),
+        }
+        while !shifts.is_empty() {
+            let waiting = mem::take(&mut crdt.waiting);
+            let count = waiting.len();
+            for update in waiting {
+                match self.integrate_as_author(&mut crdt, &update) {
+                    Integration::Applied(applied) => shifts.extend(applied),
+                    Integration::Waiting => crdt.waiting.push(update),
+                    Integration::Invalid(reason) => warn!("dropping update {update:?}: {reason}"),
+                }
+            }
+            if crdt.waiting.len() == count {
+                break;
+            }
+        }
+        self.crdt = Some(crdt);
+        if !shifts.is_empty() {
+            self.apply_remote(shifts);
+        }
+        result
+    }
+
+    /// Applies changes of other replicas, moving the anchors and the undo and redo history past
+    /// them.
+    pub(crate) fn apply_remote(&mut self, shifts: Vec<Shift>) {
+        self.journal_remote(&shifts);
+        if self.rebase.is_none() && !(self.history.is_empty() && self.redo.is_empty()) {
+            let (history, redo) = (mem::take(&mut self.history), mem::take(&mut self.redo));
+            self.rebase = Some(Rebase::new(&self.pieces, &history, &redo));
+        }
+        let mut changes = Vec::new();
+        for (at, removed, inserted) in shifts {
+            let inserted_len = inserted.iter().map(Piece::len).sum();
+            let remote = PieceOperation::new()
+                .retain(at)
+                .insert(inserted)
+                .delete(removed)
+                .retain(self.len() - at - removed);
+            if let Some(rebase) = &mut self.rebase {
+                rebase.follow(&remote);
+            }
+            changes.extend(remote.apply(&mut self.pieces));
+            self.anchors.shift(at, removed, inserted_len);
+        }
+        // remote changes make versions too, but aren't in the history to be undone
+        self.versions
+            .applied(&changes.into_iter().collect(), &self.pieces);
+        self.saved_at = None;
+    }
+
+    /// Integrates `update`, blaming the text it inserts on the replica which inserted it.
+    fn integrate_as_author(&mut self, crdt: &mut Crdt, update: &Update) -> Integration {
+        let appended = self.addition_buffer.len();
+        let integration = crdt.integrate(update, self);
+        if let (true, Update::Insert { id, .. }) = (self.authors.is_enabled(), update) {
+            // text of other replicas is blamed on them
+            self.authors
+                .record_as(appended..self.addition_buffer.len(), id.replica);
+        }
+        integration
+    }
+
+    /// Returns the updates which bring a replica starting from the same text to the current
+    /// state: one insert per item, then the removals of the removed ones.
+    pub(crate) fn crdt_snapshot(&self) -> Vec<Update> {
+        let Some(crdt) = &self.crdt else {
+            return Vec::new();
+        };
+        let inserts = crdt
+            .items
+            .iter()
+            .filter(|item| item.id.replica != 0)
+            .map(|item| Update::Insert {
+                id: item.id,
+                origin_left: item.origin_left,
+                origin_right: item.origin_right,
+                text: self.piece_text(&item.piece).to_string(),
+            });
+        let deletes = crdt
+            .items
+            .iter()
+            .filter(|item| item.deleted)
+            .map(|item| Update::Delete {
+                id: item.id,
+                len: item.len(),
+            });
+        inserts.chain(deletes).collect()
+    }
+
+    /// Returns how many received updates wait for updates they depend on.
+    #[must_use]
+    pub fn waiting_updates(&self) -> usize {
+        self.crdt.as_ref().map_or(0, |crdt| crdt.waiting.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Bias;
+    use proptest::prelude::*;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    fn replica(id: u64, txt: &str) -> PieceTable<'_> {
+        let mut table = PieceTable::from_text(txt);
+        table.enable_crdt(NonZeroU64::new(id).unwrap());
+        table
+    }
+
+    fn send(from: &mut PieceTable, to: &mut [&mut PieceTable]) {
+        for update in from.take_updates() {
+            for table in to.iter_mut() {
+                table.apply_update(update.clone());
+            }
+        }
+    }
+
+    #[test]
+    fn should_send_local_edits_as_updates() {
+        init_logger();
+        // given
+        let mut table = replica(1, "hello");
+
+        // when
+        table.insert_str(" world", 5);
+        table.remove(0..1);
+        let updates = table.take_updates();
+
+        // then
+        assert_eq!(
+            updates,
+            vec![
+                Update::Insert {
+                    id: ItemId {
+                        replica: 1,
+                        counter: 0
+                    },
+                    origin_left: Some(ItemId {
+                        replica: 0,
+                        counter: 4
+                    }),
+                    origin_right: None,
+                    text: " world".to_string(),
+                },
+                Update::Delete {
+                    id: ItemId {
+                        replica: 0,
+                        counter: 0
+                    },
+                    len: 1,
+                },
+            ]
+        );
+        assert_eq!(table.take_updates(), vec![]);
+    }
+
+    #[test]
+    fn should_converge_with_inserts_at_same_place() {
+        init_logger();
+        // given
+        let mut a = replica(1, "ac");
+        let mut b = replica(2, "ac");
+        a.insert_str("x", 1);
+        b.insert_str("y", 1);
+
+        // when
+        send(&mut a, &mut [&mut b]);
+        send(&mut b, &mut [&mut a]);
+
+        // then
+        assert_eq!(a.project(), "axyc");
+        assert_eq!(b.project(), "axyc");
+    }
+
+    #[test]
+    fn should_keep_insert_into_removed_text() {
+        init_logger();
+        // given
+        let mut a = replica(1, "one two three");
+        let mut b = replica(2, "one two three");
+        a.remove(3..7);
+        b.insert_str("and ", 4);
+
+        // when
+        send(&mut a, &mut [&mut b]);
+        send(&mut b, &mut [&mut a]);
+
+        // then
+        assert_eq!(a.project(), "oneand  three");
+        assert_eq!(b.project(), "oneand  three");
+    }
+
+    #[test]
+    fn should_wait_for_updates_it_depends_on() {
+        init_logger();
+        // given
+        let mut a = replica(1, "");
+        let mut b = replica(2, "");
+        a.insert_str("abc", 0);
+        a.insert_str("d", 3);
+        a.remove(1..2);
+        let updates = a.take_updates();
+
+        // when
+        b.apply_update(updates[2].clone());
+        b.apply_update(updates[1].clone());
+        let waiting = b.waiting_updates();
+        b.apply_update(updates[0].clone());
+
+        // then
+        assert_eq!(waiting, 2);
+        assert_eq!(b.waiting_updates(), 0);
+        assert_eq!(b.project(), "acd");
+    }
+
+    #[test]
+    fn should_ignore_repeated_updates() {
+        init_logger();
+        // given
+        let mut a = replica(1, "text");
+        let mut b = replica(2, "text");
+        a.insert_str("s", 4);
+        a.remove(0..1);
+        let updates = a.take_updates();
+
+        // when
+        for update in updates.iter().chain(&updates) {
+            b.apply_update(update.clone());
+        }
+
+        // then
+        assert_eq!(b.project(), "exts");
+    }
+
+    #[test]
+    fn should_send_undo_and_redo() {
+        init_logger();
+        // given
+        let mut a = replica(1, "text");
+        let mut b = replica(2, "text");
+        a.insert_str("more ", 0);
+        a.remove(5..6);
+
+        // when
+        a.undo();
+        send(&mut a, &mut [&mut b]);
+        let undone = b.project();
+        a.redo();
+        send(&mut a, &mut [&mut b]);
+
+        // then
+        assert_eq!(undone, "more text");
+        assert_eq!(b.project(), "more ext");
+    }
+
+    #[test]
+    fn should_move_anchors_with_remote_changes() {
+        init_logger();
+        // given
+        let mut a = replica(1, "hello world");
+        let mut b = replica(2, "hello world");
+        let cursor = b.anchor(6, Bias::Left).unwrap();
+        a.insert_str("big ", 6);
+        a.remove(0..6);
+
+        // when
+        send(&mut a, &mut [&mut b]);
+
+        // then
+        assert_eq!(b.project(), "big world");
+        assert_eq!(b.anchor_position(cursor), Some(0));
+    }
+
+    #[test]
+    fn should_undo_local_edit_after_remote_change() {
+        init_logger();
+        // given
+        let mut a = replica(1, "abc");
+        let mut b = replica(2, "abc");
+        b.insert_str("X", 0);
+        a.insert_str("Y", 3);
+
+        // when
+        send(&mut a, &mut [&mut b]);
+        b.undo();
+
+        // then
+        assert_eq!(b.project(), "abcY");
+        b.redo();
+        assert_eq!(b.project(), "XabcY");
+    }
+
+    #[test]
+    fn should_undo_local_edits_made_between_remote_changes() {
+        init_logger();
+        // given
+        let mut a = replica(1, "abc");
+        let mut b = replica(2, "abc");
+        b.insert_str("X", 0);
+        a.insert_str("Y", 3);
+        send(&mut a, &mut [&mut b]);
+        b.insert_str("Z", 2);
+        a.remove(0..1);
+        send(&mut a, &mut [&mut b]);
+
+        // when
+        b.undo();
+        let once = b.project();
+        b.undo();
+
+        // then
+        assert_eq!(once, "XbcY");
+        assert_eq!(b.project(), "bcY");
+        b.redo();
+        b.redo();
+        assert_eq!(b.project(), "XZbcY");
+    }
+
+    #[test]
+    fn should_not_bring_back_text_removed_by_remote_change_on_undo() {
+        init_logger();
+        // given
+        let mut a = replica(1, "some text");
+        let mut b = replica(2, "some text");
+        b.remove(0..5);
+        a.remove(5..9);
+
+        // when
+        send(&mut a, &mut [&mut b]);
+        b.undo();
+
+        // then
+        assert_eq!(b.project(), "some ");
+    }
+
+    #[test]
+    fn should_drop_undone_edit_removed_by_remote_change() {
+        init_logger();
+        // given
+        let mut a = replica(1, "text");
+        let mut b = replica(2, "text");
+        b.insert_str("new ", 0);
+        send(&mut b, &mut [&mut a]);
+        a.remove(0..4);
+
+        // when
+        send(&mut a, &mut [&mut b]);
+        b.undo();
+
+        // then
+        assert_eq!(b.project(), "text");
+        assert!(b.history.is_empty());
+    }
+
+    #[test]
+    fn should_drop_removal_splitting_char() {
+        init_logger();
+        // given
+        let mut table = replica(1, "\u{17c}x");
+
+        // when
+        table.apply_update(Update::Delete {
+            id: ItemId {
+                replica: 0,
+                counter: 1,
+            },
+            len: 1,
+        });
+
+        // then
+        assert_eq!(table.project(), "\u{17c}x");
+        assert_eq!(table.waiting_updates(), 0);
+    }
+
+    #[test]
+    fn should_drop_insert_splitting_char() {
+        init_logger();
+        // given
+        let mut table = replica(1, "\u{17c}x");
+
+        // when
+        table.apply_update(Update::Insert {
+            id: ItemId {
+                replica: 2,
+                counter: 0,
+            },
+            origin_left: Some(ItemId {
+                replica: 0,
+                counter: 0,
+            }),
+            origin_right: None,
+            text: "y".to_string(),
+        });
+
+        // then
+        assert_eq!(table.project(), "\u{17c}x");
+    }
+
+    #[test]
+    fn should_drop_update_with_overflowing_ids() {
+        init_logger();
+        // given
+        let mut table = replica(1, "text");
+
+        // when
+        table.apply_update(Update::Delete {
+            id: ItemId {
+                replica: 0,
+                counter: u64::MAX,
+            },
+            len: 2,
+        });
+        table.apply_update(Update::Insert {
+            id: ItemId {
+                replica: 2,
+                counter: u64::MAX,
+            },
+            origin_left: None,
+            origin_right: None,
+            text: "ab".to_string(),
+        });
+
+        // then
+        assert_eq!(table.project(), "text");
+        assert_eq!(table.waiting_updates(), 0);
+    }
+
+    /// Edit made by a replica: byte position and length of the removal as fractions of the
+    /// text, the inserted text, and whether it undoes the last edit instead.
+    type ReplicaEdit = (usize, usize, String, bool);
+
+    fn edits() -> impl Strategy<Value = Vec<ReplicaEdit>> {
+        prop::collection::vec(
+            (
+                any::<usize>(),
+                0..4usize,
+                "[ab\u{e9}]{0,3}",
+                prop::bool::weighted(0.1),
+            ),
+            0..6,
+        )
+    }
+
+    fn edit(table: &mut PieceTable, edits: &[ReplicaEdit]) {
+        for (at, len, txt, undo) in edits {
+            if *undo {
+                table.undo();
+                continue;
+            }
+            let txt_now = table.project();
+            let boundaries: Vec<_> = txt_now
+                .char_indices()
+                .map(|(idx, _)| idx)
+                .chain([txt_now.len()])
+                .collect();
+            let start = at % boundaries.len();
+            let end = (start + len).min(boundaries.len() - 1);
+            table.remove(boundaries[start]..boundaries[end]);
+            table.insert_str(txt, boundaries[start]);
+        }
+    }
+
+    /// Shuffles `updates` with a xorshift generator seeded with `seed`.
+    fn shuffle(updates: &mut [Update], mut seed: u64) {
+        seed |= 1;
+        for idx in (1..updates.len()).rev() {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            updates.swap(
+                idx,
+                usize::try_from(seed % (u64::try_from(idx).unwrap() + 1)).unwrap(),
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn replicas_converge_whatever_the_order_of_updates(
+            base in "[ab\u{e9}\n]{0,8}",
+            rounds in prop::collection::vec(prop::collection::vec(edits(), 3), 1..3),
+            seed in any::<u64>(),
+        ) {
+            // given
+            let mut replicas: Vec<_> = (1..=3).map(|id| replica(id, &base)).collect();
+
+            // when
+            for (round, replica_edits) in rounds.iter().enumerate() {
+                let mut sent = Vec::new();
+                for (table, edits) in replicas.iter_mut().zip(replica_edits) {
+                    edit(table, edits);
+                    sent.push(table.take_updates());
+                }
+                for (idx, table) in replicas.iter_mut().enumerate() {
+                    let mut received: Vec<_> = sent
+                        .iter()
+                        .enumerate()
+                        .filter(|(from, _)| *from != idx)
+                        .flat_map(|(_, updates)| updates.iter().cloned())
+                        .collect();
+                    received.extend(received.clone().into_iter().take(2));
+                    shuffle(&mut received, seed.wrapping_add(u64::try_from(round * 3 + idx).unwrap()));
+                    for update in received {
+                        table.apply_update(update);
+                    }
+                }
+            }
+
+            // then
+            for table in &replicas {
+                prop_assert_eq!(table.waiting_updates(), 0);
+                prop_assert_eq!(table.project(), replicas[0].project());
+            }
+        }
+
+        #[test]
+        fn snapshot_brings_replica_up_to_date(
+            base in "[ab\u{e9}\n]{0,8}",
+            rounds in prop::collection::vec((edits(), edits()), 1..3),
+        ) {
+            // given
+            let mut a = replica(1, &base);
+            let mut b = replica(2, &base);
+            let mut c = replica(3, &base);
+            for (a_edits, c_edits) in &rounds {
+                edit(&mut a, a_edits);
+                edit(&mut c, c_edits);
+                send(&mut c, &mut [&mut a]);
+            }
+            edit(&mut b, &rounds[0].0);
+
+            // when
+            for update in a.crdt_snapshot() {
+                b.apply_update(update);
+            }
+            send(&mut b, &mut [&mut a]);
+
+            // then
+            prop_assert_eq!(b.waiting_updates(), 0);
+            prop_assert_eq!(b.project(), a.project());
+        }
+
+        #[test]
+        fn history_survives_remote_updates(
+            base in "[ab\u{e9}\n]{0,8}",
+            local in edits(),
+            remote in edits(),
+            later_local in edits(),
+            later_remote in edits(),
+        ) {
+            // given
+            let mut a = replica(1, &base);
+            let mut b = replica(2, &base);
+            edit(&mut a, &local);
+            edit(&mut b, &remote);
+            send(&mut b, &mut [&mut a]);
+            edit(&mut a, &later_local);
+            edit(&mut b, &later_remote);
+            send(&mut b, &mut [&mut a]);
+            let txt = a.project();
+
+            // when
+            a.settle_history();
+            let undos = a.history.len();
+            for _ in 0..undos {
+                a.undo();
+            }
+            for _ in 0..undos {
+                a.redo();
+            }
+
+            // then
+            prop_assert_eq!(a.project(), txt);
+            send(&mut a, &mut [&mut b]);
+            prop_assert_eq!(a.project(), b.project());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn should_serialize_updates() {
+        init_logger();
+        // given
+        let mut table = replica(3, "text");
+        table.insert_str("con", 0);
+        let update = table.take_updates().remove(0);
+
+        // when
+        let json = serde_json::to_string(&update).unwrap();
+
+        // then
+        assert_eq!(serde_json::from_str::<Update>(&json).unwrap(), update);
+    }
+}
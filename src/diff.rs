@@ -0,0 +1,679 @@
+use crate::{Piece, PieceTable, Source};
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
+
+/// A region where two texts differ: `old` in the first text is replaced by `new` in the second.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Difference {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+impl Difference {
+    fn new(old: Range<usize>, new: Range<usize>) -> Self {
+        Self { old, new }
+    }
+}
+
+/// Piece and its position in the text, `shared` when the other table is known to have the same
+/// text behind a piece with the same source and range.
+struct Token {
+    piece: Piece,
+    span: Range<usize>,
+    shared: bool,
+}
+
+impl Token {
+    fn new(piece: Piece, start: usize, shared: bool) -> Self {
+        let span = start..start + piece.len();
+        Self {
+            piece,
+            span,
+            shared,
+        }
+    }
+}
+
+/// Which parts of the buffers hold the same text in both tables.
+struct Sharing {
+    originals: bool,
+    /// Length of the common start of both addition buffers.
+    additions: usize,
+    /// Which shared buffers are the same at the same index in both tables.
+    buffers: Vec<bool>,
+}
+
+impl Sharing {
+    fn is_shared(&self, piece: &Piece) -> bool {
+        match piece.source {
+            Source::Original => self.originals,
+            Source::Add => piece.range.end <= self.additions,
+            Source::Shared(idx) => self.buffers.get(idx) == Some(&true),
+        }
+    }
+}
+
+/// Offsets at which pieces of each buffer get split.
+type Cuts = HashMap<Source, BTreeSet<usize>>;
+
+impl PieceTable<'_> {
+    /// Computes the minimal list of character level differences turning this text into the
+    /// text of `other`. Ranges are byte indices, `old` ones into this table and `new` ones into
+    /// `other`.
+    ///
+    /// Pieces both tables got from the same buffers are matched first, so only the text between
+    /// them has to be compared. To diff against a snapshot of plain text, wrap it with
+    /// [`PieceTable::from_text`].
+    #[must_use]
+    pub fn diff(&self, other: &PieceTable) -> Vec<Difference> {
+        self.unshared_regions(other)
+            .into_iter()
+            .flat_map(|region| {
+                let old: Vec<_> = self.char_indices(region.old.clone()).collect();
+                let new: Vec<_> = other.char_indices(region.new.clone()).collect();
+                myers(old.len(), new.len(), |a, b| old[a].1 == new[b].1)
+                    .into_iter()
+                    .map(move |(a, b)| {
+                        Difference::new(
+                            token_span(&old, a, region.old.end),
+                            token_span(&new, b, region.new.end),
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    /// Like [`diff`](Self::diff) but compares whole lines, so every range starts at the start of
+    /// a line and ends after a newline or at the end of the text.
+    #[must_use]
+    pub fn diff_lines(&self, other: &PieceTable) -> Vec<Difference> {
+        self.line_regions(other)
+            .into_iter()
+            .flat_map(|region| {
+                let old_txt = self.text_in(region.old.clone());
+                let new_txt = other.text_in(region.new.clone());
+                let old = lines(&old_txt, region.old.start);
+                let new = lines(&new_txt, region.new.start);
+                myers(old.len(), new.len(), |a, b| old[a].1 == new[b].1)
+                    .into_iter()
+                    .map(|(a, b)| {
+                        Difference::new(
+                            token_span(&old, a, region.old.end),
+                            token_span(&new, b, region.new.end),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Regions outside of which both texts are the same, found by matching shared pieces.
+    fn unshared_regions(&self, other: &PieceTable) -> Vec<Difference> {
+        let sharing = Sharing {
+            originals: same_text(self.original_buffer(), other.original_buffer()),
+            additions: common_prefix(&self.addition_buffer, &other.addition_buffer),
+            buffers: (self.buffers.iter().zip(&other.buffers))
+                .map(|(a, b)| a.is_same(b))
+                .collect(),
+        };
+        // pieces of both tables are cut at the same places, so a piece split in only one of
+        // them still matches
+        let mut cuts = Cuts::new();
+        for piece in self.pieces.iter().chain(&other.pieces) {
+            if sharing.is_shared(piece) {
+                cuts.entry(piece.source.clone())
+                    .or_default()
+                    .extend([piece.range.start, piece.range.end]);
+            }
+        }
+        let tokens = self.tokens(&sharing, &cuts);
+        let other_tokens = other.tokens(&sharing, &cuts);
+        myers(tokens.len(), other_tokens.len(), |a, b| {
+            let (a, b) = (&tokens[a], &other_tokens[b]);
+            a.shared && b.shared && a.piece == b.piece
+        })
+        .into_iter()
+        .map(|(a, b)| {
+            Difference::new(
+                covered(&tokens, a, self.len()),
+                covered(&other_tokens, b, other.len()),
+            )
+        })
+        .collect()
+    }
+
+    /// Grows the unshared regions to whole lines, merging the ones which end up on the same line.
+    fn line_regions(&self, other: &PieceTable) -> Vec<Difference> {
+        let mut regions: Vec<Difference> = Vec::new();
+        for region in self.unshared_regions(other) {
+            let prev_end = regions.last().map_or(0, |prev| prev.old.end);
+            // the text between two regions is the same in both tables, so is the distance to the
+            // closest line break
+            let back = self
+                .chunks_in(prev_end..region.old.start)
+                .rev()
+                .find_map(|(start, txt)| txt.rfind('\n').map(|idx| start + idx + 1))
+                .map(|line_start| region.old.start - line_start);
+            let mut region = match (back, regions.pop()) {
+                (Some(back), prev) => {
+                    regions.extend(prev);
+                    Difference::new(
+                        region.old.start - back..region.old.end,
+                        region.new.start - back..region.new.end,
+                    )
+                }
+                (None, Some(prev)) => Difference::new(
+                    prev.old.start..region.old.end,
+                    prev.new.start..region.new.end,
+                ),
+                (None, None) => Difference::new(0..region.old.end, 0..region.new.end),
+            };
+            if !self.starts_line(region.old.end) || !other.starts_line(region.new.end) {
+                let forward = self
+                    .chunks_in(region.old.end..self.len())
+                    .find_map(|(start, txt)| txt.find('\n').map(|idx| start + idx + 1))
+                    .unwrap_or(self.len())
+                    - region.old.end;
+                region.old.end += forward;
+                region.new.end += forward;
+            }
+            match regions.last_mut() {
+                Some(prev) if prev.old.end >= region.old.start => {
+                    prev.old.end = region.old.end;
+                    prev.new.end = region.new.end;
+                }
+                _ => regions.push(region),
+            }
+        }
+        regions
+    }
+
+    fn starts_line(&self, idx: usize) -> bool {
+        idx == 0
+            || self
+                .chunks_in(idx - 1..idx)
+                .next()
+                .is_some_and(|(_, txt)| txt.ends_with('\n'))
+    }
+
+    fn tokens(&self, sharing: &Sharing, cuts: &Cuts) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let mut start = 0;
+        for piece in self.pieces.iter().filter(|piece| piece.len() > 0) {
+            let shared = sharing.is_shared(piece);
+            let piece_start = start;
+            let mut rest = piece.clone();
+            if shared {
+                for &cut in cuts[&piece.source].range(piece.range.start + 1..piece.range.end) {
+                    let offset = cut - rest.range.start;
+                    let (first, second) = rest.split_at(offset);
+                    tokens.push(Token::new(first, start, shared));
+                    start = piece_start + cut - piece.range.start;
+                    rest = second;
+                }
+            }
+            tokens.push(Token::new(rest, start, shared));
+            start = piece_start + piece.len();
+        }
+        tokens
+    }
+}
+
+fn same_text(a: &str, b: &str) -> bool {
+    (a.as_ptr() == b.as_ptr() && a.len() == b.len()) || a == b
+}
+
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.bytes().zip(b.bytes()).take_while(|(a, b)| a == b).count()
+}
+
+/// Byte range covered by the `range` of tokens, which may be empty.
+fn covered(tokens: &[Token], range: Range<usize>, len: usize) -> Range<usize> {
+    let start = tokens
+        .get(range.start)
+        .map_or(len, |token| token.span.start);
+    let end = tokens.get(range.end).map_or(len, |token| token.span.start);
+    start..end
+}
+
+/// Byte range covered by the `range` of tokens starting at the given offsets.
+pub(crate) fn token_span<T>(
+    tokens: &[(usize, T)],
+    range: Range<usize>,
+    end: usize,
+) -> Range<usize> {
+    let start = tokens.get(range.start).map_or(end, |(start, _)| *start);
+    let end = tokens.get(range.end).map_or(end, |(start, _)| *start);
+    start..end
+}
+
+fn lines(txt: &str, offset: usize) -> Vec<(usize, &str)> {
+    let mut start = offset;
+    txt.split_inclusive('\n')
+        .map(|line| {
+            let token = (start, line);
+            start += line.len();
+            token
+        })
+        .collect()
+}
+
+/// Myers' O(ND) difference algorithm over two sequences of `n` and `m` elements, in its linear
+/// space variant. Returns the index ranges that differ, with adjacent ones merged.
+pub(crate) fn myers(
+    n: usize,
+    m: usize,
+    eq: impl Fn(usize, usize) -> bool,
+) -> Vec<(Range<usize>, Range<usize>)> {
+    let mut myers = Myers {
+        eq,
+        forward: Vec::new(),
+        backward: Vec::new(),
+        edits: Vec::new(),
+    };
+    myers.diff(0..n, 0..m);
+    myers.edits
+}
+
+struct Myers<F> {
+    eq: F,
+    /// Furthest reaching x of the forward paths on each diagonal, reused between calls.
+    forward: Vec<isize>,
+    /// Furthest reaching y of the backward paths on each diagonal, reused between calls.
+    backward: Vec<isize>,
+    edits: Vec<(Range<usize>, Range<usize>)>,
+}
+
+impl<F: Fn(usize, usize) -> bool> Myers<F> {
+    /// Diffs `a` against `b` by splitting them at the middle snake of their shortest edit script
+    /// until what's left is only inserted or only deleted.
+    fn diff(&mut self, mut a: Range<usize>, mut b: Range<usize>) {
+        while !a.is_empty() && !b.is_empty() && (self.eq)(a.start, b.start) {
+            a.start += 1;
+            b.start += 1;
+        }
+        while !a.is_empty() && !b.is_empty() && (self.eq)(a.end - 1, b.end - 1) {
+            a.end -= 1;
+            b.end -= 1;
+        }
+        if a.is_empty() && b.is_empty() {
+            return;
+        }
+        if a.is_empty() || b.is_empty() {
+            match self.edits.last_mut() {
+                Some(last) if last.0.end == a.start && last.1.end == b.start => {
+                    last.0.end = a.end;
+                    last.1.end = b.end;
+                }
+                _ => self.edits.push((a, b)),
+            }
+            return;
+        }
+        let (start, end) = self.middle_snake(&a, &b);
+        self.diff(a.start..start.0, b.start..start.1);
+        self.diff(start.0..end.0, start.1..end.1);
+        self.diff(end.0..a.end, end.1..b.end);
+    }
+
+    /// Returns where the middle snake of the shortest edit script of `old` and `new`, both not
+    /// empty, starts and ends, together with the edit next to it.
+    fn middle_snake(
+        &mut self,
+        old: &Range<usize>,
+        new: &Range<usize>,
+    ) -> ((usize, usize), (usize, usize)) {
+        let (left, top) = (signed(old.start), signed(new.start));
+        let (right, bottom) = (signed(old.end), signed(new.end));
+        let delta = (right - left) - (bottom - top);
+        let max = (right - left + bottom - top + 1) / 2;
+        // diagonals go from -max to max, the path of round 0 starts from diagonal 1
+        let index = |k: isize| unsigned(k + max);
+        for v in [&mut self.forward, &mut self.backward] {
+            v.clear();
+            v.resize(unsigned(2 * max + 2), 0);
+        }
+        self.forward[index(1)] = left;
+        self.backward[index(1)] = bottom;
+
+        for round in 0..=max {
+            for k in (-round..=round).rev().step_by(2) {
+                let forward = &self.forward;
+                let (prev_x, mut x) = if k == -round
+                    || (k != round && forward[index(k - 1)] < forward[index(k + 1)])
+                {
+                    (forward[index(k + 1)], forward[index(k + 1)])
+                } else {
+                    (forward[index(k - 1)], forward[index(k - 1)] + 1)
+                };
+                let mut y = top + (x - left) - k;
+                let prev_y = if round == 0 || x != prev_x { y } else { y - 1 };
+                while x < right && y < bottom && (self.eq)(unsigned(x), unsigned(y)) {
+                    x += 1;
+                    y += 1;
+                }
+                self.forward[index(k)] = x;
+                let c = k - delta;
+                if delta % 2 != 0 && -round < c && c < round && y >= self.backward[index(c)] {
+                    return (
+                        (unsigned(prev_x), unsigned(prev_y)),
+                        (unsigned(x), unsigned(y)),
+                    );
+                }
+            }
+            for c in (-round..=round).rev().step_by(2) {
+                let backward = &self.backward;
+                let (prev_y, mut y) = if c == -round
+                    || (c != round && backward[index(c - 1)] > backward[index(c + 1)])
+                {
+                    (backward[index(c + 1)], backward[index(c + 1)])
+                } else {
+                    (backward[index(c - 1)], backward[index(c - 1)] - 1)
+                };
+                let k = c + delta;
+                let mut x = left + (y - top) + k;
+                let prev_x = if round == 0 || y != prev_y { x } else { x + 1 };
+                while x > left && y > top && (self.eq)(unsigned(x - 1), unsigned(y - 1)) {
+                    x -= 1;
+                    y -= 1;
+                }
+                self.backward[index(c)] = y;
+                if delta % 2 == 0 && -round <= k && k <= round && x <= self.forward[index(k)] {
+                    return (
+                        (unsigned(x), unsigned(y)),
+                        (unsigned(prev_x), unsigned(prev_y)),
+                    );
+                }
+            }
+        }
+        unreachable!("the paths meet after at most max rounds")
+    }
+}
+
+fn signed(idx: usize) -> isize {
+    isize::try_from(idx).expect("lengths of sequences fit in isize")
+}
+
+fn unsigned(idx: isize) -> usize {
+    usize::try_from(idx).expect("indexes within the sequences are not negative")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    /// Applies the differences to `old`, taking the replacement text from `new`.
+    fn apply(old: &str, new: &str, differences: &[Difference]) -> String {
+        let mut txt = old.to_string();
+        for difference in differences.iter().rev() {
+            txt.replace_range(difference.old.clone(), &new[difference.new.clone()]);
+        }
+        txt
+    }
+
+    mod myers {
+        use super::*;
+
+        use proptest::prelude::*;
+
+        fn diff(a: &str, b: &str) -> Vec<(Range<usize>, Range<usize>)> {
+            let (a, b): (Vec<_>, Vec<_>) = (a.chars().collect(), b.chars().collect());
+            myers(a.len(), b.len(), |i, j| a[i] == b[j])
+        }
+
+        /// Length of the longest common subsequence, by dynamic programming.
+        fn lcs(a: &[char], b: &[char]) -> usize {
+            let mut row = vec![0; b.len() + 1];
+            for x in a {
+                let mut diagonal = 0;
+                for (j, y) in b.iter().enumerate() {
+                    let above = row[j + 1];
+                    row[j + 1] = if x == y {
+                        diagonal + 1
+                    } else {
+                        above.max(row[j])
+                    };
+                    diagonal = above;
+                }
+            }
+            row[b.len()]
+        }
+
+        proptest! {
+            #[test]
+            fn finds_shortest_edit_script(a in "[abc]{0,24}", b in "[abc]{0,24}") {
+                // given
+                let chars_a: Vec<_> = a.chars().collect();
+                let chars_b: Vec<_> = b.chars().collect();
+
+                // when
+                let edits = diff(&a, &b);
+
+                // then
+                let mut patched = chars_a.clone();
+                for (old, new) in edits.iter().rev() {
+                    patched.splice(old.clone(), chars_b[new.clone()].iter().copied());
+                }
+                prop_assert_eq!(patched, chars_b.clone());
+                let edited: usize = edits.iter().map(|(old, new)| old.len() + new.len()).sum();
+                let shortest = chars_a.len() + chars_b.len() - 2 * lcs(&chars_a, &chars_b);
+                prop_assert_eq!(edited, shortest);
+                prop_assert!(edits.windows(2).all(|pair| pair[0].0.end < pair[1].0.start
+                    || pair[0].1.end < pair[1].1.start));
+            }
+        }
+
+        #[test]
+        fn should_find_nothing_for_same_sequences() {
+            init_logger();
+            // given
+            let txt = "abcabba";
+
+            // when
+            let edits = diff(txt, txt);
+
+            // then
+            assert_eq!(edits, vec![]);
+        }
+
+        #[test]
+        fn should_find_minimal_edits() {
+            init_logger();
+            // given
+            let (a, b) = ("abcabba", "cbabac");
+
+            // when
+            let edits = diff(a, b);
+
+            // then
+            let changed: usize = edits.iter().map(|(a, b)| a.len() + b.len()).sum();
+            assert_eq!(changed, 5);
+        }
+
+        #[test]
+        fn should_merge_adjacent_edits() {
+            init_logger();
+            // given
+            let (a, b) = ("a-xy-b", "a-zw-b");
+
+            // when
+            let edits = diff(a, b);
+
+            // then
+            assert_eq!(edits, vec![(2..4, 2..4)]);
+        }
+
+        #[test]
+        fn should_handle_one_empty_sequence() {
+            init_logger();
+            // given
+            let (a, b) = ("", "abc");
+
+            // when
+            let edits = diff(a, b);
+
+            // then
+            assert_eq!(edits, vec![(0..0, 0..3)]);
+        }
+    }
+
+    mod diff {
+        use super::*;
+
+        #[test]
+        fn should_find_nothing_between_same_texts() {
+            init_logger();
+            // given
+            let txt = "some text";
+            let mut table = PieceTable::from_text(txt);
+            table.insert_str("more ", 5);
+            let other = PieceTable::from_text("some more text");
+
+            // when
+            let differences = table.diff(&other);
+
+            // then
+            assert_eq!(differences, vec![]);
+        }
+
+        #[test]
+        fn should_diff_edits_of_same_original() {
+            init_logger();
+            // given
+            let txt = "The quick brown fox jumps over the lazy dog";
+            let mut table = PieceTable::from_text(txt);
+            table.remove(4..10);
+            let mut other = PieceTable::from_text(txt);
+            other.insert_str("very ", 40);
+            other.remove(20..26);
+
+            // when
+            let differences = table.diff(&other);
+
+            // then
+            assert_eq!(
+                differences,
+                vec![
+                    Difference::new(4..4, 4..10),
+                    Difference::new(14..20, 20..20),
+                    Difference::new(34..34, 34..39)
+                ]
+            );
+            assert_eq!(
+                apply(&table.project(), &other.project(), &differences),
+                other.project()
+            );
+        }
+
+        #[test]
+        fn should_diff_unrelated_tables() {
+            init_logger();
+            // given
+            let table = PieceTable::from_text("zażółć gęślą jaźń");
+            let other = PieceTable::from_text("zażółcić gęsią jaźń!");
+
+            // when
+            let differences = table.diff(&other);
+
+            // then
+            assert_eq!(
+                apply(&table.project(), &other.project(), &differences),
+                other.project()
+            );
+            assert_eq!(differences.len(), 3);
+        }
+
+        #[test]
+        fn should_compare_text_of_different_pieces() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("abc");
+            table.insert_str("def", 3);
+            let mut other = PieceTable::from_text("abc");
+            other.insert_str("xdeyf", 3);
+
+            // when
+            let differences = table.diff(&other);
+
+            // then
+            assert_eq!(
+                differences,
+                vec![Difference::new(3..3, 3..4), Difference::new(5..5, 6..7)]
+            );
+        }
+    }
+
+    mod diff_lines {
+        use super::*;
+
+        #[test]
+        fn should_report_whole_changed_lines() {
+            init_logger();
+            // given
+            let txt = "one\ntwo\nthree\nfour\n";
+            let mut table = PieceTable::from_text(txt);
+            table.insert_char('!', 5);
+            table.remove(16..19);
+            let other = PieceTable::from_text(txt);
+
+            // when
+            let differences = table.diff_lines(&other);
+
+            // then
+            assert_eq!(
+                differences,
+                vec![Difference::new(4..9, 4..8), Difference::new(15..17, 14..19)]
+            );
+        }
+
+        #[test]
+        fn should_merge_changes_on_same_line() {
+            init_logger();
+            // given
+            let txt = "one two three\nfour\n";
+            let mut table = PieceTable::from_text(txt);
+            table.insert_char('1', 3);
+            table.insert_char('3', 14);
+            let other = PieceTable::from_text(txt);
+
+            // when
+            let differences = table.diff_lines(&other);
+
+            // then
+            assert_eq!(differences, vec![Difference::new(0..16, 0..14)]);
+        }
+
+        #[test]
+        fn should_report_inserted_lines() {
+            init_logger();
+            // given
+            let txt = "one\nthree";
+            let table = PieceTable::from_text(txt);
+            let mut other = PieceTable::from_text(txt);
+            other.insert_str("two\n", 4);
+
+            // when
+            let differences = table.diff_lines(&other);
+
+            // then
+            assert_eq!(differences, vec![Difference::new(4..4, 4..8)]);
+        }
+
+        #[test]
+        fn should_handle_last_line_without_newline() {
+            init_logger();
+            // given
+            let table = PieceTable::from_text("a\nb\nc");
+            let other = PieceTable::from_text("a\nb\nd");
+
+            // when
+            let differences = table.diff_lines(&other);
+
+            // then
+            assert_eq!(differences, vec![Difference::new(4..5, 4..5)]);
+        }
+    }
+}
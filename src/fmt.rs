@@ -0,0 +1,63 @@
+use crate::PieceTable;
+use std::fmt;
+
+impl fmt::Display for PieceTable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.width().is_some() || f.precision().is_some() {
+            // padding needs the whole text at once
+            return f.pad(&self.project());
+        }
+        for chunk in self.chunks() {
+            f.write_str(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+/// Appends the formatted text at the end of the table.
+impl fmt::Write for PieceTable<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.insert_str(s, self.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Write;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    #[test]
+    fn should_display_text_of_all_pieces() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("some text");
+        table.insert_str("more ", 5);
+
+        // when
+        let displayed = table.to_string();
+        let padded = format!("[{table:>16}]");
+
+        // then
+        assert_eq!(displayed, "some more text");
+        assert_eq!(padded, "[  some more text]");
+    }
+
+    #[test]
+    fn should_append_formatted_text() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("count:");
+
+        // when
+        write!(table, " {}", 42).unwrap();
+        table.write_char('!').unwrap();
+
+        // then
+        assert_eq!(table.project(), "count: 42!");
+    }
+}
@@ -0,0 +1,307 @@
+use crate::merge::clusters;
+use crate::{Component, Conflict, Difference, Operation, PieceTable, Version};
+use std::fmt;
+
+/// Where a branch was forked from.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Fork {
+    parent: u64,
+    version: Version,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ForkError {
+    /// The table wasn't forked from the one it is merged into.
+    NotABranch,
+    /// Versions since the fork were forgotten, see [`PieceTable::forget_versions_before`].
+    VersionsForgotten,
+}
+
+impl fmt::Display for ForkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotABranch => write!(f, "table is not a branch of the one it is merged into"),
+            Self::VersionsForgotten => write!(f, "versions since the fork were forgotten"),
+        }
+    }
+}
+
+impl std::error::Error for ForkError {}
+
+impl<'a> PieceTable<'a> {
+    /// Creates an independent branch with the current text, e.g. for an experimental change,
+    /// which can be merged back with [`PieceTable::merge`].
+    ///
+    /// The branch shares the original text and the shared buffers, and starts with a copy of the
+    /// addition buffer so that the pieces of both tables point at the same text, along with its
+    /// authorship. Forking takes
+    /// time and memory in proportion to all the text inserted so far, removed or not.
+    #[must_use]
+    pub fn fork(&self) -> PieceTable<'a> {
+        let mut branch = PieceTable::from_parts(
+            self.original_buffer.clone(),
+            self.addition_buffer.clone(),
+            self.pieces.clone(),
+            Vec::new(),
+            Vec::new(),
+        );
+        branch.fork = Some(Fork {
+            parent: self.id,
+            version: self.version(),
+        });
+        branch.buffers.clone_from(&self.buffers);
+        branch.authors = self.authors.clone();
+        branch
+    }
+
+    /// Merges the changes made in `branch` since it was forked from this table.
+    ///
+    /// The operations recorded on both sides since the fork are transformed against each other,
+    /// so all the changes of both are kept. Changes of both sides touching the same or adjacent
+    /// text are returned as conflicts, in order, with `ours` being the text after the merge. The
+    /// merge is a single step in the undo history.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the recorded operations don't lead to the text of either table, which is a bug.
+    pub fn merge(&mut self, branch: &PieceTable<'_>) -> Result<Vec<Conflict>, ForkError> {
+        let fork = branch
+            .fork
+            .filter(|fork| fork.parent == self.id)
+            .ok_or(ForkError::NotABranch)?;
+        let ours = self
+            .operation_since(fork.version)
+            .ok_or(ForkError::VersionsForgotten)?;
+        let theirs = branch
+            .operation_since(Version::default())
+            .ok_or(ForkError::VersionsForgotten)?;
+        let (_, their_changes) = ours
+            .transform(&theirs)
+            .expect("both sides start from the same text");
+
+        let conflicts = clusters(&differences(&ours), &differences(&theirs))
+            .into_iter()
+            .filter(|cluster| cluster.changed_by_us && cluster.changed_by_them)
+            .map(|cluster| Conflict {
+                base: cluster.base,
+                ours: transform_index(&their_changes, cluster.ours.start, false)
+                    ..transform_index(&their_changes, cluster.ours.end, true),
+                theirs: cluster.theirs,
+            })
+            .collect();
+        self.apply(&their_changes)
+            .expect("transformed operation applies to the table");
+        Ok(conflicts)
+    }
+}
+
+/// Returns the parts of the text changed by `operation`.
+fn differences(operation: &Operation) -> Vec<Difference> {
+    let mut differences: Vec<Difference> = Vec::new();
+    let (mut old, mut new) = (0, 0);
+    let mut changing = false;
+    for component in operation.components() {
+        let (old_len, new_len) = match component {
+            Component::Retain(len) => {
+                old += len;
+                new += len;
+                changing = false;
+                continue;
+            }
+            Component::Insert(txt) => (0, txt.len()),
+            Component::Delete(len) => (*len, 0),
+        };
+        match differences.last_mut() {
+            Some(last) if changing => {
+                last.old.end += old_len;
+                last.new.end += new_len;
+            }
+            _ => differences.push(Difference {
+                old: old..old + old_len,
+                new: new..new + new_len,
+            }),
+        }
+        old += old_len;
+        new += new_len;
+        changing = true;
+    }
+    differences
+}
+
+/// Returns where byte index `idx` ends up after `operation`. Text inserted at it goes before it
+/// with `after_inserts`.
+fn transform_index(operation: &Operation, idx: usize, after_inserts: bool) -> usize {
+    let (mut old, mut new) = (0, 0);
+    for component in operation.components() {
+        match component {
+            Component::Retain(len) => {
+                if idx < old + len {
+                    return new + idx - old;
+                }
+                old += len;
+                new += len;
+            }
+            Component::Insert(txt) => {
+                if idx == old && !after_inserts {
+                    return new;
+                }
+                new += txt.len();
+            }
+            Component::Delete(len) => {
+                if idx < old + len {
+                    return new;
+                }
+                old += len;
+            }
+        }
+    }
+    new
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    #[test]
+    fn should_merge_changes_of_both_sides() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("fn main() {\n    run();\n}\n");
+        let mut branch = table.fork();
+        branch.insert_str("    init();\n", 12);
+        branch.remove(35..37);
+        table.insert_str("pub ", 0);
+
+        // when
+        let conflicts = table.merge(&branch).unwrap();
+
+        // then
+        assert_eq!(conflicts, vec![]);
+        assert_eq!(
+            table.project(),
+            "pub fn main() {\n    init();\n    run();\n"
+        );
+    }
+
+    #[test]
+    fn should_keep_authorship_in_branch() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("text");
+        table.set_author(Some(3));
+        table.insert_str("some ", 0);
+
+        // when
+        let branch = table.fork();
+
+        // then
+        assert_eq!(branch.author(), Some(3));
+        assert_eq!(branch.blame(0..9), table.blame(0..9));
+    }
+
+    #[test]
+    fn should_keep_both_sides_of_conflicting_changes() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("let x = 1;");
+        let mut branch = table.fork();
+        branch.remove(8..9);
+        branch.insert_str("2", 8);
+        table.remove(8..9);
+        table.insert_str("3", 8);
+
+        // when
+        let conflicts = table.merge(&branch).unwrap();
+
+        // then
+        assert_eq!(table.project(), "let x = 32;");
+        assert_eq!(
+            conflicts,
+            vec![Conflict {
+                base: 8..9,
+                ours: 8..10,
+                theirs: 8..9,
+            }]
+        );
+    }
+
+    #[test]
+    fn should_merge_branch_edited_with_undo_and_redo() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("one");
+        table.insert_str(" two", 3);
+        let mut branch = table.fork();
+        branch.insert_str(" three", 7);
+        branch.insert_str(" four", 13);
+        branch.undo();
+        branch.undo();
+        branch.redo();
+        table.undo();
+
+        // when
+        let conflicts = table.merge(&branch).unwrap();
+
+        // then
+        assert_eq!(table.project(), "one three");
+        assert_eq!(conflicts.len(), 1);
+    }
+
+    #[test]
+    fn should_undo_merge_at_once() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("text");
+        let mut branch = table.fork();
+        branch.insert_str("some ", 0);
+        branch.insert_char('!', 9);
+
+        // when
+        table.merge(&branch).unwrap();
+        let merged = table.project();
+        table.undo();
+
+        // then
+        assert_eq!(merged, "some text!");
+        assert_eq!(table.project(), "text");
+    }
+
+    #[test]
+    fn should_refuse_table_which_is_not_a_branch() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("text");
+        let other = PieceTable::from_text("text");
+        let grandchild = table.fork().fork();
+
+        // when
+        let results = (table.merge(&other), table.merge(&grandchild));
+
+        // then
+        assert_eq!(
+            results,
+            (Err(ForkError::NotABranch), Err(ForkError::NotABranch))
+        );
+    }
+
+    #[test]
+    fn should_refuse_branch_forked_at_forgotten_version() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("text");
+        let branch = table.fork();
+        table.insert_str("more ", 0);
+
+        // when
+        table.forget_versions_before(table.version());
+        let result = table.merge(&branch);
+
+        // then
+        assert_eq!(result, Err(ForkError::VersionsForgotten));
+        assert_eq!(table.project(), "more text");
+    }
+}
@@ -0,0 +1,191 @@
+use crate::{Piece, Source};
+use std::ops::Range;
+
+/// Single user-visible modification of the table. It is undone and redone as a whole, even if
+/// it touched the pieces in many places (e.g. replacing all matches of a pattern).
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct Edit {
+    changes: Vec<Change>,
+}
+
+impl Edit {
+    pub(crate) fn push(&mut self, change: Change) {
+        self.changes.push(change);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Applies the changes as redo does, but first checks that they fit the pieces, which may
+    /// not be the case for history loaded from outside.
+    pub(crate) fn checked_apply(&self, pieces: &mut Vec<Piece>) -> bool {
+        self.changes.iter().all(|change| {
+            let fits = pieces.get(change.at..change.at + change.removed.len())
+                == Some(change.removed.as_slice());
+            if fits {
+                change.apply(pieces);
+            }
+            fits
+        })
+    }
+
+    /// Reverts the changes as undo does, but first checks that they fit the pieces.
+    pub(crate) fn checked_revert(&self, pieces: &mut Vec<Piece>) -> bool {
+        self.changes.iter().rev().all(|change| {
+            let fits = pieces.get(change.at..change.at + change.inserted.len())
+                == Some(change.inserted.as_slice());
+            if fits {
+                change.revert(pieces);
+            }
+            fits
+        })
+    }
+
+    pub(crate) fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    pub(crate) fn pieces(&self) -> impl Iterator<Item = &Piece> {
+        self.changes
+            .iter()
+            .flat_map(|change| change.removed.iter().chain(&change.inserted))
+    }
+}
+
+impl FromIterator<Change> for Edit {
+    fn from_iter<I: IntoIterator<Item = Change>>(iter: I) -> Self {
+        Self {
+            changes: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl From<Change> for Edit {
+    fn from(change: Change) -> Self {
+        Self {
+            changes: vec![change],
+        }
+    }
+}
+
+/// Replacement of `removed` pieces starting at index `at` with `inserted` ones.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct Change {
+    at: usize,
+    removed: Vec<Piece>,
+    inserted: Vec<Piece>,
+}
+
+impl Change {
+    pub(crate) fn new(at: usize, removed: Vec<Piece>, inserted: Vec<Piece>) -> Self {
+        Self {
+            at,
+            removed,
+            inserted,
+        }
+    }
+
+    pub(crate) fn at(&self) -> usize {
+        self.at
+    }
+
+    pub(crate) fn removed(&self) -> &[Piece] {
+        &self.removed
+    }
+
+    pub(crate) fn inserted(&self) -> &[Piece] {
+        &self.inserted
+    }
+
+    pub(crate) fn apply(&self, pieces: &mut Vec<Piece>) {
+        let end = self.at + self.removed.len();
+        pieces.splice(self.at..end, self.inserted.iter().cloned());
+    }
+
+    pub(crate) fn revert(&self, pieces: &mut Vec<Piece>) {
+        let end = self.at + self.inserted.len();
+        pieces.splice(self.at..end, self.removed.iter().cloned());
+    }
+
+    /// Returns the change reverting this one.
+    pub(crate) fn inverse(self) -> Change {
+        Change::new(self.at, self.inserted, self.removed)
+    }
+}
+
+/// Text replaced by a change: `removed` bytes at `at` replaced by the `inserted` bytes of the
+/// text of the inserted pieces. Text which only moved to other pieces, e.g. because a piece got
+/// split, is not counted as replaced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TextSplice {
+    pub(crate) at: usize,
+    pub(crate) removed: usize,
+    pub(crate) inserted: Range<usize>,
+}
+
+impl TextSplice {
+    /// Describes replacing `removed` pieces starting at index `at` of `pieces` with `inserted`.
+    pub(crate) fn new(pieces: &[Piece], at: usize, removed: &[Piece], inserted: &[Piece]) -> Self {
+        let start: usize = pieces[..at].iter().map(Piece::len).sum();
+        let removed_len: usize = removed.iter().map(Piece::len).sum();
+        let inserted_len: usize = inserted.iter().map(Piece::len).sum();
+        let prefix = common_len(removed.iter(), inserted.iter(), false);
+        let suffix = common_len(removed.iter().rev(), inserted.iter().rev(), true)
+            .min(removed_len.min(inserted_len) - prefix);
+        Self {
+            at: start + prefix,
+            removed: removed_len - prefix - suffix,
+            inserted: prefix..inserted_len - suffix,
+        }
+    }
+}
+
+/// Returns how many bytes at the start (or the end when `backwards`) of both piece sequences
+/// come from the same place in the same buffer.
+fn common_len<'p>(
+    a: impl Iterator<Item = &'p Piece>,
+    b: impl Iterator<Item = &'p Piece>,
+    backwards: bool,
+) -> usize {
+    let (mut a, mut b) = (spans(a), spans(b));
+    let (mut x, mut y) = (a.next(), b.next());
+    let mut len = 0;
+    while let (Some((x_source, x_range)), Some((y_source, y_range))) = (&mut x, &mut y) {
+        let aligned = if backwards {
+            x_range.end == y_range.end
+        } else {
+            x_range.start == y_range.start
+        };
+        if x_source != y_source || !aligned {
+            break;
+        }
+        let step = x_range.len().min(y_range.len());
+        len += step;
+        if backwards {
+            x_range.end -= step;
+            y_range.end -= step;
+        } else {
+            x_range.start += step;
+            y_range.start += step;
+        }
+        let (x_done, y_done) = (x_range.start == x_range.end, y_range.start == y_range.end);
+        if x_done {
+            x = a.next();
+        }
+        if y_done {
+            y = b.next();
+        }
+    }
+    len
+}
+
+fn spans<'p>(
+    pieces: impl Iterator<Item = &'p Piece>,
+) -> impl Iterator<Item = (&'p Source, Range<usize>)> {
+    pieces
+        .filter(|piece| piece.len() > 0)
+        .map(|piece| (&piece.source, piece.range.clone()))
+}
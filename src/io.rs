@@ -0,0 +1,451 @@
+use crate::buffer::Buffer;
+use crate::{Piece, PieceTable, Source};
+use std::fs;
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::Path;
+
+/// Appends the written bytes at the end of the table.
+///
+/// The bytes have to be valid UTF-8, but a char may be split between writes: the bytes written
+/// of it are kept aside until the next write brings the rest.
+impl Write for PieceTable<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        if let Some(&first) = self.unfinished_char.first() {
+            let width = match first {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                _ => 4,
+            };
+            let missing = (width - self.unfinished_char.len()).min(rest.len());
+            self.unfinished_char.extend_from_slice(&rest[..missing]);
+            rest = &rest[missing..];
+            if self.unfinished_char.len() < width {
+                return Ok(buf.len());
+            }
+            let bytes = mem::take(&mut self.unfinished_char);
+            let c = std::str::from_utf8(&bytes)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.insert_str(c, self.len());
+        }
+        let valid = match std::str::from_utf8(rest) {
+            Ok(txt) => txt,
+            Err(e) if e.error_len().is_none() => {
+                let (valid, unfinished) = rest.split_at(e.valid_up_to());
+                self.unfinished_char = unfinished.to_vec();
+                std::str::from_utf8(valid).expect("valid prefix")
+            }
+            // the invalid bytes fail the next write, once the ones before are taken
+            Err(e) if e.valid_up_to() > 0 || rest.len() < buf.len() => {
+                let valid = std::str::from_utf8(&rest[..e.valid_up_to()]).expect("valid prefix");
+                if !valid.is_empty() {
+                    self.insert_str(valid, self.len());
+                }
+                return Ok(buf.len() - rest.len() + valid.len());
+            }
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        if !valid.is_empty() {
+            self.insert_str(valid, self.len());
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads the text of a [`PieceTable`] straight from its buffers.
+#[derive(Debug, Clone)]
+pub struct Reader<'t, 'a> {
+    table: &'t PieceTable<'a>,
+    pos: usize,
+    /// Index of a piece at or before `pos`, and where its text starts, so that reading on doesn't
+    /// look for the piece from the start again.
+    piece: usize,
+    piece_start: usize,
+}
+
+impl<'a> PieceTable<'a> {
+    #[must_use]
+    pub fn reader(&self) -> Reader<'_, 'a> {
+        Reader {
+            table: self,
+            pos: 0,
+            piece: 0,
+            piece_start: 0,
+        }
+    }
+
+    /// Inserts the content of the file at `path` at `cursor_idx`, as a single edit.
+    ///
+    /// The content becomes a new read-only buffer the inserted piece points into, instead of
+    /// being copied into the addition buffer. It has to be valid UTF-8.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cursor_idx > len`.
+    pub fn insert_file<P: AsRef<Path>>(&mut self, path: P, cursor_idx: usize) -> io::Result<()> {
+        let len = self.len();
+        assert!(
+            cursor_idx <= len,
+            "insertion index (is {cursor_idx}) should be <= len (is {len})"
+        );
+        let txt = fs::read_to_string(path)?;
+        if txt.is_empty() {
+            return Ok(());
+        }
+        let piece = Piece::new(0..txt.len(), Source::Shared(self.buffers.len()));
+        self.buffers.push(Buffer::Shared(txt.into()));
+        self.insert_at(cursor_idx, vec![piece]);
+        Ok(())
+    }
+}
+
+impl<'t> Reader<'t, '_> {
+    /// Returns the bytes from `pos` to the end of the piece containing it, moving the cursor to
+    /// that piece.
+    fn bytes_from(&mut self, pos: usize) -> &'t [u8] {
+        let table = self.table;
+        if pos < self.piece_start {
+            self.piece = 0;
+            self.piece_start = 0;
+        }
+        while let Some(piece) = table.pieces.get(self.piece) {
+            let end = self.piece_start + piece.len();
+            if pos < end {
+                return &table.piece_text(piece).as_bytes()[pos - self.piece_start..];
+            }
+            self.piece += 1;
+            self.piece_start = end;
+        }
+        &[]
+    }
+}
+
+impl Read for Reader<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let count = available.len().min(buf.len());
+        buf[..count].copy_from_slice(&available[..count]);
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+impl BufRead for Reader<'_, '_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        Ok(self.bytes_from(self.pos))
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+impl Seek for Reader<'_, '_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.pos = usize::try_from(offset)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                return Ok(offset);
+            }
+            SeekFrom::End(offset) => (self.table.len(), offset),
+            SeekFrom::Current(offset) => (self.pos, offset),
+        };
+        let new_pos = i64::try_from(base)
+            .ok()
+            .and_then(|base| base.checked_add(offset))
+            .and_then(|pos| usize::try_from(pos).ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "invalid seek to a negative or overflowing position",
+                )
+            })?;
+        self.pos = new_pos;
+        Ok(u64::try_from(new_pos).expect("positions fit in u64"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    fn table_with_pieces<'a>(original: &'a str, added: &str) -> PieceTable<'a> {
+        let mut table = PieceTable::from_text(original);
+        for c in added.chars() {
+            table.insert_char(c, table.len());
+        }
+        table
+    }
+
+    mod write {
+        use super::*;
+
+        /// Reader returning a single byte at a time.
+        struct ByteByByte<'b>(&'b [u8]);
+
+        impl Read for ByteByByte<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let len = buf.len().min(self.0.len()).min(1);
+                buf[..len].copy_from_slice(&self.0[..len]);
+                self.0 = &self.0[len..];
+                Ok(len)
+            }
+        }
+
+        #[test]
+        fn should_append_written_bytes() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("line 1\n");
+
+            // when
+            writeln!(table, "line {}", 2).unwrap();
+
+            // then
+            assert_eq!(table.project(), "line 1\nline 2\n");
+        }
+
+        #[test]
+        fn should_join_char_split_between_writes() {
+            init_logger();
+            // given
+            let mut table = PieceTable::default();
+            let bytes = "a\u{17c}".as_bytes();
+
+            // when
+            let written = table.write(&bytes[..2]).unwrap();
+            let partial = table.project();
+            let rest = table.write(&bytes[2..]).unwrap();
+
+            // then
+            assert_eq!((written, rest), (2, 1));
+            assert_eq!(partial, "a");
+            assert_eq!(table.project(), "a\u{17c}");
+        }
+
+        #[test]
+        fn should_copy_from_reader_splitting_chars() {
+            init_logger();
+            // given
+            let txt = "a\u{17c}\u{20ac}\u{1f600}!";
+            let mut table = PieceTable::default();
+
+            // when
+            let copied = io::copy(&mut ByteByByte(txt.as_bytes()), &mut table).unwrap();
+
+            // then
+            assert_eq!(copied, txt.len() as u64);
+            assert_eq!(table.project(), txt);
+        }
+
+        #[test]
+        fn should_reject_invalid_continuation_of_split_char() {
+            init_logger();
+            // given
+            let mut table = PieceTable::default();
+            table.write_all(&"\u{17c}".as_bytes()[..1]).unwrap();
+
+            // when
+            let result = table.write(b"ab");
+
+            // then
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+            assert!(table.is_empty());
+        }
+
+        #[test]
+        fn should_reject_invalid_utf8() {
+            init_logger();
+            // given
+            let mut table = PieceTable::default();
+
+            // when
+            let result = table.write(&[0xff, b'a']);
+
+            // then
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+            assert!(table.is_empty());
+        }
+    }
+
+    mod reader {
+        use super::*;
+
+        #[test]
+        fn should_read_whole_text() {
+            init_logger();
+            // given
+            let table = table_with_pieces("some ", "text");
+
+            // when
+            let mut txt = String::new();
+            table.reader().read_to_string(&mut txt).unwrap();
+
+            // then
+            assert_eq!(txt, "some text");
+        }
+
+        #[test]
+        fn should_read_lines_across_pieces() {
+            init_logger();
+            // given
+            let table = table_with_pieces("first\nsec", "ond\nthird");
+
+            // when
+            let lines: Vec<_> = table.reader().lines().map(Result::unwrap).collect();
+
+            // then
+            assert_eq!(lines, ["first", "second", "third"]);
+        }
+
+        #[test]
+        fn should_seek_before_reading() {
+            init_logger();
+            // given
+            let table = table_with_pieces("0123", "456789");
+            let mut reader = table.reader();
+
+            // when
+            reader.seek(SeekFrom::End(-4)).unwrap();
+            let mut end = String::new();
+            reader.read_to_string(&mut end).unwrap();
+            reader.seek(SeekFrom::Start(2)).unwrap();
+            let pos = reader.seek(SeekFrom::Current(1)).unwrap();
+            let mut buf = [0; 3];
+            reader.read_exact(&mut buf).unwrap();
+
+            // then
+            assert_eq!(end, "6789");
+            assert_eq!(pos, 3);
+            assert_eq!(&buf, b"345");
+        }
+
+        #[test]
+        fn should_read_again_after_seeking_back() {
+            init_logger();
+            // given
+            let table = table_with_pieces("ab", "cdef");
+            let mut reader = table.reader();
+            let mut skipped = [0; 5];
+            reader.read_exact(&mut skipped).unwrap();
+
+            // when
+            reader.seek(SeekFrom::Start(1)).unwrap();
+            let mut txt = String::new();
+            reader.read_to_string(&mut txt).unwrap();
+
+            // then
+            assert_eq!(&skipped, b"abcde");
+            assert_eq!(txt, "bcdef");
+            assert_eq!(reader.piece, table.pieces.len());
+        }
+
+        #[test]
+        fn should_not_seek_before_start() {
+            init_logger();
+            // given
+            let table = PieceTable::from_text("text");
+            let mut reader = table.reader();
+
+            // when
+            let result = reader.seek(SeekFrom::Current(-1));
+
+            // then
+            assert!(result.is_err());
+        }
+    }
+
+    mod insert_file {
+        use super::*;
+        use tempfile::tempdir;
+
+        #[test]
+        fn should_point_into_buffer_with_file_content() {
+            init_logger();
+            // given
+            let dir = tempdir().unwrap();
+            let path = dir.path().join("inserted.txt");
+            fs::write(&path, "inserted\n").unwrap();
+            let mut table = PieceTable::from_text("first\nlast\n");
+
+            // when
+            table.insert_file(&path, 6).unwrap();
+
+            // then
+            assert_eq!(table.project(), "first\ninserted\nlast\n");
+            assert_eq!(table.addition_buffer, "");
+            assert_eq!(
+                table.pieces,
+                [
+                    Piece::new(0..6, Source::Original),
+                    Piece::new(0..9, Source::Shared(0)),
+                    Piece::new(6..11, Source::Original),
+                ]
+            );
+        }
+
+        #[test]
+        fn should_undo_file_insertion() {
+            init_logger();
+            // given
+            let dir = tempdir().unwrap();
+            let path = dir.path().join("inserted.txt");
+            fs::write(&path, "more ").unwrap();
+            let mut table = PieceTable::from_text("text");
+            table.insert_file(&path, 0).unwrap();
+            table.insert_file(&path, 5).unwrap();
+
+            // when
+            let inserted = table.project();
+            table.undo();
+
+            // then
+            assert_eq!(inserted, "more more text");
+            assert_eq!(table.project(), "more text");
+            assert_eq!(table.buffers.len(), 2);
+        }
+
+        #[test]
+        fn should_fail_for_file_which_is_not_utf8() {
+            init_logger();
+            // given
+            let dir = tempdir().unwrap();
+            let path = dir.path().join("binary");
+            fs::write(&path, [0xff, 0xfe]).unwrap();
+            let mut table = PieceTable::from_text("text");
+
+            // when
+            let result = table.insert_file(&path, 0);
+
+            // then
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+            assert_eq!(table.project(), "text");
+            assert!(table.buffers.is_empty());
+        }
+
+        #[test]
+        fn should_fail_for_missing_file() {
+            init_logger();
+            // given
+            let dir = tempdir().unwrap();
+            let mut table = PieceTable::from_text("text");
+
+            // when
+            let result = table.insert_file(dir.path().join("missing"), 0);
+
+            // then
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+            assert!(!table.is_dirty());
+        }
+    }
+}
@@ -0,0 +1,591 @@
+//! Append-only journal of edits, used to restore unsaved work after a crash.
+//!
+//! The journal starts with a header and a snapshot of the table at the moment the journal was
+//! attached, followed by one record per edit, undo and redo. Changes which aren't edits write
+//! a new snapshot replacing the previous state:
+//!
+//! ```text
+//! magic           4 bytes   b"POCJ"
+//! version         1 byte    3
+//! original hash   8 bytes   FNV-1a 64 of the original text, little endian
+//! records         kind (1 byte), varint payload length, payload, FNV-1a 64 of the payload
+//!                 truncated to 4 bytes, little endian
+//!
+//! snapshot (0)    addition buffer (varint length + text), pieces, undo history, redo history
+//! edit (1)        text appended to the addition buffer (varint length + text), edit
+//! undo (2)        no payload
+//! redo (3)        no payload
+//! buffer (4)      text of the next shared buffer, written before the first record using it
+//! remote (5)      text appended to the addition buffer (varint length + text), list of changes
+//!                 of other replicas, each varint byte index, varint removed length and list of
+//!                 inserted pieces
+//! ```
+//!
+//! Pieces and edits are encoded like in the session format (see [`PieceTable::save_session`]).
+//! A record is written with a single `write_all` call, so after a crash at most the last record
+//! is incomplete; such a record is ignored on recovery.
+
+use crate::buffer::Buffer;
+use crate::crdt::Shift;
+use crate::history::Edit;
+use crate::session::{self, Decoder, SessionError};
+use crate::{Piece, PieceTable};
+use log::warn;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8; 4] = b"POCJ";
+const VERSION: u8 = 3;
+
+const SNAPSHOT: u8 = 0;
+const EDIT: u8 = 1;
+const UNDO: u8 = 2;
+const REDO: u8 = 3;
+const BUFFER: u8 = 4;
+const REMOTE: u8 = 5;
+
+/// Destination of the journal, usually a file opened in append mode.
+pub struct Journal {
+    writer: Box<dyn Write + Send>,
+    error: Option<io::Error>,
+    journaled_addition: usize,
+    journaled_buffers: usize,
+}
+
+impl Journal {
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        Self {
+            writer: Box::new(writer),
+            error: None,
+            journaled_addition: 0,
+            journaled_buffers: 0,
+        }
+    }
+
+    fn write_record(&mut self, kind: u8, payload: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+        let mut record = vec![kind];
+        session::write_varint(&mut record, payload.len());
+        record.extend_from_slice(payload);
+        record.extend_from_slice(&checksum(payload).to_le_bytes());
+        if let Err(e) = self.writer.write_all(&record) {
+            warn!("failed to write to journal, journaling stopped: {e}");
+            self.error = Some(e);
+        }
+    }
+
+    fn write_buffers(&mut self, buffers: &[Buffer]) {
+        for buffer in &buffers[self.journaled_buffers..] {
+            let mut payload = Vec::new();
+            session::write_str(&mut payload, buffer);
+            self.write_record(BUFFER, &payload);
+        }
+        self.journaled_buffers = buffers.len();
+    }
+}
+
+impl fmt::Debug for Journal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Journal")
+            .field("error", &self.error)
+            .field("journaled_addition", &self.journaled_addition)
+            .field("journaled_buffers", &self.journaled_buffers)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> PieceTable<'a> {
+    /// Starts logging all edits, undos and redos to `journal`.
+    ///
+    /// The current state of the table is written first, so the journal is enough to restore the
+    /// table with [`PieceTable::recover`]. Write errors don't interrupt editing, they stop the
+    /// journaling and are returned by [`PieceTable::flush_journal`].
+    pub fn set_journal(&mut self, mut journal: Journal) {
+        let mut header = Vec::new();
+        header.extend_from_slice(MAGIC);
+        header.push(VERSION);
+        header.extend_from_slice(&session::hash(self.original_buffer().as_bytes()).to_le_bytes());
+        if let Err(e) = journal.writer.write_all(&header) {
+            journal.error = Some(e);
+        }
+        journal.journaled_buffers = 0;
+        self.journal = Some(journal);
+        self.settle_history();
+        self.journal_snapshot();
+    }
+
+    pub fn take_journal(&mut self) -> Option<Journal> {
+        self.journal.take()
+    }
+
+    /// Flushes the journal and reports the error which stopped journaling, if any.
+    pub fn flush_journal(&mut self) -> io::Result<()> {
+        let Some(journal) = &mut self.journal else {
+            return Ok(());
+        };
+        if let Some(e) = journal.error.take() {
+            return Err(e);
+        }
+        journal.writer.flush()
+    }
+
+    /// Restores the table from a journal written with [`PieceTable::set_journal`].
+    ///
+    /// An incomplete or damaged last record (e.g. the one being written during a crash) is
+    /// skipped, any other damage is reported as [`SessionError::Corrupted`].
+    pub fn recover<R: Read>(original: &'a str, mut journal: R) -> Result<Self, SessionError> {
+        let mut bytes = Vec::new();
+        journal.read_to_end(&mut bytes)?;
+        let mut decoder = Decoder {
+            reader: bytes.as_slice(),
+        };
+        let mut magic = [0; 4];
+        decoder.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(SessionError::NotASession);
+        }
+        let version = decoder.read_u8()?;
+        if version != VERSION {
+            return Err(SessionError::UnsupportedVersion(version));
+        }
+        let mut original_hash = [0; 8];
+        decoder.read_exact(&mut original_hash)?;
+        if u64::from_le_bytes(original_hash) != session::hash(original.as_bytes()) {
+            return Err(SessionError::OriginalMismatch);
+        }
+
+        let mut table = Self::from_text(original);
+        let mut has_snapshot = false;
+        while !decoder.reader.is_empty() {
+            let Some((kind, payload)) = read_record(&mut decoder)? else {
+                warn!("skipping incomplete last record of the journal");
+                break;
+            };
+            let mut payload = Decoder { reader: payload };
+            match (kind, has_snapshot) {
+                (SNAPSHOT, _) => {
+                    let buffers = std::mem::take(&mut table.buffers);
+                    table = Self::from_parts(
+                        Buffer::Borrowed(original),
+                        payload.read_str()?,
+                        payload.read_pieces()?,
+                        payload.read_edits()?,
+                        payload.read_edits()?,
+                    );
+                    table.buffers = buffers;
+                    has_snapshot = true;
+                }
+                (BUFFER, _) => {
+                    let buffer = payload.read_str()?;
+                    table.buffers.push(Buffer::Shared(buffer.into()));
+                }
+                (EDIT, true) => {
+                    let appended = payload.read_str()?;
+                    let edit = payload.read_edit()?;
+                    table.replay(&appended, edit)?;
+                }
+                (REMOTE, true) => {
+                    let appended = payload.read_str()?;
+                    let mut shifts = Vec::new();
+                    for _ in 0..payload.read_varint()? {
+                        shifts.push((
+                            payload.read_varint()?,
+                            payload.read_varint()?,
+                            payload.read_pieces()?,
+                        ));
+                    }
+                    table.replay_remote(&appended, shifts)?;
+                }
+                (UNDO, true) => table.undo(),
+                (REDO, true) => table.redo(),
+                (kind, _) => {
+                    return Err(SessionError::Corrupted(format!(
+                        "unexpected record of kind {kind}"
+                    )))
+                }
+            }
+        }
+        table.settle_history();
+        table.validate().map_err(SessionError::Corrupted)?;
+        Ok(table)
+    }
+
+    fn replay(&mut self, appended: &str, edit: Edit) -> Result<(), SessionError> {
+        self.addition_buffer.push_str(appended);
+        if !edit.checked_apply(&mut self.pieces) {
+            return Err(SessionError::Corrupted(
+                "journaled edit doesn't match the pieces".into(),
+            ));
+        }
+        self.redo.clear();
+        self.push_history(edit);
+        Ok(())
+    }
+
+    fn replay_remote(&mut self, appended: &str, shifts: Vec<Shift>) -> Result<(), SessionError> {
+        let mut len = self.len();
+        for (at, removed, inserted) in &shifts {
+            if at + removed > len {
+                return Err(SessionError::Corrupted(
+                    "journaled remote change is out of bounds".into(),
+                ));
+            }
+            len = len - removed + inserted.iter().map(Piece::len).sum::<usize>();
+        }
+        self.addition_buffer.push_str(appended);
+        self.apply_remote(shifts);
+        Ok(())
+    }
+
+    pub(crate) fn journal_edit(&mut self, edit: &Edit) {
+        let Some(journal) = &mut self.journal else {
+            return;
+        };
+        journal.write_buffers(&self.buffers);
+        let mut payload = Vec::new();
+        session::write_str(
+            &mut payload,
+            &self.addition_buffer[journal.journaled_addition..],
+        );
+        session::write_edit(&mut payload, edit);
+        journal.write_record(EDIT, &payload);
+        journal.journaled_addition = self.addition_buffer.len();
+    }
+
+    /// Writes the whole state, which the following records start from.
+    fn journal_snapshot(&mut self) {
+        let Some(journal) = &mut self.journal else {
+            return;
+        };
+        journal.write_buffers(&self.buffers);
+        let mut snapshot = Vec::new();
+        session::write_str(&mut snapshot, &self.addition_buffer);
+        session::write_pieces(&mut snapshot, &self.pieces);
+        session::write_edits(&mut snapshot, &self.history);
+        session::write_edits(&mut snapshot, &self.redo);
+        journal.write_record(SNAPSHOT, &snapshot);
+        journal.journaled_addition = self.addition_buffer.len();
+    }
+
+    pub(crate) fn journal_remote(&mut self, shifts: &[Shift]) {
+        let Some(journal) = &mut self.journal else {
+            return;
+        };
+        journal.write_buffers(&self.buffers);
+        let mut payload = Vec::new();
+        session::write_str(
+            &mut payload,
+            &self.addition_buffer[journal.journaled_addition..],
+        );
+        session::write_varint(&mut payload, shifts.len());
+        for (at, removed, inserted) in shifts {
+            session::write_varint(&mut payload, *at);
+            session::write_varint(&mut payload, *removed);
+            session::write_pieces(&mut payload, inserted);
+        }
+        journal.write_record(REMOTE, &payload);
+        journal.journaled_addition = self.addition_buffer.len();
+    }
+
+    pub(crate) fn journal_undo(&mut self) {
+        if let Some(journal) = &mut self.journal {
+            journal.write_record(UNDO, &[]);
+        }
+    }
+
+    pub(crate) fn journal_redo(&mut self) {
+        if let Some(journal) = &mut self.journal {
+            journal.write_record(REDO, &[]);
+        }
+    }
+}
+
+fn checksum(payload: &[u8]) -> u32 {
+    let [a, b, c, d, ..] = session::hash(payload).to_le_bytes();
+    u32::from_le_bytes([a, b, c, d])
+}
+
+/// Returns `None` if the record is the last one and is incomplete or damaged.
+fn read_record<'b>(
+    decoder: &mut Decoder<&'b [u8]>,
+) -> Result<Option<(u8, &'b [u8])>, SessionError> {
+    let Ok(kind) = decoder.read_u8() else {
+        return Ok(None);
+    };
+    let Ok(len) = decoder.read_varint() else {
+        return Ok(None);
+    };
+    let rest = decoder.reader;
+    if rest.len() < len + 4 {
+        return Ok(None);
+    }
+    let (payload, rest) = rest.split_at(len);
+    let (sum, rest) = rest.split_at(4);
+    decoder.reader = rest;
+    if sum == checksum(payload).to_le_bytes() {
+        Ok(Some((kind, payload)))
+    } else if rest.is_empty() {
+        Ok(None)
+    } else {
+        Err(SessionError::Corrupted("journal record is damaged".into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    /// Writer which can be inspected while the table owns the journal.
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl SharedBuf {
+        fn bytes(&self) -> Vec<u8> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_recover_edits_undos_and_redos() {
+        init_logger();
+        // given
+        let original = "some text";
+        let buf = SharedBuf::default();
+        let mut table = PieceTable::from_text(original);
+        table.set_journal(Journal::new(buf.clone()));
+        table.insert_char('!', 9);
+        table.remove(0..5);
+        table.insert_str("more ", 0);
+        table.undo();
+        table.undo();
+        table.redo();
+
+        // when
+        let mut recovered = PieceTable::recover(original, buf.bytes().as_slice()).unwrap();
+
+        // then
+        assert_eq!(recovered.project(), "text!");
+        assert_eq!(recovered.pieces, table.pieces);
+        recovered.redo();
+        assert_eq!(recovered.project(), "more text!");
+        recovered.undo();
+        recovered.undo();
+        recovered.undo();
+        assert_eq!(recovered.project(), original);
+    }
+
+    #[test]
+    fn should_recover_grouped_edit_as_one_step() {
+        init_logger();
+        // given
+        let original = "a b a b";
+        let buf = SharedBuf::default();
+        let mut table = PieceTable::from_text(original);
+        table.set_journal(Journal::new(buf.clone()));
+        table.replace_all("a", "x", None);
+
+        // when
+        let mut recovered = PieceTable::recover(original, buf.bytes().as_slice()).unwrap();
+        let replaced = recovered.project();
+        recovered.undo();
+
+        // then
+        assert_eq!(replaced, "x b x b");
+        assert_eq!(recovered.project(), original);
+    }
+
+    #[test]
+    fn should_keep_history_from_before_journal_was_set() {
+        init_logger();
+        // given
+        let original = "text";
+        let buf = SharedBuf::default();
+        let mut table = PieceTable::from_text(original);
+        table.insert_str("some ", 0);
+        table.set_journal(Journal::new(buf.clone()));
+        table.insert_char('!', table.len());
+
+        // when
+        let mut recovered = PieceTable::recover(original, buf.bytes().as_slice()).unwrap();
+        recovered.undo();
+        recovered.undo();
+
+        // then
+        assert_eq!(recovered.project(), original);
+    }
+
+    #[test]
+    fn should_recover_updates_from_other_replicas() {
+        init_logger();
+        // given
+        let original = "text";
+        let buf = SharedBuf::default();
+        let mut other = PieceTable::from_text(original);
+        other.enable_crdt(std::num::NonZeroU64::new(2).unwrap());
+        other.insert_str("remote ", 0);
+        let mut table = PieceTable::from_text(original);
+        table.enable_crdt(std::num::NonZeroU64::new(1).unwrap());
+        table.set_journal(Journal::new(buf.clone()));
+        table.insert_char('!', 4);
+        for update in other.take_updates() {
+            table.apply_update(update);
+        }
+        table.insert_char('?', 0);
+
+        // when
+        let recovered = PieceTable::recover(original, buf.bytes().as_slice()).unwrap();
+
+        // then
+        assert_eq!(recovered.project(), "?remote text!");
+    }
+
+    #[test]
+    fn should_recover_history_moved_past_remote_updates() {
+        init_logger();
+        // given
+        let original = "text";
+        let buf = SharedBuf::default();
+        let mut other = PieceTable::from_text(original);
+        other.enable_crdt(std::num::NonZeroU64::new(2).unwrap());
+        let mut table = PieceTable::from_text(original);
+        table.enable_crdt(std::num::NonZeroU64::new(1).unwrap());
+        table.set_journal(Journal::new(buf.clone()));
+        table.insert_char('!', 4);
+        other.insert_str("remote ", 0);
+        for update in other.take_updates() {
+            table.apply_update(update);
+        }
+        let journaled = buf.bytes().len();
+        other.insert_char('?', 0);
+        for update in other.take_updates() {
+            table.apply_update(update);
+        }
+
+        // when
+        let mut recovered = PieceTable::recover(original, buf.bytes().as_slice()).unwrap();
+        recovered.undo();
+
+        // then
+        assert!(buf.bytes().len() - journaled < 32);
+        assert_eq!(recovered.project(), "?remote text");
+    }
+
+    #[test]
+    fn should_recover_text_pasted_from_other_tables() {
+        init_logger();
+        // given
+        let original = "text";
+        let buf = SharedBuf::default();
+        let first = PieceTable::from_text("first ");
+        let mut table = PieceTable::from_text(original);
+        table.insert_from(&first, 0..6, 0);
+        table.set_journal(Journal::new(buf.clone()));
+        let second = PieceTable::from_text("second ");
+        table.insert_from(&second, 0..7, 6);
+        table.insert_from(&first, 0..5, table.len());
+
+        // when
+        let recovered = PieceTable::recover(original, buf.bytes().as_slice()).unwrap();
+
+        // then
+        assert_eq!(recovered.project(), "first second textfirst");
+        assert_eq!(recovered.buffers.len(), 2);
+    }
+
+    #[test]
+    fn should_skip_truncated_last_record() {
+        init_logger();
+        // given
+        let original = "text";
+        let buf = SharedBuf::default();
+        let mut table = PieceTable::from_text(original);
+        table.set_journal(Journal::new(buf.clone()));
+        table.insert_char('1', 4);
+        table.insert_char('2', 5);
+        let bytes = buf.bytes();
+
+        // when
+        let recovered = PieceTable::recover(original, &bytes[..bytes.len() - 2]).unwrap();
+
+        // then
+        assert_eq!(recovered.project(), "text1");
+    }
+
+    #[test]
+    fn should_refuse_journal_of_other_text() {
+        init_logger();
+        // given
+        let buf = SharedBuf::default();
+        let mut table = PieceTable::from_text("text");
+        table.set_journal(Journal::new(buf.clone()));
+
+        // when
+        let result = PieceTable::recover("other text", buf.bytes().as_slice());
+
+        // then
+        assert!(matches!(result, Err(SessionError::OriginalMismatch)));
+    }
+
+    #[test]
+    fn should_refuse_journal_of_previous_version() {
+        init_logger();
+        // given
+        let buf = SharedBuf::default();
+        let mut table = PieceTable::from_text("text");
+        table.set_journal(Journal::new(buf.clone()));
+        let mut bytes = buf.bytes();
+        bytes[4] = 1;
+
+        // when
+        let result = PieceTable::recover("text", bytes.as_slice());
+
+        // then
+        assert!(matches!(result, Err(SessionError::UnsupportedVersion(1))));
+    }
+
+    #[test]
+    fn should_report_write_errors_without_interrupting_edits() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("text");
+        table.set_journal(Journal::new(FailingWriter));
+
+        // when
+        table.insert_char('s', 4);
+        let result = table.flush_journal();
+
+        // then
+        assert_eq!(table.project(), "texts");
+        assert!(result.is_err());
+    }
+}
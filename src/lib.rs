@@ -1,58 +1,236 @@
 #![allow(clippy::missing_errors_doc)]
 
+use anchor::Anchors;
+use blame::Authors;
+use buffer::Buffer;
+use crdt::Crdt;
+use fork::Fork;
+use history::{Change, Edit, TextSplice};
 use log::trace;
-use std::ops::Range;
-
-#[derive(Debug, PartialEq, Eq)]
+use rebase::Rebase;
+use std::ops::{Bound, Range, RangeBounds};
+use std::sync::atomic::{AtomicU64, Ordering};
+use version::Versions;
+
+pub use anchor::{Anchor, Bias};
+pub use blame::{Authorship, Blame};
+pub use crdt::{ItemId, Update};
+pub use diff::Difference;
+pub use fork::ForkError;
+pub use io::Reader;
+pub use journal::Journal;
+pub use line_changes::LineChange;
+pub use merge::{Conflict, MergeOptions};
+pub use operation::{Component, Operation, OperationError};
+pub use patch::{AppliedHunk, HunkRejected, Patch, PatchError, RejectReason};
+#[cfg(feature = "regex")]
+pub use regex::{Captures, Regex, RegexError};
+pub use replace::ReplacePattern;
+pub use save::{SaveError, SaveOptions};
+pub use search::Pattern;
+pub use session::SessionError;
+pub use sync::{SyncClient, SyncError, SyncServer, VersionVector};
+pub use version::Version;
+
+mod anchor;
+mod blame;
+mod buffer;
+mod cmp;
+mod crdt;
+mod diff;
+mod fmt;
+mod fork;
+mod history;
+mod io;
+mod journal;
+mod line_changes;
+mod merge;
+mod operation;
+mod patch;
+mod rebase;
+#[cfg(feature = "regex")]
+mod regex;
+mod reload;
+mod replace;
+mod revert;
+mod save;
+mod search;
+#[cfg(feature = "serde")]
+mod serialize;
+mod session;
+mod split;
+mod sync;
+mod version;
+
+#[derive(Debug)]
 pub struct PieceTable<'a> {
-    original_buffer: &'a str,
+    original_buffer: Buffer<'a>,
     addition_buffer: String,
+    /// Read-only buffers besides the original one, shared with other tables or read from files,
+    /// see [`Source::Shared`].
+    buffers: Vec<Buffer<'a>>,
     pieces: Vec<Piece>,
-    undo: Vec<(usize, Piece)>,
+    history: Vec<Edit>,
+    redo: Vec<Edit>,
+    /// Undo and redo history moved past remote changes, which takes the place of `history` and
+    /// `redo` until [`PieceTable::settle_history`].
+    rebase: Option<Rebase>,
+    pending: Option<Edit>,
+    journal: Option<Journal>,
+    /// Length of the undo history when the text was last saved, `None` if that state was
+    /// discarded from the redo history.
+    saved_at: Option<usize>,
+    anchors: Anchors,
+    crdt: Option<Crdt>,
+    authors: Authors,
+    versions: Versions,
+    /// Unique among the tables, to tell which one a branch was forked from.
+    id: u64,
+    fork: Option<Fork>,
+    /// Bytes of a char only partly written through [`io::Write`](std::io::Write) yet.
+    unfinished_char: Vec<u8>,
 }
 
 impl<'a> PieceTable<'a> {
     #[must_use]
     pub fn from_text(txt: &'a str) -> Self {
+        let pieces = vec![Piece::new(0..txt.len(), Source::Original)];
+        Self::from_parts(
+            Buffer::Borrowed(txt),
+            String::new(),
+            pieces,
+            Vec::new(),
+            Vec::new(),
+        )
+    }
+
+    fn from_parts(
+        original_buffer: Buffer<'a>,
+        addition_buffer: String,
+        pieces: Vec<Piece>,
+        history: Vec<Edit>,
+        redo: Vec<Edit>,
+    ) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let versions = Versions::new(&pieces);
         Self {
-            original_buffer: txt,
-            addition_buffer: String::new(),
-            pieces: vec![Piece::new(0..txt.len(), Source::Original)],
-            undo: Vec::new(),
+            original_buffer,
+            addition_buffer,
+            buffers: Vec::new(),
+            pieces,
+            history,
+            redo,
+            rebase: None,
+            pending: None,
+            journal: None,
+            saved_at: Some(0),
+            anchors: Anchors::default(),
+            crdt: None,
+            authors: Authors::default(),
+            versions,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            fork: None,
+            unfinished_char: Vec::new(),
         }
     }
 
     pub fn insert_char(&mut self, c: char, cursor_idx: usize) {
+        let start = self.addition_buffer().len();
+        let add_piece = Piece::new(start..start + c.len_utf8(), Source::Add);
+        self.extend_addition_buffer(c);
+        self.authors.record(start..self.addition_buffer.len());
+        self.insert_at(cursor_idx, vec![add_piece]);
+    }
+
+    pub fn insert_str(&mut self, txt: &str, cursor_idx: usize) {
+        if txt.is_empty() {
+            return;
+        }
+        let start = self.addition_buffer().len();
+        let add_piece = Piece::new(start..start + txt.len(), Source::Add);
+        self.addition_buffer.push_str(txt);
+        self.authors.record(start..self.addition_buffer.len());
+        self.insert_at(cursor_idx, vec![add_piece]);
+    }
+
+    /// Inserts the text in `range` of `other` at `cursor_idx`, as a single edit.
+    ///
+    /// The original text and the shared buffers of `other` are not copied, the inserted pieces
+    /// point into them, so pasting a large region is cheap. Only text inserted into `other` is
+    /// copied into the addition buffer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is not within `other`, or if `cursor_idx > len`.
+    pub fn insert_from(&mut self, other: &PieceTable<'a>, range: Range<usize>, cursor_idx: usize) {
+        let other_len = other.len();
+        assert!(
+            range.start <= range.end && range.end <= other_len,
+            "range {range:?} should be within the other table (len is {other_len})"
+        );
         let len = self.len();
-        if len < cursor_idx {
-            panic!("insertion index (is {cursor_idx}) should be <= len (is {len})");
+        assert!(
+            cursor_idx <= len,
+            "insertion index (is {cursor_idx}) should be <= len (is {len})"
+        );
+        let mut inserted = Vec::new();
+        for piece in slice_pieces(&other.pieces, range) {
+            let source = match piece.source {
+                Source::Original => self.share(&other.original_buffer),
+                Source::Shared(idx) => self.share(&other.buffers[idx]),
+                Source::Add => {
+                    let start = self.addition_buffer.len();
+                    self.addition_buffer.push_str(other.piece_text(&piece));
+                    self.authors.record(start..self.addition_buffer.len());
+                    inserted.push(Piece::new(start..self.addition_buffer.len(), Source::Add));
+                    continue;
+                }
+            };
+            inserted.push(Piece::new(piece.range, source));
+        }
+        if !inserted.is_empty() {
+            self.insert_at(cursor_idx, inserted);
         }
+    }
 
-        let start = self.addition_buffer().len();
-        let add_piece = Piece::new(start..start + 1, Source::Add);
+    /// Returns the source referring to `buffer`, adding it to the shared buffers if needed.
+    ///
+    /// Even the own original text is shared rather than referred to as [`Source::Original`], so
+    /// that text coming from elsewhere never counts as original text which wasn't changed.
+    fn share(&mut self, buffer: &Buffer<'a>) -> Source {
+        if let Some(idx) = self.buffers.iter().position(|b| b.is_same(buffer)) {
+            return Source::Shared(idx);
+        }
+        self.buffers.push(buffer.clone());
+        Source::Shared(self.buffers.len() - 1)
+    }
 
-        self.extend_addition_buffer(c);
+    fn insert_at(&mut self, cursor_idx: usize, mut inserted: Vec<Piece>) {
+        let len = self.len();
+        if len < cursor_idx {
+            panic!("insertion index (is {cursor_idx}) should be <= len (is {len})");
+        }
 
         if cursor_idx == len {
             // we are appending txt at the end
             trace!("text empty or appending at the end");
-            self.append_piece(add_piece);
+            let pieces_len = self.pieces.len();
+            self.splice_pieces(pieces_len..pieces_len, inserted);
             return;
         }
 
         trace!("inserting text in the middle");
-        let (piece_idx, _) = self.find_piece_idx(cursor_idx);
+        let (piece_idx, offset) = self.find_piece_idx(cursor_idx);
 
         let current_piece = self.piece(piece_idx);
-        if current_piece.len() > 1 {
+        if current_piece.len() > 1 && offset > 0 {
             // we need to split the original piece into two and insert new in the middle
-            let current_piece = self.remove_piece(piece_idx);
-            let (first_piece, second_piece) = current_piece.split_at(cursor_idx);
-            self.insert_piece(piece_idx, first_piece);
-            self.insert_piece(piece_idx + 1, add_piece);
-            self.insert_piece(piece_idx + 2, second_piece);
+            let (first_piece, second_piece) = current_piece.clone().split_at(offset);
+            inserted.insert(0, first_piece);
+            inserted.push(second_piece);
+            self.splice_pieces(piece_idx..=piece_idx, inserted);
         } else {
-            self.insert_piece(piece_idx, add_piece);
+            self.splice_pieces(piece_idx..piece_idx, inserted);
         }
     }
 
@@ -65,11 +243,7 @@ impl<'a> PieceTable<'a> {
     }
 
     fn original_buffer(&self) -> &str {
-        self.original_buffer
-    }
-
-    fn append_piece(&mut self, add_piece: Piece) {
-        self.pieces.push(add_piece);
+        &self.original_buffer
     }
 
     fn find_piece_idx(&self, cursor_idx: usize) -> (usize, usize) {
@@ -85,63 +259,190 @@ impl<'a> PieceTable<'a> {
         panic!("cursor index is out of range")
     }
 
-    fn remove_piece(&mut self, idx: usize) -> Piece {
-        assert!(idx < self.pieces.len());
-        self.pieces.remove(idx)
+    /// Replaces `range` of pieces with `inserted` and records it in the undo history.
+    fn splice_pieces<R>(&mut self, range: R, inserted: Vec<Piece>)
+    where
+        R: RangeBounds<usize>,
+    {
+        let at = match range.start_bound() {
+            Bound::Included(&idx) => idx,
+            Bound::Excluded(&idx) => idx + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&idx) => idx + 1,
+            Bound::Excluded(&idx) => idx,
+            Bound::Unbounded => self.pieces.len(),
+        };
+        let removed: Vec<_> = self.pieces.splice(at..end, inserted.clone()).collect();
+        self.follow_change(at, &removed, &inserted);
+        self.record(Change::new(at, removed, inserted));
+    }
+
+    /// Lets the anchors and the CRDT state follow `removed` pieces at index `at` being replaced
+    /// with `inserted` ones. Only the pieces before `at` are looked at, so it doesn't matter if
+    /// the replacement was already done.
+    fn follow_change(&mut self, at: usize, removed: &[Piece], inserted: &[Piece]) {
+        if self.anchors.is_empty() && self.crdt.is_none() {
+            return;
+        }
+        let splice = TextSplice::new(&self.pieces, at, removed, inserted);
+        self.anchors
+            .shift(splice.at, splice.removed, splice.inserted.len());
+        if self.crdt.is_some() {
+            let inserted: Vec<_> = slice_pieces(inserted, splice.inserted.clone())
+                .into_iter()
+                .map(|piece| {
+                    let txt = self.piece_text(&piece).to_string();
+                    (piece, txt)
+                })
+                .collect();
+            if let Some(crdt) = &mut self.crdt {
+                crdt.local_change(splice.at, splice.removed, inserted);
+            }
+        }
+    }
+
+    fn record(&mut self, change: Change) {
+        self.redo.clear();
+        match &mut self.pending {
+            Some(edit) => edit.push(change),
+            None => self.push_history(Edit::from(change)),
+        }
+    }
+
+    fn push_history(&mut self, edit: Edit) {
+        if self
+            .saved_at
+            .is_some_and(|saved_at| saved_at > self.history.len())
+        {
+            // the saved state was in the redo history, which a new edit clears
+            self.saved_at = None;
+        }
+        self.journal_edit(&edit);
+        self.versions.applied(&edit, &self.pieces);
+        match &mut self.rebase {
+            Some(rebase) => rebase.record(&self.pieces, &edit),
+            None => self.history.push(edit),
+        }
+        self.authors.end_edit();
     }
 
-    fn insert_piece(&mut self, current_idx: usize, first_piece: Piece) {
-        self.pieces.insert(current_idx, first_piece);
+    /// Runs `f` so that all the changes it makes are undone and redone as a single step.
+    fn transaction<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        if self.pending.is_some() {
+            return f(self);
+        }
+        self.pending = Some(Edit::default());
+        let result = f(self);
+        let edit = self.pending.take().unwrap_or_default();
+        if !edit.is_empty() {
+            self.push_history(edit);
+        }
+        result
     }
 
     pub fn remove_char(&mut self, cursor_idx: usize) -> Option<char> {
         let char = self.char_at(cursor_idx);
+        let char_len = char.len_utf8();
         let (piece_idx, offset) = self.find_piece_idx(cursor_idx);
-        let current_piece = self.remove_piece(piece_idx);
+        let current_piece = self.piece(piece_idx).clone();
         let real_idx = current_piece.range.start + offset;
-        if current_piece.range.start < real_idx && real_idx < current_piece.range.end - 1 {
-            let (first_piece, mut second_piece) = current_piece.split_at(cursor_idx);
-            second_piece.range.start += 1;
-            self.insert_piece(piece_idx, first_piece);
-            self.insert_piece(piece_idx + 1, second_piece);
+        if current_piece.range.start < real_idx && real_idx < current_piece.range.end - char_len {
+            let (first_piece, mut second_piece) = current_piece.split_at(offset);
+            second_piece.range.start += char_len;
+            self.splice_pieces(piece_idx..=piece_idx, vec![first_piece, second_piece]);
         } else if current_piece.range.start == real_idx {
             let mut current_piece = current_piece;
-            current_piece.range.start += 1;
-            self.insert_piece(piece_idx, current_piece);
+            current_piece.range.start += char_len;
+            self.splice_pieces(piece_idx..=piece_idx, vec![current_piece]);
         } else {
             let mut current_piece = current_piece;
-            current_piece.range.end -= 1;
-            self.insert_piece(piece_idx, current_piece);
+            current_piece.range.end -= char_len;
+            self.splice_pieces(piece_idx..=piece_idx, vec![current_piece]);
         }
         Some(char)
     }
 
     pub fn remove(&mut self, range: Range<usize>) -> Option<String> {
-        let mut chars = vec![' '; range.len()];
-        let mut i = range.len() - 1;
-        for cursor_idx in range.rev() {
-            chars[i] = self.remove_char(cursor_idx)?;
-            i = i.saturating_sub(1);
+        if range.start > range.end || range.end > self.len() {
+            return None;
+        }
+        if range.is_empty() {
+            return Some(String::new());
         }
-        Some(chars.into_iter().collect())
+        let removed = self.chunks_in(range.clone()).map(|(_, txt)| txt).collect();
+        self.remove_pieces(range);
+        Some(removed)
+    }
+
+    /// Removes the non-empty, in bounds `range` of the text.
+    fn remove_pieces(&mut self, range: Range<usize>) {
+        let (first_idx, first_offset) = self.find_piece_idx(range.start);
+        let (last_idx, last_offset) = self.find_piece_idx(range.end - 1);
+        let mut kept = Vec::new();
+        if first_offset > 0 {
+            let (before, _) = self.piece(first_idx).clone().split_at(first_offset);
+            kept.push(before);
+        }
+        if last_offset + 1 < self.piece(last_idx).len() {
+            let (_, after) = self.piece(last_idx).clone().split_at(last_offset + 1);
+            kept.push(after);
+        }
+        self.splice_pieces(first_idx..=last_idx, kept);
     }
 
     pub fn undo(&mut self) {
-        let last_idx = self.pieces.len() - 1;
-        let last_piece = self.pieces.remove(last_idx);
-        self.undo.push((last_idx, last_piece));
+        self.settle_history();
+        let Some(edit) = self.history.pop() else {
+            return;
+        };
+        for change in edit.changes().iter().rev() {
+            change.revert(&mut self.pieces);
+            self.follow_change(change.at(), change.inserted(), change.removed());
+        }
+        self.versions.reverted(&edit, &self.pieces);
+        self.redo.push(edit);
+        self.journal_undo();
     }
 
     pub fn redo(&mut self) {
-        let (last_op_idx, last_op) = self.undo.remove(self.undo.len() - 1);
-        self.pieces.insert(last_op_idx, last_op);
+        self.settle_history();
+        let Some(edit) = self.redo.pop() else {
+            return;
+        };
+        for change in edit.changes() {
+            change.apply(&mut self.pieces);
+            self.follow_change(change.at(), change.removed(), change.inserted());
+        }
+        self.versions.applied(&edit, &self.pieces);
+        self.history.push(edit);
+        self.journal_redo();
+    }
+
+    /// Remembers the current state as the one written to disk.
+    pub fn mark_saved(&mut self) {
+        self.settle_history();
+        self.saved_at = Some(self.history.len());
+    }
+
+    /// Tells if the text differs from the last saved state. Undoing or redoing back to the saved
+    /// state makes the table clean again.
+    #[must_use]
+    pub fn is_dirty(&self) -> bool {
+        self.saved_at != Some(self.history.len())
+    }
+
+    /// Returns how many undos or redos separate the current state from the saved one, or `None`
+    /// if the saved state can't be reached anymore.
+    #[must_use]
+    pub fn changes_since_save(&self) -> Option<usize> {
+        self.saved_at
+            .map(|saved_at| saved_at.abs_diff(self.history.len()))
     }
 
     #[must_use]
     pub fn project(&self) -> String {
-        if self.pieces.is_empty() {
-            return self.original_buffer().to_string();
-        }
         let mut txt = String::new();
         for piece in &self.pieces {
             self.append_from(&mut txt, piece);
@@ -150,17 +451,65 @@ impl<'a> PieceTable<'a> {
     }
 
     fn append_from(&self, txt: &mut String, piece: &Piece) {
-        let buff = match piece.source {
+        txt.push_str(self.piece_text(piece));
+    }
+
+    fn piece_text(&self, piece: &Piece) -> &str {
+        match piece.source {
             Source::Original => &self.original_buffer[piece.range.clone()],
             Source::Add => &self.addition_buffer[piece.range.clone()],
-        };
-        txt.push_str(buff);
+            Source::Shared(idx) => &self.buffers[idx][piece.range.clone()],
+        }
     }
 
-    pub fn len(&self) -> usize {
-        if self.pieces.is_empty() {
-            return self.original_buffer().len();
+    fn chunks(&self) -> impl DoubleEndedIterator<Item = &str> {
+        self.chunks_in(0..self.len()).map(|(_, txt)| txt)
+    }
+
+    /// Returns the parts of the text which fall into `range`, together with their starting
+    /// offsets, without copying anything out of the buffers.
+    fn chunks_in(&self, range: Range<usize>) -> impl DoubleEndedIterator<Item = (usize, &str)> {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        for piece in &self.pieces {
+            chunks.push((start, self.piece_text(piece)));
+            start += piece.len();
         }
+        chunks.into_iter().filter_map(move |(start, txt)| {
+            let end = start + txt.len();
+            if end <= range.start || range.end <= start || txt.is_empty() {
+                return None;
+            }
+            let from = range.start.max(start);
+            let to = range.end.min(end);
+            Some((from, &txt[from - start..to - start]))
+        })
+    }
+
+    fn text_in(&self, range: Range<usize>) -> String {
+        self.chunks_in(range).map(|(_, txt)| txt).collect()
+    }
+
+    fn is_char_boundary(&self, idx: usize) -> bool {
+        let mut start = 0;
+        for piece in &self.pieces {
+            if idx < start + piece.len() {
+                return self.piece_text(piece).is_char_boundary(idx - start);
+            }
+            start += piece.len();
+        }
+        idx == start
+    }
+
+    fn char_indices(
+        &self,
+        range: Range<usize>,
+    ) -> impl DoubleEndedIterator<Item = (usize, char)> + '_ {
+        self.chunks_in(range)
+            .flat_map(|(start, txt)| txt.char_indices().map(move |(idx, c)| (start + idx, c)))
+    }
+
+    pub fn len(&self) -> usize {
         let mut len = 0;
         for piece in &self.pieces {
             len += piece.range.len();
@@ -177,14 +526,54 @@ impl<'a> PieceTable<'a> {
         &self.pieces[current_piece_idx]
     }
 
+    /// Checks that a table which didn't come from editing (e.g. was loaded from a file) is
+    /// consistent: every piece points at whole chars of its buffer and the history can be
+    /// replayed on the pieces.
+    fn validate(&self) -> Result<(), String> {
+        let history_pieces = self.history.iter().chain(&self.redo).flat_map(Edit::pieces);
+        for piece in self.pieces.iter().chain(history_pieces) {
+            let buffer = match piece.source {
+                Source::Original => self.original_buffer(),
+                Source::Add => self.addition_buffer(),
+                Source::Shared(idx) => match self.buffers.get(idx) {
+                    Some(buffer) => buffer,
+                    None => return Err(format!("piece {piece:?} refers to a missing buffer")),
+                },
+            };
+            if buffer.get(piece.range.clone()).is_none() {
+                return Err(format!(
+                    "piece {:?} doesn't point at whole chars of {:?} buffer (length {})",
+                    piece.range,
+                    piece.source,
+                    buffer.len()
+                ));
+            }
+        }
+        let mut pieces = self.pieces.clone();
+        if !self
+            .history
+            .iter()
+            .rev()
+            .all(|edit| edit.checked_revert(&mut pieces))
+        {
+            return Err("undo history doesn't match the pieces".to_string());
+        }
+        let mut pieces = self.pieces.clone();
+        if !self
+            .redo
+            .iter()
+            .rev()
+            .all(|edit| edit.checked_apply(&mut pieces))
+        {
+            return Err("redo history doesn't match the pieces".to_string());
+        }
+        Ok(())
+    }
+
     fn char_at(&self, char_idx: usize) -> char {
         let (piece_idx, offset) = self.find_piece_idx(char_idx);
         let piece = self.piece(piece_idx);
-        let buff = match piece.source {
-            Source::Original => self.original_buffer(),
-            Source::Add => self.addition_buffer(),
-        };
-        buff.chars().nth(piece.range.start + offset).unwrap()
+        self.piece_text(piece)[offset..].chars().next().unwrap()
     }
 }
 
@@ -194,7 +583,50 @@ impl<'a> Default for PieceTable<'a> {
     }
 }
 
+impl FromIterator<char> for PieceTable<'_> {
+    fn from_iter<I: IntoIterator<Item = char>>(iter: I) -> Self {
+        let mut table = Self::default();
+        table.extend(iter);
+        table
+    }
+}
+
+impl Extend<char> for PieceTable<'_> {
+    fn extend<I: IntoIterator<Item = char>>(&mut self, iter: I) {
+        let txt: String = iter.into_iter().collect();
+        self.insert_str(&txt, self.len());
+    }
+}
+
+impl<'s> Extend<&'s str> for PieceTable<'_> {
+    fn extend<I: IntoIterator<Item = &'s str>>(&mut self, iter: I) {
+        let txt: String = iter.into_iter().collect();
+        self.insert_str(&txt, self.len());
+    }
+}
+
+/// Returns the pieces for bytes `range` of the text made of `pieces`.
+fn slice_pieces(pieces: &[Piece], range: Range<usize>) -> Vec<Piece> {
+    let mut sliced = Vec::new();
+    let mut start = 0;
+    for piece in pieces {
+        let end = start + piece.len();
+        let from = range.start.max(start);
+        let to = range.end.min(end);
+        if from < to {
+            let piece_start = piece.range.start;
+            sliced.push(Piece::new(
+                piece_start + from - start..piece_start + to - start,
+                piece.source.clone(),
+            ));
+        }
+        start = end;
+    }
+    sliced
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 struct Piece {
     range: Range<usize>,
     source: Source,
@@ -205,7 +637,8 @@ impl Piece {
         Self { range, source }
     }
 
-    fn split_at(self, idx: usize) -> (Piece, Piece) {
+    fn split_at(self, offset: usize) -> (Piece, Piece) {
+        let idx = self.range.start + offset;
         let mut first_piece = self.clone();
         let mut second_piece = self.clone();
         first_piece.range.end = idx;
@@ -219,9 +652,12 @@ impl Piece {
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 enum Source {
     Original,
     Add,
+    /// Buffer at an index of [`PieceTable::buffers`].
+    Shared(usize),
 }
 
 #[cfg(test)]
@@ -356,6 +792,31 @@ mod tests {
         }
     }
 
+    mod insert_str {
+        use super::*;
+
+        #[test]
+        fn should_add_single_piece_for_whole_text() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("some text");
+
+            // when
+            table.insert_str("inserted ", 5);
+
+            // then
+            assert_eq!(table.project(), "some inserted text");
+            assert_eq!(
+                table.pieces,
+                [
+                    Piece::new(0..5, Source::Original),
+                    Piece::new(0..9, Source::Add),
+                    Piece::new(5..9, Source::Original),
+                ]
+            );
+        }
+    }
+
     mod remove_char {
         use super::*;
 
@@ -481,6 +942,35 @@ mod tests {
             assert_eq!(table.pieces.len(), 1);
             assert_eq!(table.pieces, [Piece::new(0..7, Source::Original)]);
         }
+
+        #[test]
+        fn should_not_remove_range_out_of_bounds() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("initial text");
+
+            // when
+            let removed = table.remove(7..13);
+
+            // then
+            assert_eq!(removed, None);
+            assert_eq!(table.project(), "initial text");
+        }
+
+        #[test]
+        fn should_remove_whole_text() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("text");
+
+            // when
+            let removed = table.remove(0..4);
+
+            // then
+            assert_eq!(removed, Some("text".to_string()));
+            assert_eq!(table.project(), "");
+            assert!(table.is_empty());
+        }
     }
 
     mod undo {
@@ -495,14 +985,29 @@ mod tests {
             let new_char = 's';
             table.insert_char(new_char, initial_txt.len());
             assert_eq!(table.pieces.len(), 2);
-            assert!(table.undo.is_empty());
+            assert!(table.redo.is_empty());
 
             // when
             table.undo();
 
             // then
             assert_eq!(table.pieces.len(), 1);
-            assert_eq!(table.undo.len(), 1);
+            assert_eq!(table.redo.len(), 1);
+        }
+
+        #[test]
+        fn should_undo_removal_spanning_pieces() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("initial text");
+            table.insert_str("ly", 7);
+            table.remove(5..11);
+
+            // when
+            table.undo();
+
+            // then
+            assert_eq!(table.project(), "initially text");
         }
     }
 
@@ -519,14 +1024,100 @@ mod tests {
             table.insert_char(new_char, initial_txt.len());
             table.undo();
             assert_eq!(table.pieces.len(), 1);
-            assert_eq!(table.undo.len(), 1);
+            assert_eq!(table.redo.len(), 1);
 
             // when
             table.redo();
 
             // then
             assert_eq!(table.pieces.len(), 2);
-            assert!(table.undo.is_empty());
+            assert!(table.redo.is_empty());
+        }
+    }
+
+    mod dirty {
+        use super::*;
+
+        #[test]
+        fn new_table_is_clean() {
+            init_logger();
+            // given
+            let table = PieceTable::from_text("text");
+
+            // when
+            let dirty = table.is_dirty();
+
+            // then
+            assert!(!dirty);
+            assert_eq!(table.changes_since_save(), Some(0));
+        }
+
+        #[test]
+        fn should_be_dirty_after_edits_since_save() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("text");
+            table.insert_char('1', 4);
+            table.mark_saved();
+
+            // when
+            table.insert_char('2', 5);
+            table.remove(0..1);
+
+            // then
+            assert!(table.is_dirty());
+            assert_eq!(table.changes_since_save(), Some(2));
+        }
+
+        #[test]
+        fn should_be_clean_after_undoing_to_save_point() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("text");
+            table.insert_char('1', 4);
+            table.mark_saved();
+            table.insert_char('2', 5);
+
+            // when
+            table.undo();
+
+            // then
+            assert!(!table.is_dirty());
+        }
+
+        #[test]
+        fn should_be_clean_after_redoing_to_save_point() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("text");
+            table.insert_char('1', 4);
+            table.mark_saved();
+            table.undo();
+            assert_eq!(table.changes_since_save(), Some(1));
+
+            // when
+            table.redo();
+
+            // then
+            assert!(!table.is_dirty());
+        }
+
+        #[test]
+        fn should_stay_dirty_when_save_point_is_discarded() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("text");
+            table.insert_char('1', 4);
+            table.mark_saved();
+            table.undo();
+
+            // when
+            table.insert_char('2', 4);
+            table.undo();
+
+            // then
+            assert!(table.is_dirty());
+            assert_eq!(table.changes_since_save(), None);
         }
     }
 
@@ -611,6 +1202,21 @@ mod tests {
             assert_eq!(&txt, "some sinitial text");
         }
 
+        #[test]
+        fn should_edit_between_multibyte_chars() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("za\u{17c}\u{f3}\u{142}\u{107}");
+            table.insert_char('!', 4);
+            table.remove_char(7);
+
+            // when
+            let txt = table.project();
+
+            // then
+            assert_eq!(txt, "za\u{17c}!\u{f3}\u{107}");
+        }
+
         #[test]
         fn should_remove_char_from_the_middle() {
             init_logger();
@@ -692,6 +1298,134 @@ mod tests {
         }
     }
 
+    mod from_iter_and_extend {
+        use super::*;
+
+        #[test]
+        fn should_collect_chars_into_table() {
+            init_logger();
+            // given
+            let chars = ['a', 'b', 'c'];
+
+            // when
+            let table: PieceTable = chars.into_iter().collect();
+
+            // then
+            assert_eq!(table.project(), "abc");
+        }
+
+        #[test]
+        fn should_extend_with_chars_and_strings_as_single_pieces() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("a");
+
+            // when
+            table.extend("bcd".chars());
+            table.extend(["ef", "gh"]);
+
+            // then
+            assert_eq!(table.project(), "abcdefgh");
+            assert_eq!(table.pieces.len(), 3);
+        }
+    }
+
+    mod insert_from {
+        use super::*;
+
+        #[test]
+        fn should_point_into_original_of_other_table() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("some text");
+            let other = PieceTable::from_text("other text");
+
+            // when
+            table.insert_from(&other, 0..6, 5);
+
+            // then
+            assert_eq!(table.project(), "some other text");
+            assert_eq!(table.addition_buffer, "");
+            assert_eq!(
+                table.pieces,
+                [
+                    Piece::new(0..5, Source::Original),
+                    Piece::new(0..6, Source::Shared(0)),
+                    Piece::new(5..9, Source::Original),
+                ]
+            );
+        }
+
+        #[test]
+        fn should_share_each_buffer_once() {
+            init_logger();
+            // given
+            let first = PieceTable::from_text("shared text");
+            let mut second = PieceTable::default();
+            second.insert_from(&first, 0..6, 0);
+            let mut table = PieceTable::default();
+
+            // when
+            table.insert_from(&first, 6..11, 0);
+            table.insert_from(&second, 0..6, 0);
+
+            // then
+            assert_eq!(table.project(), "shared text");
+            assert_eq!(table.buffers.len(), 1);
+        }
+
+        #[test]
+        fn should_copy_text_inserted_into_other_table() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("text");
+            let mut other = PieceTable::from_text("other");
+            other.insert_str("an ", 0);
+
+            // when
+            table.insert_from(&other, 0..other.len(), 0);
+
+            // then
+            assert_eq!(table.project(), "an othertext");
+            assert_eq!(table.addition_buffer, "an ");
+        }
+
+        #[test]
+        fn should_treat_text_pasted_from_fork_as_inserted() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("one two");
+            let branch = table.fork();
+            table.insert_from(&branch, 0..3, 7);
+
+            // when
+            let reverted = table.revert_range(5..9);
+
+            // then
+            assert_eq!(reverted, Some(5..7));
+            assert_eq!(table.project(), "one two");
+            assert_eq!(table.buffers.len(), 1);
+        }
+
+        #[test]
+        fn should_undo_paste_at_once() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("text");
+            let mut other = PieceTable::from_text("a b");
+            other.insert_str("c ", 2);
+
+            // when
+            table.insert_from(&other, 0..5, 2);
+            let pasted = table.project();
+            table.undo();
+
+            // then
+            assert_eq!(pasted, "tea c bxt");
+            assert_eq!(table.project(), "text");
+        }
+    }
+
     mod len_and_empty {
         use super::*;
 
@@ -0,0 +1,248 @@
+use crate::{PieceTable, Source};
+use std::ops::Range;
+
+/// How a line differs from the original text, like the markers in a git gutter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LineChange {
+    /// The line consists only of inserted text.
+    Added,
+    /// The line mixes original and inserted text, or had some of its text removed.
+    Modified,
+    /// Original lines were removed right before the line. Reported as an empty range of lines.
+    Deleted,
+}
+
+/// Where the text of a line comes from, not counting the newline.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Content {
+    #[default]
+    Empty,
+    Original,
+    Added,
+    Mixed,
+}
+
+impl Content {
+    fn with(self, added: bool) -> Self {
+        match (self, added) {
+            (Self::Empty | Self::Added, true) => Self::Added,
+            (Self::Empty | Self::Original, false) => Self::Original,
+            _ => Self::Mixed,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct LineState {
+    content: Content,
+    newline_added: bool,
+    lost_text: bool,
+}
+
+impl LineState {
+    fn is_empty(&self) -> bool {
+        self.content == Content::Empty
+    }
+
+    fn change(&self) -> Option<LineChange> {
+        match self.content {
+            Content::Added => Some(LineChange::Added),
+            Content::Empty if self.newline_added => Some(LineChange::Added),
+            Content::Mixed => Some(LineChange::Modified),
+            Content::Original if self.lost_text || self.newline_added => Some(LineChange::Modified),
+            _ => None,
+        }
+    }
+}
+
+impl PieceTable<'_> {
+    /// Lists lines which differ from the original text, with consecutive lines of the same kind
+    /// merged into one range. Lines are counted from 0.
+    ///
+    /// It only looks at which pieces come from the original buffer, so it is cheap, but text
+    /// typed back exactly as it was is still reported as changed.
+    #[must_use]
+    pub fn line_changes(&self) -> Vec<(Range<usize>, LineChange)> {
+        let mut lines = Vec::new();
+        let mut deleted_before = Vec::new();
+        let mut line = LineState::default();
+        let mut expected_original = 0;
+        for piece in self.pieces.iter().filter(|piece| piece.len() > 0) {
+            if piece.source == Source::Original {
+                if piece.range.start != expected_original {
+                    mark_deletion(&mut line, lines.len(), &mut deleted_before);
+                }
+                expected_original = piece.range.end;
+            }
+            let added = piece.source != Source::Original;
+            for segment in self.piece_text(piece).split_inclusive('\n') {
+                let content = segment.strip_suffix('\n');
+                if !content.unwrap_or(segment).is_empty() {
+                    line.content = line.content.with(added);
+                }
+                if content.is_some() {
+                    line.newline_added = added;
+                    lines.push(std::mem::take(&mut line));
+                }
+            }
+        }
+        if expected_original != self.original_buffer().len() {
+            mark_deletion(&mut line, lines.len(), &mut deleted_before);
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+
+        let mut changes: Vec<(Range<usize>, LineChange)> = Vec::new();
+        let mut deleted_before = deleted_before.into_iter().peekable();
+        for (idx, line) in lines.iter().enumerate() {
+            if deleted_before.next_if_eq(&idx).is_some() && line.change().is_none() {
+                changes.push((idx..idx, LineChange::Deleted));
+            }
+            let Some(change) = line.change() else {
+                continue;
+            };
+            match changes.last_mut() {
+                Some((range, last)) if *last == change && range.end == idx => range.end += 1,
+                _ => changes.push((idx..idx + 1, change)),
+            }
+        }
+        if deleted_before.next().is_some() {
+            changes.push((lines.len()..lines.len(), LineChange::Deleted));
+        }
+        changes
+    }
+}
+
+fn mark_deletion(line: &mut LineState, line_idx: usize, deleted_before: &mut Vec<usize>) {
+    if line.is_empty() {
+        if deleted_before.last() != Some(&line_idx) {
+            deleted_before.push(line_idx);
+        }
+    } else {
+        line.lost_text = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    const TXT: &str = "first\nsecond\nthird\n";
+
+    #[test]
+    fn unchanged_text_has_no_changes() {
+        init_logger();
+        // given
+        let table = PieceTable::from_text(TXT);
+
+        // when
+        let changes = table.line_changes();
+
+        // then
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn should_mark_inserted_lines_as_added() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text(TXT);
+        table.insert_str("new 1\nnew 2\n", 6);
+
+        // when
+        let changes = table.line_changes();
+
+        // then
+        assert_eq!(table.project(), "first\nnew 1\nnew 2\nsecond\nthird\n");
+        assert_eq!(changes, [(1..3, LineChange::Added)]);
+    }
+
+    #[test]
+    fn should_mark_lines_edited_in_the_middle_as_modified() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text(TXT);
+        table.insert_char('!', 5);
+        table.remove_char(10);
+
+        // when
+        let changes = table.line_changes();
+
+        // then
+        assert_eq!(table.project(), "first!\nsecnd\nthird\n");
+        assert_eq!(changes, [(0..2, LineChange::Modified)]);
+    }
+
+    #[test]
+    fn should_mark_removed_lines_as_deleted() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text(TXT);
+        table.remove(6..13);
+
+        // when
+        let changes = table.line_changes();
+
+        // then
+        assert_eq!(table.project(), "first\nthird\n");
+        assert_eq!(changes, [(1..1, LineChange::Deleted)]);
+    }
+
+    #[test]
+    fn should_mark_deletion_at_the_end() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text(TXT);
+        table.remove(13..19);
+
+        // when
+        let changes = table.line_changes();
+
+        // then
+        assert_eq!(changes, [(2..2, LineChange::Deleted)]);
+    }
+
+    #[test]
+    fn should_mark_joined_lines_as_modified() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text(TXT);
+        table.remove_char(5);
+
+        // when
+        let changes = table.line_changes();
+
+        // then
+        assert_eq!(table.project(), "firstsecond\nthird\n");
+        assert_eq!(changes, [(0..1, LineChange::Modified)]);
+    }
+
+    #[test]
+    fn should_report_all_kinds_in_line_order() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text(TXT);
+        table.remove(0..6);
+        table.insert_str("added\n", 7);
+        table.insert_char('3', 18);
+
+        // when
+        let changes = table.line_changes();
+
+        // then
+        assert_eq!(table.project(), "second\nadded\nthird3\n");
+        assert_eq!(
+            changes,
+            [
+                (0..0, LineChange::Deleted),
+                (1..2, LineChange::Added),
+                (2..3, LineChange::Modified),
+            ]
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use crate::{Difference, PieceTable};
+use std::ops::Range;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MergeOptions {
+    markers: bool,
+}
+
+impl MergeOptions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces each conflict with both versions between `<<<<<<< ours`, `=======` and
+    /// `>>>>>>> theirs` lines, like git does. Without it the table keeps its own version.
+    #[must_use]
+    pub fn markers(mut self) -> Self {
+        self.markers = true;
+        self
+    }
+}
+
+/// Text changed differently in the table and in the other version, as byte ranges. They cover
+/// whole lines for [`PieceTable::three_way_merge`], and the changed text for
+/// [`PieceTable::merge`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// The bytes of the original text.
+    pub base: Range<usize>,
+    /// The bytes of the table after the merge, including the markers if they were inserted.
+    pub ours: Range<usize>,
+    /// The bytes of the other version.
+    pub theirs: Range<usize>,
+}
+
+/// Changes of both sides touching the same part of the original text, as byte ranges.
+pub(crate) struct Cluster {
+    pub(crate) base: Range<usize>,
+    pub(crate) ours: Range<usize>,
+    pub(crate) theirs: Range<usize>,
+    pub(crate) changed_by_us: bool,
+    pub(crate) changed_by_them: bool,
+}
+
+impl PieceTable<'_> {
+    /// Merges `theirs`, a version of the original text changed independently from this table,
+    /// e.g. the file rewritten on disk while the table has unsaved changes.
+    ///
+    /// Lines changed only in `theirs` are changed in the table too, and lines changed the same
+    /// way on both sides are left alone. Changes touching the same or adjacent lines are
+    /// conflicts, returned in order. The whole merge is a single step in the undo history.
+    pub fn three_way_merge(&mut self, theirs: &str, options: &MergeOptions) -> Vec<Conflict> {
+        let base = PieceTable::from_text(self.original_buffer());
+        let their_table = PieceTable::from_text(theirs);
+        let clusters = clusters(&base.diff_lines(self), &base.diff_lines(&their_table));
+
+        let mut edits = Vec::new();
+        let mut conflicts = Vec::new();
+        let mut shift = 0isize;
+        for cluster in clusters {
+            let ours = self.text_in(cluster.ours.clone());
+            let their_txt = &theirs[cluster.theirs.clone()];
+            let replacement = if !cluster.changed_by_us {
+                their_txt.to_string()
+            } else if !cluster.changed_by_them || ours == their_txt {
+                continue;
+            } else if options.markers {
+                markers(&ours, their_txt)
+            } else {
+                ours.clone()
+            };
+            let start = cluster.ours.start.saturating_add_signed(shift);
+            if cluster.changed_by_us {
+                conflicts.push(Conflict {
+                    base: cluster.base,
+                    ours: start..start + replacement.len(),
+                    theirs: cluster.theirs,
+                });
+            }
+            shift += replacement
+                .len()
+                .wrapping_sub(cluster.ours.len())
+                .cast_signed();
+            if replacement != ours {
+                edits.push((cluster.ours, replacement));
+            }
+        }
+        self.transaction(|table| {
+            for (range, txt) in edits.iter().rev() {
+                table.remove(range.clone());
+                table.insert_str(txt, range.start);
+            }
+        });
+        conflicts
+    }
+}
+
+/// Change and whether it was made in the table.
+type SidedChange<'d> = (&'d Difference, bool);
+
+/// Groups the changes of both sides into clusters of changes touching each other in the base.
+pub(crate) fn clusters(ours: &[Difference], theirs: &[Difference]) -> Vec<Cluster> {
+    let mut changes: Vec<SidedChange> = ours
+        .iter()
+        .map(|change| (change, true))
+        .chain(theirs.iter().map(|change| (change, false)))
+        .collect();
+    changes.sort_by_key(|(change, _)| (change.old.start, change.old.end));
+
+    let mut clusters: Vec<(Range<usize>, Vec<SidedChange>)> = Vec::new();
+    for (change, by_us) in changes {
+        match clusters.last_mut() {
+            Some((base, members)) if change.old.start <= base.end => {
+                base.end = base.end.max(change.old.end);
+                members.push((change, by_us));
+            }
+            _ => clusters.push((change.old.clone(), vec![(change, by_us)])),
+        }
+    }
+
+    // outside of the changes both sides are the same as the base, just shifted
+    let mut shifts = (0isize, 0isize);
+    clusters
+        .into_iter()
+        .map(|(base, members)| {
+            let side = |by_us: bool, shift: &mut isize| {
+                let changes: Vec<_> = members
+                    .iter()
+                    .filter(|(_, side)| *side == by_us)
+                    .map(|(change, _)| *change)
+                    .collect();
+                let range = match (changes.first(), changes.last()) {
+                    (Some(first), Some(last)) => {
+                        first.new.start - (first.old.start - base.start)
+                            ..last.new.end + (base.end - last.old.end)
+                    }
+                    _ => {
+                        base.start.saturating_add_signed(*shift)
+                            ..base.end.saturating_add_signed(*shift)
+                    }
+                };
+                *shift = range.end.wrapping_sub(base.end).cast_signed();
+                (range, !changes.is_empty())
+            };
+            let (ours, changed_by_us) = side(true, &mut shifts.0);
+            let (theirs, changed_by_them) = side(false, &mut shifts.1);
+            Cluster {
+                base,
+                ours,
+                theirs,
+                changed_by_us,
+                changed_by_them,
+            }
+        })
+        .collect()
+}
+
+fn markers(ours: &str, theirs: &str) -> String {
+    let end_line = |txt: &str| {
+        if txt.is_empty() || txt.ends_with('\n') {
+            ""
+        } else {
+            "\n"
+        }
+    };
+    format!(
+        "<<<<<<< ours\n{ours}{}=======\n{theirs}{}>>>>>>> theirs\n",
+        end_line(ours),
+        end_line(theirs)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    const BASE: &str = "one\ntwo\nthree\nfour\nfive\n";
+
+    #[test]
+    fn should_take_changes_from_both_sides() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text(BASE);
+        table.insert_str("ONE", 0);
+        table.remove(3..6);
+
+        // when
+        let conflicts =
+            table.three_way_merge("one\ntwo\nthree\nfour\n4.5\nfive\n", &MergeOptions::new());
+
+        // then
+        assert_eq!(conflicts, vec![]);
+        assert_eq!(table.project(), "ONE\ntwo\nthree\nfour\n4.5\nfive\n");
+    }
+
+    #[test]
+    fn should_accept_same_change_on_both_sides() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text(BASE);
+        table.remove(4..8);
+
+        // when
+        let conflicts = table.three_way_merge("one\nthree\nfour\nfive\n", &MergeOptions::new());
+
+        // then
+        assert_eq!(conflicts, vec![]);
+        assert_eq!(table.project(), "one\nthree\nfour\nfive\n");
+    }
+
+    #[test]
+    fn should_keep_own_version_of_conflicts() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text(BASE);
+        table.insert_str("2", 4);
+        let theirs = "one\nTWO\nthree\nfour\nfive\nsix\n";
+
+        // when
+        let conflicts = table.three_way_merge(theirs, &MergeOptions::new());
+
+        // then
+        assert_eq!(
+            conflicts,
+            vec![Conflict {
+                base: 4..8,
+                ours: 4..9,
+                theirs: 4..8,
+            }]
+        );
+        assert_eq!(table.project(), "one\n2two\nthree\nfour\nfive\nsix\n");
+    }
+
+    #[test]
+    fn should_insert_conflict_markers() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text(BASE);
+        table.remove(14..19);
+        table.insert_str("4\n", 14);
+        let theirs = "1\ntwo\nthree\nfive\n";
+
+        // when
+        let conflicts = table.three_way_merge(theirs, &MergeOptions::new().markers());
+
+        // then
+        let merged = "1\ntwo\nthree\n<<<<<<< ours\n4\n=======\n>>>>>>> theirs\nfive\n";
+        assert_eq!(
+            conflicts,
+            vec![Conflict {
+                base: 14..19,
+                ours: 12..50,
+                theirs: 12..12,
+            }]
+        );
+        assert_eq!(table.project(), merged);
+    }
+
+    #[test]
+    fn should_conflict_on_insertions_at_same_place() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("a\nb");
+        table.insert_str("x\n", 2);
+
+        // when
+        let conflicts = table.three_way_merge("a\ny\nb", &MergeOptions::new().markers());
+
+        // then
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(
+            table.project(),
+            "a\n<<<<<<< ours\nx\n=======\ny\n>>>>>>> theirs\nb"
+        );
+    }
+
+    #[test]
+    fn should_undo_merge_at_once() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text(BASE);
+        table.insert_str("zero\n", 0);
+        let edited = table.project();
+
+        // when
+        table.three_way_merge("one\ntwo\n3\nfour\n5\n", &MergeOptions::new());
+        let merged = table.project();
+        table.undo();
+
+        // then
+        assert_eq!(merged, "zero\none\ntwo\n3\nfour\n5\n");
+        assert_eq!(table.project(), edited);
+    }
+}
@@ -0,0 +1,719 @@
+use crate::history::TextSplice;
+use crate::{Piece, PieceTable};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Step of an [`Operation`], with lengths in bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Component {
+    /// Keeps the next bytes of the text.
+    Retain(usize),
+    Insert(String),
+    /// Removes the next bytes of the text.
+    Delete(usize),
+}
+
+/// Edit of a whole text as a sequence of retained, inserted and deleted parts, which can be
+/// sent to other replicas and transformed against edits made there concurrently.
+///
+/// Operations are built with [`retain`](Self::retain), [`insert`](Self::insert) and
+/// [`delete`](Self::delete), which keep them normalized: neighbouring components of the same
+/// kind are merged and an insertion always comes before a deletion at the same place, so equal
+/// edits are equal operations.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "UncheckedOperation")
+)]
+pub struct Operation {
+    components: Vec<Component>,
+    base_len: usize,
+    target_len: usize,
+}
+
+/// Deserialized operation, which becomes an [`Operation`] once rebuilt from its components and
+/// checked against the lengths it claims.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct UncheckedOperation {
+    components: Vec<Component>,
+    base_len: usize,
+    target_len: usize,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<UncheckedOperation> for Operation {
+    type Error = OperationError;
+
+    fn try_from(unchecked: UncheckedOperation) -> Result<Self, Self::Error> {
+        // summed first, so that huge lengths fail instead of overflowing in the builder
+        let (mut base_len, mut target_len) = (Some(0usize), Some(0usize));
+        for component in &unchecked.components {
+            let (base, target) = match component {
+                Component::Retain(len) => (*len, *len),
+                Component::Insert(txt) => (0, txt.len()),
+                Component::Delete(len) => (*len, 0),
+            };
+            base_len = base_len.and_then(|sum| sum.checked_add(base));
+            target_len = target_len.and_then(|sum| sum.checked_add(target));
+        }
+        check_len(unchecked.base_len, base_len.unwrap_or(usize::MAX))?;
+        check_len(unchecked.target_len, target_len.unwrap_or(usize::MAX))?;
+        Ok(unchecked
+            .components
+            .into_iter()
+            .fold(Operation::new(), |operation, component| match component {
+                Component::Retain(len) => operation.retain(len),
+                Component::Insert(txt) => operation.insert(&txt),
+                Component::Delete(len) => operation.delete(len),
+            }))
+    }
+}
+
+impl Operation {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn retain(mut self, len: usize) -> Self {
+        if len == 0 {
+            return self;
+        }
+        self.base_len += len;
+        self.target_len += len;
+        match self.components.last_mut() {
+            Some(Component::Retain(last)) => *last += len,
+            _ => self.components.push(Component::Retain(len)),
+        }
+        self
+    }
+
+    #[must_use]
+    pub fn insert(mut self, txt: &str) -> Self {
+        if txt.is_empty() {
+            return self;
+        }
+        self.target_len += txt.len();
+        let len = self.components.len();
+        match self.components.as_mut_slice() {
+            [.., Component::Insert(last)] | [.., Component::Insert(last), Component::Delete(_)] => {
+                last.push_str(txt);
+            }
+            [.., Component::Delete(_)] => {
+                self.components
+                    .insert(len - 1, Component::Insert(txt.to_string()));
+            }
+            _ => self.components.push(Component::Insert(txt.to_string())),
+        }
+        self
+    }
+
+    #[must_use]
+    pub fn delete(mut self, len: usize) -> Self {
+        if len == 0 {
+            return self;
+        }
+        self.base_len += len;
+        match self.components.last_mut() {
+            Some(Component::Delete(last)) => *last += len,
+            _ => self.components.push(Component::Delete(len)),
+        }
+        self
+    }
+
+    #[must_use]
+    pub fn components(&self) -> &[Component] {
+        &self.components
+    }
+
+    /// Length of the text the operation applies to.
+    #[must_use]
+    pub fn base_len(&self) -> usize {
+        self.base_len
+    }
+
+    /// Length of the text after applying the operation.
+    #[must_use]
+    pub fn target_len(&self) -> usize {
+        self.target_len
+    }
+
+    /// Returns `true` if the operation doesn't change the text.
+    #[must_use]
+    pub fn is_noop(&self) -> bool {
+        self.components
+            .iter()
+            .all(|component| matches!(component, Component::Retain(_)))
+    }
+
+    /// Applies the operation to `txt`.
+    pub fn apply_to(&self, txt: &str) -> Result<String, OperationError> {
+        check_len(self.base_len, txt.len())?;
+        let mut result = String::with_capacity(self.target_len);
+        let mut pos = 0;
+        for component in &self.components {
+            match component {
+                Component::Retain(len) => {
+                    let end = pos + len;
+                    result.push_str(
+                        txt.get(pos..end)
+                            .ok_or(OperationError::NotCharBoundary(end))?,
+                    );
+                    pos = end;
+                }
+                Component::Insert(inserted) => result.push_str(inserted),
+                Component::Delete(len) => {
+                    pos += len;
+                    if !txt.is_char_boundary(pos) {
+                        return Err(OperationError::NotCharBoundary(pos));
+                    }
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Combines the operation with `next`, which applies to the text this operation produces,
+    /// into one operation with the same effect as applying both in turn.
+    pub fn compose(&self, next: &Operation) -> Result<Operation, OperationError> {
+        check_len(self.target_len, next.base_len)?;
+        let mut composed = Operation::new();
+        let mut first = self.components.iter().cloned();
+        let mut second = next.components.iter().cloned();
+        let (mut a, mut b) = (first.next(), second.next());
+        loop {
+            match (a.take(), b.take()) {
+                (None, None) => return Ok(composed),
+                (Some(Component::Delete(len)), other) => {
+                    composed = composed.delete(len);
+                    (a, b) = (first.next(), other);
+                }
+                (other, Some(Component::Insert(txt))) => {
+                    composed = composed.insert(&txt);
+                    (a, b) = (other, second.next());
+                }
+                (Some(Component::Retain(x)), Some(Component::Retain(y))) => {
+                    composed = composed.retain(x.min(y));
+                    (a, b) = split(
+                        x,
+                        y,
+                        &mut first,
+                        &mut second,
+                        Component::Retain,
+                        Component::Retain,
+                    );
+                }
+                (Some(Component::Retain(x)), Some(Component::Delete(y))) => {
+                    composed = composed.delete(x.min(y));
+                    (a, b) = split(
+                        x,
+                        y,
+                        &mut first,
+                        &mut second,
+                        Component::Retain,
+                        Component::Delete,
+                    );
+                }
+                (Some(Component::Insert(txt)), Some(Component::Retain(len))) => {
+                    let (kept, rest) = split_str(&txt, len)?;
+                    composed = composed.insert(kept);
+                    a = rest.map(|rest| Component::Insert(rest.to_string()));
+                    b = (len > txt.len()).then(|| Component::Retain(len - txt.len()));
+                    a = a.or_else(|| first.next());
+                    b = b.or_else(|| second.next());
+                }
+                (Some(Component::Insert(txt)), Some(Component::Delete(len))) => {
+                    let (_, rest) = split_str(&txt, len)?;
+                    a = rest.map(|rest| Component::Insert(rest.to_string()));
+                    b = (len > txt.len()).then(|| Component::Delete(len - txt.len()));
+                    a = a.or_else(|| first.next());
+                    b = b.or_else(|| second.next());
+                }
+                (Some(_), None) | (None, Some(_)) => {
+                    unreachable!("lengths of composed operations are checked")
+                }
+            }
+        }
+    }
+
+    /// Transforms two operations made concurrently on the same text into `(a', b')`, so that
+    /// applying `b'` after `self` gives the same text as applying `a'` after `other`.
+    ///
+    /// When both insert at the same place, the text inserted by `self` comes first.
+    pub fn transform(&self, other: &Operation) -> Result<(Operation, Operation), OperationError> {
+        check_len(self.base_len, other.base_len)?;
+        let (mut a_prime, mut b_prime) = (Operation::new(), Operation::new());
+        let mut first = self.components.iter().cloned();
+        let mut second = other.components.iter().cloned();
+        let (mut a, mut b) = (first.next(), second.next());
+        loop {
+            match (a.take(), b.take()) {
+                (None, None) => return Ok((a_prime, b_prime)),
+                (Some(Component::Insert(txt)), other) => {
+                    b_prime = b_prime.retain(txt.len());
+                    a_prime = a_prime.insert(&txt);
+                    (a, b) = (first.next(), other);
+                }
+                (other, Some(Component::Insert(txt))) => {
+                    a_prime = a_prime.retain(txt.len());
+                    b_prime = b_prime.insert(&txt);
+                    (a, b) = (other, second.next());
+                }
+                (Some(Component::Retain(x)), Some(Component::Retain(y))) => {
+                    a_prime = a_prime.retain(x.min(y));
+                    b_prime = b_prime.retain(x.min(y));
+                    (a, b) = split(
+                        x,
+                        y,
+                        &mut first,
+                        &mut second,
+                        Component::Retain,
+                        Component::Retain,
+                    );
+                }
+                (Some(Component::Delete(x)), Some(Component::Delete(y))) => {
+                    (a, b) = split(
+                        x,
+                        y,
+                        &mut first,
+                        &mut second,
+                        Component::Delete,
+                        Component::Delete,
+                    );
+                }
+                (Some(Component::Delete(x)), Some(Component::Retain(y))) => {
+                    a_prime = a_prime.delete(x.min(y));
+                    (a, b) = split(
+                        x,
+                        y,
+                        &mut first,
+                        &mut second,
+                        Component::Delete,
+                        Component::Retain,
+                    );
+                }
+                (Some(Component::Retain(x)), Some(Component::Delete(y))) => {
+                    b_prime = b_prime.delete(x.min(y));
+                    (a, b) = split(
+                        x,
+                        y,
+                        &mut first,
+                        &mut second,
+                        Component::Retain,
+                        Component::Delete,
+                    );
+                }
+                (Some(_), None) | (None, Some(_)) => {
+                    unreachable!("lengths of transformed operations are checked")
+                }
+            }
+        }
+    }
+}
+
+/// Consumes the shorter of two components of lengths `x` and `y` from both sides, returning
+/// what is left of the longer one, made with `first_rest` or `second_rest`, and the next
+/// component in place of the shorter one.
+fn split(
+    x: usize,
+    y: usize,
+    first: &mut impl Iterator<Item = Component>,
+    second: &mut impl Iterator<Item = Component>,
+    first_rest: fn(usize) -> Component,
+    second_rest: fn(usize) -> Component,
+) -> (Option<Component>, Option<Component>) {
+    match x.cmp(&y) {
+        Ordering::Less => (first.next(), Some(second_rest(y - x))),
+        Ordering::Equal => (first.next(), second.next()),
+        Ordering::Greater => (Some(first_rest(x - y)), second.next()),
+    }
+}
+
+/// Splits `txt` after `len` bytes, or returns all of it if it is shorter.
+fn split_str(txt: &str, len: usize) -> Result<(&str, Option<&str>), OperationError> {
+    if len >= txt.len() {
+        return Ok((txt, None));
+    }
+    if !txt.is_char_boundary(len) {
+        return Err(OperationError::NotCharBoundary(len));
+    }
+    let (head, tail) = txt.split_at(len);
+    Ok((head, Some(tail)))
+}
+
+fn check_len(expected: usize, found: usize) -> Result<(), OperationError> {
+    if expected == found {
+        Ok(())
+    } else {
+        Err(OperationError::LengthMismatch { expected, found })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OperationError {
+    /// The operation applies to a text of `expected` length, but the text or the other
+    /// operation has `found`.
+    LengthMismatch { expected: usize, found: usize },
+    /// The operation splits a character at the given byte index.
+    NotCharBoundary(usize),
+}
+
+impl fmt::Display for OperationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LengthMismatch { expected, found } => write!(
+                f,
+                "operation applies to text of length {expected}, not {found}"
+            ),
+            Self::NotCharBoundary(idx) => {
+                write!(f, "operation splits a character at byte {idx}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for OperationError {}
+
+impl PieceTable<'_> {
+    /// Returns the operation made by the last edit still in the undo history, which turns the
+    /// text from before the edit into the current one.
+    #[must_use]
+    pub fn last_operation(&self) -> Option<Operation> {
+        let (history, _) = self.settled_history();
+        let edit = history.last()?;
+        let mut pieces = self.pieces.clone();
+        for change in edit.changes().iter().rev() {
+            change.revert(&mut pieces);
+        }
+        let mut operation = Operation::new().retain(pieces.iter().map(Piece::len).sum());
+        for change in edit.changes() {
+            let step =
+                self.change_operation(&pieces, change.at(), change.removed(), change.inserted());
+            // changes of an edit follow each other, so they always compose
+            operation = operation.compose(&step).ok()?;
+            change.apply(&mut pieces);
+        }
+        Some(operation)
+    }
+
+    /// Returns the operation replacing `removed` pieces at index `at` of `pieces` with `inserted`.
+    pub(crate) fn change_operation(
+        &self,
+        pieces: &[Piece],
+        at: usize,
+        removed: &[Piece],
+        inserted: &[Piece],
+    ) -> Operation {
+        let len: usize = pieces.iter().map(Piece::len).sum();
+        let splice = TextSplice::new(pieces, at, removed, inserted);
+        let inserted: String = inserted
+            .iter()
+            .map(|piece| self.piece_text(piece))
+            .collect();
+        Operation::new()
+            .retain(splice.at)
+            .insert(&inserted[splice.inserted])
+            .delete(splice.removed)
+            .retain(len - splice.at - splice.removed)
+    }
+
+    /// Applies an operation, e.g. one received from another replica, as a single step in the
+    /// undo history.
+    pub fn apply(&mut self, operation: &Operation) -> Result<(), OperationError> {
+        check_len(operation.base_len, self.len())?;
+        let mut pos = 0;
+        for component in &operation.components {
+            if let Component::Retain(len) | Component::Delete(len) = component {
+                pos += len;
+                if !self.is_char_boundary(pos) {
+                    return Err(OperationError::NotCharBoundary(pos));
+                }
+            }
+        }
+        self.transaction(|table| {
+            let mut pos = 0;
+            for component in &operation.components {
+                match component {
+                    Component::Retain(len) => pos += len,
+                    Component::Insert(txt) => {
+                        table.insert_str(txt, pos);
+                        pos += txt.len();
+                    }
+                    Component::Delete(len) => {
+                        table.remove(pos..pos + len);
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    /// Text with multi-byte characters and line breaks.
+    fn text() -> impl Strategy<Value = String> {
+        "[abżź\n]{0,12}"
+    }
+
+    /// Operation on `base` built from random steps, each retaining, deleting or inserting.
+    fn operation_on(base: String) -> impl Strategy<Value = (String, Operation)> {
+        prop::collection::vec((0..3u8, 1..4usize, text()), 0..6).prop_map(move |steps| {
+            let mut operation = Operation::new();
+            let mut chars = base.chars();
+            for (kind, count, inserted) in steps {
+                match kind {
+                    0 => {
+                        operation =
+                            operation.retain(chars.by_ref().take(count).map(char::len_utf8).sum());
+                    }
+                    1 => {
+                        operation =
+                            operation.delete(chars.by_ref().take(count).map(char::len_utf8).sum());
+                    }
+                    _ => operation = operation.insert(&inserted),
+                }
+            }
+            let operation = operation.retain(chars.as_str().len());
+            (base.clone(), operation)
+        })
+    }
+
+    fn text_with_operation() -> impl Strategy<Value = (String, Operation)> {
+        text().prop_flat_map(operation_on)
+    }
+
+    fn text_with_concurrent_operations() -> impl Strategy<Value = (String, Operation, Operation)> {
+        text().prop_flat_map(|base| {
+            (operation_on(base.clone()), operation_on(base))
+                .prop_map(|((base, a), (_, b))| (base, a, b))
+        })
+    }
+
+    fn text_with_consecutive_operations() -> impl Strategy<Value = (String, Operation, Operation)> {
+        text_with_operation().prop_flat_map(|(base, a)| {
+            let next = a.apply_to(&base).unwrap();
+            operation_on(next).prop_map(move |(_, b)| (base.clone(), a.clone(), b))
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn applying_to_table_matches_applying_to_text((base, operation) in text_with_operation()) {
+            // given
+            let mut table = PieceTable::from_text(&base);
+
+            // when
+            table.apply(&operation).unwrap();
+
+            // then
+            prop_assert_eq!(table.project(), operation.apply_to(&base).unwrap());
+        }
+
+        #[test]
+        fn composed_equals_both_in_turn((base, a, b) in text_with_consecutive_operations()) {
+            // given
+            let in_turn = b.apply_to(&a.apply_to(&base).unwrap()).unwrap();
+
+            // when
+            let composed = a.compose(&b).unwrap();
+
+            // then
+            prop_assert_eq!(composed.apply_to(&base).unwrap(), in_turn);
+        }
+
+        #[test]
+        fn transformed_operations_converge((base, a, b) in text_with_concurrent_operations()) {
+            // given
+            let after_a = a.apply_to(&base).unwrap();
+            let after_b = b.apply_to(&base).unwrap();
+
+            // when
+            let (a_prime, b_prime) = a.transform(&b).unwrap();
+
+            // then
+            prop_assert_eq!(
+                b_prime.apply_to(&after_a).unwrap(),
+                a_prime.apply_to(&after_b).unwrap()
+            );
+        }
+
+        #[test]
+        fn last_operation_replays_edit((base, a, b) in text_with_consecutive_operations()) {
+            prop_assume!(!b.is_noop());
+            // given
+            let mut table = PieceTable::from_text(&base);
+            table.apply(&a).unwrap();
+            let before = table.project();
+
+            // when
+            table.apply(&b).unwrap();
+
+            // then
+            let last = table.last_operation().unwrap();
+            prop_assert_eq!(last.apply_to(&before).unwrap(), table.project());
+        }
+    }
+
+    #[test]
+    fn should_normalize_components() {
+        init_logger();
+        // given
+        let operation = Operation::new()
+            .retain(2)
+            .retain(1)
+            .delete(1)
+            .insert("a")
+            .delete(2)
+            .insert("b");
+
+        // when
+        let components = operation.components();
+
+        // then
+        assert_eq!(
+            components,
+            [
+                Component::Retain(3),
+                Component::Insert("ab".into()),
+                Component::Delete(3)
+            ]
+        );
+        assert_eq!((operation.base_len(), operation.target_len()), (6, 5));
+    }
+
+    #[test]
+    fn should_give_inserts_of_first_operation_priority() {
+        init_logger();
+        // given
+        let a = Operation::new().retain(1).insert("a").retain(1);
+        let b = Operation::new().retain(1).insert("b").retain(1);
+
+        // when
+        let (a_prime, b_prime) = a.transform(&b).unwrap();
+
+        // then
+        assert_eq!(b_prime.apply_to("xaz").unwrap(), "xabz");
+        assert_eq!(a_prime.apply_to("xbz").unwrap(), "xabz");
+    }
+
+    #[test]
+    fn should_produce_operation_of_last_edit() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("hello world");
+        table.replace_all("o", "0", None);
+
+        // when
+        let operation = table.last_operation();
+
+        // then
+        assert_eq!(
+            operation,
+            Some(
+                Operation::new()
+                    .retain(4)
+                    .insert("0")
+                    .delete(1)
+                    .retain(2)
+                    .insert("0")
+                    .delete(1)
+                    .retain(3)
+            )
+        );
+    }
+
+    #[test]
+    fn should_reject_operation_for_other_text() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("zażółć");
+        let too_short = Operation::new().retain(3);
+        let splitting = Operation::new().retain(3).delete(1).retain(6);
+
+        // when
+        let length = table.apply(&too_short);
+        let boundary = table.apply(&splitting);
+
+        // then
+        assert_eq!(
+            length,
+            Err(OperationError::LengthMismatch {
+                expected: 3,
+                found: 10
+            })
+        );
+        assert_eq!(boundary, Err(OperationError::NotCharBoundary(3)));
+        assert_eq!(table.project(), "zażółć");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn should_survive_serialization() {
+        init_logger();
+        // given
+        let operation = Operation::new().retain(1).insert("a").delete(2);
+
+        // when
+        let json = serde_json::to_string(&operation).unwrap();
+
+        // then
+        assert_eq!(serde_json::from_str::<Operation>(&json).unwrap(), operation);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn should_refuse_to_deserialize_inconsistent_operation() {
+        use serde_json::json;
+        init_logger();
+        // given
+        let inconsistent = json!({
+            "components": [{"Retain": 5}],
+            "base_len": 2,
+            "target_len": 2,
+        });
+        let overflowing = json!({
+            "components": [{"Retain": usize::MAX}, {"Retain": 1}],
+            "base_len": usize::MAX,
+            "target_len": 0,
+        });
+
+        // when
+        let inconsistent = serde_json::from_value::<Operation>(inconsistent);
+        let overflowing = serde_json::from_value::<Operation>(overflowing);
+
+        // then
+        assert!(inconsistent.is_err());
+        assert!(overflowing.is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn should_normalize_deserialized_operation() {
+        use serde_json::json;
+        init_logger();
+        // given
+        let value = json!({
+            "components": [{"Delete": 1}, {"Insert": "a"}, {"Retain": 0}],
+            "base_len": 1,
+            "target_len": 1,
+        });
+
+        // when
+        let operation = serde_json::from_value::<Operation>(value).unwrap();
+
+        // then
+        assert_eq!(operation, Operation::new().insert("a").delete(1));
+    }
+}
@@ -0,0 +1,850 @@
+use crate::{Difference, PieceTable};
+use std::fmt::{self, Write};
+use std::ops::Range;
+use std::str::FromStr;
+
+const NO_NEWLINE: &str = "\\ No newline at end of file";
+
+/// Line of a hunk, with its line break unless it is the last line of a text not ending with one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Line {
+    Context(String),
+    Removed(String),
+    Added(String),
+}
+
+impl Line {
+    fn text(&self) -> &str {
+        match self {
+            Self::Context(txt) | Self::Removed(txt) | Self::Added(txt) => txt,
+        }
+    }
+
+    fn text_mut(&mut self) -> &mut String {
+        match self {
+            Self::Context(txt) | Self::Removed(txt) | Self::Added(txt) => txt,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Hunk {
+    /// Index of the first line the hunk changes in the old text, counted from 0.
+    old_start: usize,
+    lines: Vec<Line>,
+}
+
+impl Hunk {
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter(|line| !matches!(line, Line::Added(_)))
+            .map(Line::text)
+            .collect()
+    }
+
+    fn leading_context(&self) -> usize {
+        self.lines
+            .iter()
+            .take_while(|line| matches!(line, Line::Context(_)))
+            .count()
+    }
+
+    fn trailing_context(&self) -> usize {
+        self.lines
+            .iter()
+            .rev()
+            .take_while(|line| matches!(line, Line::Context(_)))
+            .count()
+    }
+}
+
+/// A parsed unified diff of a single file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch {
+    hunks: Vec<Hunk>,
+}
+
+impl Patch {
+    /// Parses a unified diff like the ones made by `diff -u`, `git diff` or
+    /// [`PieceTable::unified_diff`]. Anything before the `---` line is ignored.
+    pub fn parse(txt: &str) -> Result<Self, PatchError> {
+        // lines keep their own endings, so that patches of CRLF text apply
+        let lines: Vec<&str> = txt.split_inclusive('\n').collect();
+        let Some(header) = lines
+            .windows(2)
+            .position(|pair| pair[0].starts_with("--- ") && pair[1].starts_with("+++ "))
+        else {
+            return Err(PatchError::MissingHeader);
+        };
+        let mut hunks = Vec::new();
+        let mut idx = header + 2;
+        while idx < lines.len() {
+            let (hunk, next) = parse_hunk(&lines, idx)?;
+            hunks.push(hunk);
+            idx = next;
+        }
+        Ok(Self { hunks })
+    }
+
+    /// Returns the number of hunks.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.hunks.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.hunks.is_empty()
+    }
+}
+
+impl FromStr for Patch {
+    type Err = PatchError;
+
+    fn from_str(txt: &str) -> Result<Self, Self::Err> {
+        Self::parse(txt)
+    }
+}
+
+/// Parses the hunk whose header is at `lines[idx]`, returning it with the index of the line
+/// after it.
+fn parse_hunk(lines: &[&str], idx: usize) -> Result<(Hunk, usize), PatchError> {
+    let header = lines[idx];
+    let (old, new) =
+        parse_hunk_header(header).ok_or(PatchError::InvalidHunkHeader { line: idx + 1 })?;
+    let mut hunk = Hunk {
+        old_start: if old.1 == 0 { old.0 } else { old.0 - 1 },
+        lines: Vec::new(),
+    };
+    let (mut old_left, mut new_left) = (old.1, new.1);
+    let mut idx = idx + 1;
+    while old_left > 0
+        || new_left > 0
+        || lines
+            .get(idx)
+            .is_some_and(|line| line.starts_with(NO_NEWLINE))
+    {
+        let Some(&line) = lines.get(idx) else {
+            return Err(PatchError::HunkTooShort { line: idx + 1 });
+        };
+        // some tools strip the space from empty context lines
+        let (kind, content) = if line.trim_end_matches(['\r', '\n']).is_empty() {
+            (" ", line)
+        } else {
+            line.split_at_checked(1).unwrap_or(("", line))
+        };
+        let mut txt = content.to_string();
+        if !txt.ends_with('\n') {
+            txt.push('\n');
+        }
+        let line = match kind {
+            " " if old_left > 0 && new_left > 0 => {
+                old_left -= 1;
+                new_left -= 1;
+                Line::Context(txt)
+            }
+            "-" if old_left > 0 => {
+                old_left -= 1;
+                Line::Removed(txt)
+            }
+            "+" if new_left > 0 => {
+                new_left -= 1;
+                Line::Added(txt)
+            }
+            "\\" => match hunk.lines.last_mut() {
+                Some(last) => {
+                    last.text_mut().pop();
+                    idx += 1;
+                    continue;
+                }
+                None => return Err(PatchError::UnexpectedLine { line: idx + 1 }),
+            },
+            _ => return Err(PatchError::UnexpectedLine { line: idx + 1 }),
+        };
+        hunk.lines.push(line);
+        idx += 1;
+    }
+    Ok((hunk, idx))
+}
+
+/// Parses `@@ -start,len +start,len @@`, where a missing length means a single line.
+fn parse_hunk_header(header: &str) -> Option<((usize, usize), (usize, usize))> {
+    let ranges = header.strip_prefix("@@ -")?;
+    let (ranges, _) = ranges.split_once(" @@")?;
+    let (old, new) = ranges.split_once(" +")?;
+    let parse = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (old, new) = (parse(old)?, parse(new)?);
+    (old.1 == 0 || old.0 > 0).then_some((old, new))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchError {
+    /// There are no `---` and `+++` lines.
+    MissingHeader,
+    InvalidHunkHeader {
+        line: usize,
+    },
+    /// The patch ends before the hunk has as many lines as its header says.
+    HunkTooShort {
+        line: usize,
+    },
+    UnexpectedLine {
+        line: usize,
+    },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingHeader => write!(f, "patch has no `---` and `+++` header"),
+            Self::InvalidHunkHeader { line } => write!(f, "invalid hunk header at line {line}"),
+            Self::HunkTooShort { line } => {
+                write!(f, "patch ends in the middle of a hunk at line {line}")
+            }
+            Self::UnexpectedLine { line } => write!(f, "unexpected line {line} in patch"),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+/// Where a hunk got applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppliedHunk {
+    /// How many lines away from the line in the hunk header it was applied.
+    pub offset: isize,
+    /// How many context lines at each end of the hunk were ignored.
+    pub fuzz: usize,
+}
+
+/// Why a hunk couldn't be applied, described at the place it was expected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HunkRejected {
+    /// Line from the hunk header, adjusted by the offset of the previous hunks, counted from 1.
+    pub line: usize,
+    pub reason: RejectReason,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RejectReason {
+    /// The text differs from the hunk at `line`.
+    Mismatch {
+        line: usize,
+        expected: String,
+        found: String,
+    },
+    /// The text ends before the hunk.
+    PastEnd,
+    /// The hunk only matches text changed by an earlier hunk.
+    Overlap,
+}
+
+impl fmt::Display for HunkRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "hunk at line {} rejected: ", self.line)?;
+        match &self.reason {
+            RejectReason::Mismatch {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {line} is {:?} instead of {:?}",
+                found.trim_end_matches('\n'),
+                expected.trim_end_matches('\n')
+            ),
+            RejectReason::PastEnd => write!(f, "text is too short"),
+            RejectReason::Overlap => write!(f, "text was already changed by an earlier hunk"),
+        }
+    }
+}
+
+impl std::error::Error for HunkRejected {}
+
+impl PieceTable<'_> {
+    /// Returns the changes made to the original text as a unified diff of the file at `path`,
+    /// with `context` unchanged lines around each change. Returns an empty string when nothing
+    /// changed.
+    #[must_use]
+    pub fn unified_diff(&self, path: &str, context: usize) -> String {
+        let original = PieceTable::from_text(self.original_buffer());
+        let differences = original.diff_lines(self);
+        if differences.is_empty() {
+            return String::new();
+        }
+        let current = self.project();
+        let old = lines(self.original_buffer());
+        let new = lines(&current);
+        let changes: Vec<_> = differences
+            .iter()
+            .map(|Difference { old: a, new: b }| (old.lines_of(a), new.lines_of(b)))
+            .collect();
+
+        let mut diff = format!("--- a/{path}\n+++ b/{path}\n");
+        let mut start = 0;
+        while start < changes.len() {
+            // changes closer than twice the context share a hunk
+            let mut end = start + 1;
+            while end < changes.len()
+                && changes[end].0.start - changes[end - 1].0.end <= 2 * context
+            {
+                end += 1;
+            }
+            let group = &changes[start..end];
+            let (first, last) = (&group[0], &group[group.len() - 1]);
+            let before = first.0.start.min(context);
+            let after = (old.len() - last.0.end).min(context);
+            let old_range = first.0.start - before..last.0.end + after;
+            let new_range = first.1.start - before..last.1.end + after;
+            let _ = writeln!(
+                diff,
+                "@@ -{} +{} @@",
+                header_range(&old_range),
+                header_range(&new_range)
+            );
+            let mut line = old_range.start;
+            for (removed, added) in group {
+                push_lines(&mut diff, ' ', old.get(line..removed.start));
+                push_lines(&mut diff, '-', old.get(removed.clone()));
+                push_lines(&mut diff, '+', new.get(added.clone()));
+                line = removed.end;
+            }
+            push_lines(&mut diff, ' ', old.get(line..old_range.end));
+            start = end;
+        }
+        diff
+    }
+
+    /// Applies `patch`, looking for each hunk further away from the line in its header when the
+    /// text moved, and ignoring up to `fuzz` context lines at both ends of hunks that don't
+    /// match otherwise.
+    ///
+    /// Returns the outcome of every hunk. The hunks that fit are applied even if others are
+    /// rejected, all of them as a single step in the undo history.
+    pub fn apply_patch(
+        &mut self,
+        patch: &Patch,
+        fuzz: usize,
+    ) -> Vec<Result<AppliedHunk, HunkRejected>> {
+        let current = self.project();
+        let lines = lines(&current);
+        let mut outcomes = Vec::new();
+        let mut edits = Vec::new();
+        let mut offset = 0;
+        let mut free_from = 0;
+        for hunk in &patch.hunks {
+            let expected = hunk.old_start.saturating_add_signed(offset);
+            let Some((end, applied)) = find_hunk(&lines, hunk, expected, free_from, fuzz) else {
+                outcomes.push(Err(HunkRejected {
+                    line: expected + 1,
+                    reason: reject_reason(&lines, hunk, expected, free_from),
+                }));
+                continue;
+            };
+            offset += applied.offset;
+            edits.extend(hunk_edits(&lines, hunk, end, applied.fuzz));
+            free_from = end;
+            outcomes.push(Ok(applied));
+        }
+        self.transaction(|table| {
+            for (range, txt) in edits.into_iter().rev() {
+                table.remove(range.clone());
+                table.insert_str(&txt, range.start);
+            }
+        });
+        outcomes
+    }
+}
+
+/// Lines of a text with their offsets.
+struct Lines<'t> {
+    lines: Vec<&'t str>,
+    /// Offsets of the lines followed by the length of the text.
+    starts: Vec<usize>,
+}
+
+impl<'t> Lines<'t> {
+    fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    fn get(&self, range: Range<usize>) -> &[&'t str] {
+        &self.lines[range]
+    }
+
+    /// Turns a byte range of whole lines into a range of line indices.
+    fn lines_of(&self, range: &Range<usize>) -> Range<usize> {
+        let line = |offset| self.starts.partition_point(|&start| start < offset);
+        line(range.start)..line(range.end)
+    }
+
+    fn bytes_of(&self, range: Range<usize>) -> Range<usize> {
+        self.starts[range.start]..self.starts[range.end]
+    }
+}
+
+fn lines(txt: &str) -> Lines<'_> {
+    let lines: Vec<_> = txt.split_inclusive('\n').collect();
+    let mut starts = vec![0];
+    starts.extend(lines.iter().scan(0, |end, line| {
+        *end += line.len();
+        Some(*end)
+    }));
+    Lines { lines, starts }
+}
+
+fn header_range(range: &Range<usize>) -> String {
+    match range.len() {
+        0 => format!("{},0", range.start),
+        1 => format!("{}", range.start + 1),
+        len => format!("{},{len}", range.start + 1),
+    }
+}
+
+fn push_lines(diff: &mut String, kind: char, lines: &[&str]) {
+    for line in lines {
+        diff.push(kind);
+        diff.push_str(line);
+        if !line.ends_with('\n') {
+            diff.push('\n');
+            diff.push_str(NO_NEWLINE);
+            diff.push('\n');
+        }
+    }
+}
+
+/// Finds the line where the hunk, with the least fuzz needed, matches closest to `expected`.
+/// Returns the line after the matched text, where the next hunk can start at the earliest.
+fn find_hunk(
+    lines: &Lines,
+    hunk: &Hunk,
+    expected: usize,
+    free_from: usize,
+    fuzz: usize,
+) -> Option<(usize, AppliedHunk)> {
+    let old = hunk.old_lines();
+    let max_fuzz = fuzz.min(hunk.leading_context().max(hunk.trailing_context()));
+    (0..=max_fuzz).find_map(|fuzz| {
+        let skip_start = fuzz.min(hunk.leading_context());
+        let skip_end = fuzz.min(hunk.trailing_context());
+        let needle = &old[skip_start..old.len() - skip_end];
+        let expected = expected + skip_start;
+        let last = lines.len().checked_sub(needle.len())?;
+        let matches = |at: usize| lines.get(at..at + needle.len()) == needle;
+        let at = (0..=last.max(expected))
+            .flat_map(|distance| {
+                [
+                    expected.checked_sub(distance),
+                    expected.checked_add(distance),
+                ]
+                .into_iter()
+                .flatten()
+                .take(if distance == 0 { 1 } else { 2 })
+            })
+            .filter(|&at| free_from <= at && at <= last)
+            .find(|&at| matches(at))?;
+        let applied = AppliedHunk {
+            offset: at.wrapping_sub(expected).cast_signed(),
+            fuzz,
+        };
+        Some((at + needle.len(), applied))
+    })
+}
+
+fn reject_reason(lines: &Lines, hunk: &Hunk, expected: usize, free_from: usize) -> RejectReason {
+    let mismatch = hunk
+        .old_lines()
+        .into_iter()
+        .enumerate()
+        .find_map(|(idx, expected_line)| {
+            let line = expected + idx;
+            match lines.lines.get(line) {
+                Some(found) if *found == expected_line => None,
+                Some(found) => Some(RejectReason::Mismatch {
+                    line: line + 1,
+                    expected: expected_line.to_string(),
+                    found: (*found).to_string(),
+                }),
+                None => Some(RejectReason::PastEnd),
+            }
+        });
+    match mismatch {
+        Some(reason) => reason,
+        None if expected < free_from => RejectReason::Overlap,
+        None => RejectReason::PastEnd,
+    }
+}
+
+/// Byte ranges to replace and their replacements to apply a hunk which matched the text up to
+/// line `end`, without the context skipped because of fuzz.
+fn hunk_edits(lines: &Lines, hunk: &Hunk, end: usize, fuzz: usize) -> Vec<(Range<usize>, String)> {
+    let skip_start = fuzz.min(hunk.leading_context());
+    let skip_end = fuzz.min(hunk.trailing_context());
+    let mut edits = Vec::new();
+    let mut line = end + skip_start + skip_end - hunk.old_lines().len();
+    let mut removed = line..line;
+    let mut added = String::new();
+    for hunk_line in &hunk.lines[skip_start..hunk.lines.len() - skip_end] {
+        match hunk_line {
+            Line::Context(_) => {
+                if !removed.is_empty() || !added.is_empty() {
+                    edits.push((lines.bytes_of(removed), std::mem::take(&mut added)));
+                }
+                line += 1;
+                removed = line..line;
+            }
+            Line::Removed(_) => {
+                line += 1;
+                removed.end = line;
+            }
+            Line::Added(txt) => added.push_str(txt),
+        }
+    }
+    if !removed.is_empty() || !added.is_empty() {
+        edits.push((lines.bytes_of(removed), added));
+    }
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    const TXT: &str = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+
+    mod unified_diff {
+        use super::*;
+
+        #[test]
+        fn should_be_empty_without_changes() {
+            init_logger();
+            // given
+            let table = PieceTable::from_text(TXT);
+
+            // when
+            let diff = table.unified_diff("numbers.txt", 3);
+
+            // then
+            assert_eq!(diff, "");
+        }
+
+        #[test]
+        fn should_write_hunks_with_context() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text(TXT);
+            table.remove(2..4);
+            table.insert_str("two and a half\n", 2);
+            table.insert_str("11\n", table.len());
+
+            // when
+            let diff = table.unified_diff("numbers.txt", 1);
+
+            // then
+            assert_eq!(
+                diff,
+                "--- a/numbers.txt\n+++ b/numbers.txt\n\
+                 @@ -1,3 +1,3 @@\n 1\n-2\n+two and a half\n 3\n\
+                 @@ -10 +10,2 @@\n 10\n+11\n"
+            );
+        }
+
+        #[test]
+        fn should_merge_close_changes_into_one_hunk() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text(TXT);
+            table.remove(0..2);
+            table.remove(6..8);
+
+            // when
+            let diff = table.unified_diff("numbers.txt", 2);
+
+            // then
+            assert_eq!(
+                diff,
+                "--- a/numbers.txt\n+++ b/numbers.txt\n\
+                 @@ -1,7 +1,5 @@\n-1\n 2\n 3\n 4\n-5\n 6\n 7\n"
+            );
+        }
+
+        #[test]
+        fn should_mark_missing_newline_at_end() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("a\nb");
+            table.insert_char('c', 3);
+
+            // when
+            let diff = table.unified_diff("f", 3);
+
+            // then
+            assert_eq!(
+                diff,
+                "--- a/f\n+++ b/f\n@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n\
+                 +bc\n\\ No newline at end of file\n"
+            );
+        }
+    }
+
+    mod parse {
+        use super::*;
+
+        #[test]
+        fn should_parse_git_diff() {
+            init_logger();
+            // given
+            let diff = "diff --git a/f b/f\nindex 1..2 100644\n--- a/f\n+++ b/f\n\
+                        @@ -1,2 +1,2 @@ fn main\n-a\n+b\n c\n@@ -0,0 +5 @@\n+d\n";
+
+            // when
+            let patch = Patch::parse(diff);
+
+            // then
+            let patch = patch.unwrap();
+            assert_eq!(patch.len(), 2);
+            assert_eq!(patch.hunks[1].old_start, 0);
+        }
+
+        #[test]
+        fn should_reject_text_without_header() {
+            init_logger();
+            // given
+            let diff = "@@ -1 +1 @@\n-a\n+b\n";
+
+            // when
+            let patch = diff.parse::<Patch>();
+
+            // then
+            assert_eq!(patch, Err(PatchError::MissingHeader));
+        }
+
+        #[test]
+        fn should_reject_truncated_hunk() {
+            init_logger();
+            // given
+            let diff = "--- a/f\n+++ b/f\n@@ -1,3 +1,3 @@\n a\n-b\n";
+
+            // when
+            let patch = Patch::parse(diff);
+
+            // then
+            assert_eq!(patch, Err(PatchError::HunkTooShort { line: 6 }));
+        }
+
+        #[test]
+        fn should_reject_invalid_lines() {
+            init_logger();
+            // given
+            let diff = "--- a/f\n+++ b/f\n@@ -1 +1 @@\n*a\n";
+
+            // when
+            let patch = Patch::parse(diff);
+
+            // then
+            assert_eq!(patch, Err(PatchError::UnexpectedLine { line: 4 }));
+        }
+
+        #[test]
+        fn should_reject_invalid_hunk_header() {
+            init_logger();
+            // given
+            let diff = "--- a/f\n+++ b/f\n@@ -x +1 @@\n";
+
+            // when
+            let patch = Patch::parse(diff);
+
+            // then
+            assert_eq!(patch, Err(PatchError::InvalidHunkHeader { line: 3 }));
+        }
+    }
+
+    mod apply_patch {
+        use super::*;
+
+        fn patch_of(table: &PieceTable, context: usize) -> Patch {
+            Patch::parse(&table.unified_diff("f", context)).unwrap()
+        }
+
+        #[test]
+        fn should_apply_exported_diff() {
+            init_logger();
+            // given
+            let mut edited = PieceTable::from_text(TXT);
+            edited.remove(0..4);
+            edited.insert_str("five\n", 4);
+            edited.remove(9..11);
+            edited.insert_str("end", edited.len());
+            let patch = patch_of(&edited, 3);
+            let mut table = PieceTable::from_text(TXT);
+
+            // when
+            let outcomes = table.apply_patch(&patch, 0);
+
+            // then
+            assert!(outcomes.iter().all(Result::is_ok));
+            assert_eq!(table.project(), edited.project());
+        }
+
+        #[test]
+        fn should_keep_missing_newline_at_end() {
+            init_logger();
+            // given
+            let mut edited = PieceTable::from_text("a\nb");
+            edited.insert_str("c\nd", 3);
+            let patch = patch_of(&edited, 3);
+            let mut table = PieceTable::from_text("a\nb");
+
+            // when
+            let outcomes = table.apply_patch(&patch, 0);
+
+            // then
+            assert!(outcomes.iter().all(Result::is_ok));
+            assert_eq!(table.project(), "a\nbc\nd");
+        }
+
+        #[test]
+        fn should_apply_diff_of_crlf_text() {
+            init_logger();
+            // given
+            let mut edited = PieceTable::from_text("a\r\nb\r\nc\r\n");
+            edited.remove(3..6);
+            edited.insert_str("d\r\n", edited.len());
+            let patch = patch_of(&edited, 3);
+            let mut table = PieceTable::from_text("a\r\nb\r\nc\r\n");
+
+            // when
+            let outcomes = table.apply_patch(&patch, 0);
+
+            // then
+            assert!(outcomes.iter().all(Result::is_ok));
+            assert_eq!(table.project(), "a\r\nc\r\nd\r\n");
+        }
+
+        #[test]
+        fn should_apply_moved_hunks_with_offset() {
+            init_logger();
+            // given
+            let mut edited = PieceTable::from_text(TXT);
+            edited.remove(8..10);
+            let patch = patch_of(&edited, 2);
+            let mut table = PieceTable::from_text(TXT);
+            table.insert_str("a\nb\n", 0);
+
+            // when
+            let outcomes = table.apply_patch(&patch, 0);
+
+            // then
+            assert_eq!(outcomes, vec![Ok(AppliedHunk { offset: 2, fuzz: 0 })]);
+            assert_eq!(table.project(), "a\nb\n1\n2\n3\n4\n6\n7\n8\n9\n10\n");
+        }
+
+        #[test]
+        fn should_ignore_changed_context_with_fuzz() {
+            init_logger();
+            // given
+            let mut edited = PieceTable::from_text(TXT);
+            edited.remove(8..10);
+            let patch = patch_of(&edited, 2);
+            let mut table = PieceTable::from_text(TXT);
+            table.insert_char('!', 5);
+
+            // when
+            let strict = table.apply_patch(&patch, 0);
+            let fuzzy = table.apply_patch(&patch, 1);
+
+            // then
+            assert_eq!(
+                strict,
+                vec![Err(HunkRejected {
+                    line: 3,
+                    reason: RejectReason::Mismatch {
+                        line: 3,
+                        expected: "3\n".to_string(),
+                        found: "3!\n".to_string(),
+                    }
+                })]
+            );
+            assert_eq!(fuzzy, vec![Ok(AppliedHunk { offset: 0, fuzz: 1 })]);
+            assert_eq!(table.project(), "1\n2\n3!\n4\n6\n7\n8\n9\n10\n");
+        }
+
+        #[test]
+        fn should_apply_other_hunks_when_one_is_rejected() {
+            init_logger();
+            // given
+            let mut edited = PieceTable::from_text(TXT);
+            edited.remove(0..2);
+            edited.insert_str("ten\n", 16);
+            edited.remove(20..23);
+            let patch = patch_of(&edited, 1);
+            let mut table = PieceTable::from_text(TXT);
+            table.remove(0..2);
+
+            // when
+            let outcomes = table.apply_patch(&patch, 0);
+
+            // then
+            assert_eq!(outcomes.len(), 2);
+            assert!(outcomes[0].is_err());
+            assert_eq!(
+                outcomes[1],
+                Ok(AppliedHunk {
+                    offset: -1,
+                    fuzz: 0
+                })
+            );
+            assert_eq!(table.project(), "2\n3\n4\n5\n6\n7\n8\n9\nten\n");
+        }
+
+        #[test]
+        fn should_report_text_too_short() {
+            init_logger();
+            // given
+            let patch = Patch::parse("--- a/f\n+++ b/f\n@@ -2,2 +2 @@\n b\n-c\n").unwrap();
+            let mut table = PieceTable::from_text("a\nb\n");
+
+            // when
+            let outcomes = table.apply_patch(&patch, 0);
+
+            // then
+            assert_eq!(
+                outcomes,
+                vec![Err(HunkRejected {
+                    line: 2,
+                    reason: RejectReason::PastEnd
+                })]
+            );
+        }
+
+        #[test]
+        fn should_undo_whole_patch_at_once() {
+            init_logger();
+            // given
+            let mut edited = PieceTable::from_text(TXT);
+            edited.remove(0..2);
+            edited.remove(14..16);
+            let patch = patch_of(&edited, 0);
+            let mut table = PieceTable::from_text(TXT);
+
+            // when
+            table.apply_patch(&patch, 0);
+            let patched = table.project();
+            table.undo();
+
+            // then
+            assert_eq!(patched, edited.project());
+            assert_eq!(table.project(), TXT);
+        }
+    }
+}
@@ -0,0 +1,399 @@
+//! Moving the undo and redo history past changes of other replicas, so that the local edits
+//! can still be undone and redone after the text changed under them.
+//!
+//! Every change of the history is turned into an operation over the whole text which undoes
+//! (or redoes) it, with the inserted text given as pieces. Each remote change is transformed
+//! against them the way operations of two replicas are, and the transformed operations are
+//! turned back into changes of the pieces.
+//!
+//! That is put off until the history is needed, e.g. on undo, so that applying a remote change
+//! doesn't take time in proportion to the whole history. Until then the operations keep following
+//! the remote changes, and local edits are added to them.
+
+use crate::history::{Change, Edit, TextSplice};
+use crate::{slice_pieces, Piece, PieceTable};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone)]
+enum Step {
+    Retain(usize),
+    Insert(Vec<Piece>),
+    Delete(usize),
+}
+
+/// Change of a whole text, like [`crate::Operation`] but inserting pieces instead of text.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PieceOperation {
+    steps: Vec<Step>,
+}
+
+impl PieceOperation {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn retain(mut self, len: usize) -> Self {
+        if len > 0 {
+            match self.steps.last_mut() {
+                Some(Step::Retain(last)) => *last += len,
+                _ => self.steps.push(Step::Retain(len)),
+            }
+        }
+        self
+    }
+
+    pub(crate) fn insert(mut self, pieces: Vec<Piece>) -> Self {
+        let pieces: Vec<_> = pieces.into_iter().filter(|piece| piece.len() > 0).collect();
+        if !pieces.is_empty() {
+            match self.steps.last_mut() {
+                Some(Step::Insert(last)) => last.extend(pieces),
+                _ => self.steps.push(Step::Insert(pieces)),
+            }
+        }
+        self
+    }
+
+    pub(crate) fn delete(mut self, len: usize) -> Self {
+        if len > 0 {
+            match self.steps.last_mut() {
+                Some(Step::Delete(last)) => *last += len,
+                _ => self.steps.push(Step::Delete(len)),
+            }
+        }
+        self
+    }
+
+    /// Transforms two operations made on the same text into `(a', b')`, where `a'` applies
+    /// after `other` and `b'` after `self`. Text inserted by `self` at the same place goes first.
+    fn transform(&self, other: &PieceOperation) -> (PieceOperation, PieceOperation) {
+        let (mut a_prime, mut b_prime) = (PieceOperation::new(), PieceOperation::new());
+        let mut first = self.steps.iter().cloned();
+        let mut second = other.steps.iter().cloned();
+        let (mut a, mut b) = (first.next(), second.next());
+        loop {
+            match (a.take(), b.take()) {
+                (None, None) => return (a_prime, b_prime),
+                (Some(Step::Insert(pieces)), other) => {
+                    b_prime = b_prime.retain(pieces_len(&pieces));
+                    a_prime = a_prime.insert(pieces);
+                    (a, b) = (first.next(), other);
+                }
+                (other, Some(Step::Insert(pieces))) => {
+                    a_prime = a_prime.retain(pieces_len(&pieces));
+                    b_prime = b_prime.insert(pieces);
+                    (a, b) = (other, second.next());
+                }
+                (Some(Step::Retain(x)), Some(Step::Retain(y))) => {
+                    a_prime = a_prime.retain(x.min(y));
+                    b_prime = b_prime.retain(x.min(y));
+                    (a, b) = split(x, y, &mut first, &mut second, Step::Retain, Step::Retain);
+                }
+                (Some(Step::Delete(x)), Some(Step::Delete(y))) => {
+                    (a, b) = split(x, y, &mut first, &mut second, Step::Delete, Step::Delete);
+                }
+                (Some(Step::Delete(x)), Some(Step::Retain(y))) => {
+                    a_prime = a_prime.delete(x.min(y));
+                    (a, b) = split(x, y, &mut first, &mut second, Step::Delete, Step::Retain);
+                }
+                (Some(Step::Retain(x)), Some(Step::Delete(y))) => {
+                    b_prime = b_prime.delete(x.min(y));
+                    (a, b) = split(x, y, &mut first, &mut second, Step::Retain, Step::Delete);
+                }
+                (Some(_), None) | (None, Some(_)) => {
+                    unreachable!("both operations are built over the whole text")
+                }
+            }
+        }
+    }
+
+    /// Applies the operation to `pieces`, returning the changes it made, in order.
+    pub(crate) fn apply(&self, pieces: &mut Vec<Piece>) -> Vec<Change> {
+        let mut changes = Vec::new();
+        let mut pos = 0;
+        let mut steps = self.steps.iter().peekable();
+        while let Some(step) = steps.next() {
+            let (mut removed, mut inserted) = match step {
+                Step::Retain(len) => {
+                    pos += len;
+                    continue;
+                }
+                Step::Insert(pieces) => (0, pieces.clone()),
+                Step::Delete(len) => (*len, Vec::new()),
+            };
+            while let Some(step) = steps.next_if(|step| !matches!(step, Step::Retain(_))) {
+                match step {
+                    Step::Insert(pieces) => inserted.extend(pieces.iter().cloned()),
+                    Step::Delete(len) => removed += len,
+                    Step::Retain(_) => unreachable!("retains are not taken"),
+                }
+            }
+            let inserted_len = pieces_len(&inserted);
+            let change = text_change(pieces, pos, removed, inserted);
+            change.apply(pieces);
+            changes.push(change);
+            pos += inserted_len;
+        }
+        changes
+    }
+}
+
+/// Consumes the shorter of two steps of lengths `x` and `y` from both sides, returning what is
+/// left of the longer one and the next step in place of the shorter one.
+fn split(
+    x: usize,
+    y: usize,
+    first: &mut impl Iterator<Item = Step>,
+    second: &mut impl Iterator<Item = Step>,
+    first_rest: fn(usize) -> Step,
+    second_rest: fn(usize) -> Step,
+) -> (Option<Step>, Option<Step>) {
+    match x.cmp(&y) {
+        Ordering::Less => (first.next(), Some(second_rest(y - x))),
+        Ordering::Equal => (first.next(), second.next()),
+        Ordering::Greater => (Some(first_rest(x - y)), second.next()),
+    }
+}
+
+fn pieces_len(pieces: &[Piece]) -> usize {
+    pieces.iter().map(Piece::len).sum()
+}
+
+/// Returns the change replacing `removed` bytes at byte index `at` of the text of `pieces` with
+/// `inserted` pieces, splitting the pieces at both ends if needed.
+pub(crate) fn text_change(
+    pieces: &[Piece],
+    at: usize,
+    removed: usize,
+    inserted: Vec<Piece>,
+) -> Change {
+    let mut start = 0;
+    let mut first = pieces.len();
+    for (idx, piece) in pieces.iter().enumerate() {
+        if at < start + piece.len() {
+            first = idx;
+            break;
+        }
+        start += piece.len();
+    }
+    let end = at + removed;
+    let (mut last, mut last_end) = (first, start);
+    while last < pieces.len() && last_end < end {
+        last_end += pieces[last].len();
+        last += 1;
+    }
+    let mut replacement = Vec::new();
+    if at > start {
+        replacement.push(pieces[first].clone().split_at(at - start).0);
+    }
+    replacement.extend(inserted.into_iter().filter(|piece| piece.len() > 0));
+    if last_end > end {
+        let piece = &pieces[last - 1];
+        replacement.push(piece.clone().split_at(piece.len() - (last_end - end)).1);
+    }
+    Change::new(first, pieces[first..last].to_vec(), replacement)
+}
+
+/// Undo and redo history as operations, which follow remote changes until turned back into
+/// edits with [`Rebase::into_history`].
+#[derive(Debug, Clone)]
+pub(crate) struct Rebase {
+    /// Operations undoing the edits, the oldest edit first, each with its changes in the order
+    /// undo reverts them.
+    undo: Vec<Vec<PieceOperation>>,
+    /// Operations redoing the edits, the next one to redo first.
+    redo: Vec<Vec<PieceOperation>>,
+}
+
+impl Rebase {
+    pub(crate) fn new(pieces: &[Piece], history: &[Edit], redo: &[Edit]) -> Self {
+        let mut current = pieces.to_vec();
+        let mut undo_ops: Vec<_> = history
+            .iter()
+            .rev()
+            .map(|edit| undo_operations(&mut current, edit))
+            .collect();
+        undo_ops.reverse();
+
+        let mut current = pieces.to_vec();
+        let mut redo_ops = Vec::new();
+        for edit in redo.iter().rev() {
+            let mut ops = Vec::new();
+            for change in edit.changes() {
+                let len = pieces_len(&current);
+                let splice = change_splice(&current, change);
+                ops.push(
+                    PieceOperation::new()
+                        .retain(splice.at)
+                        .insert(slice_pieces(change.inserted(), splice.inserted.clone()))
+                        .delete(splice.removed)
+                        .retain(len - splice.at - splice.removed),
+                );
+                change.apply(&mut current);
+            }
+            redo_ops.push(ops);
+        }
+        Self {
+            undo: undo_ops,
+            redo: redo_ops,
+        }
+    }
+
+    /// Adds a local `edit`, which turned the text into the one of `pieces`, and clears the redo
+    /// history like a new edit does.
+    pub(crate) fn record(&mut self, pieces: &[Piece], edit: &Edit) {
+        let ops = undo_operations(&mut pieces.to_vec(), edit);
+        self.undo.push(ops);
+        self.redo.clear();
+    }
+
+    /// Moves the history past `remote`, which applies to the current text.
+    pub(crate) fn follow(&mut self, remote: &PieceOperation) {
+        let mut undo_remote = remote.clone();
+        for op in self.undo.iter_mut().rev().flatten() {
+            let (op_prime, remote_prime) = op.transform(&undo_remote);
+            *op = op_prime;
+            undo_remote = remote_prime;
+        }
+        let mut redo_remote = remote.clone();
+        for op in self.redo.iter_mut().flatten() {
+            let (op_prime, remote_prime) = op.transform(&redo_remote);
+            *op = op_prime;
+            redo_remote = remote_prime;
+        }
+    }
+
+    /// Returns the undo and redo history for the current `pieces`. Edits which have nothing
+    /// left to undo, because the remote changes removed all they did, are dropped.
+    pub(crate) fn into_history(self, pieces: &[Piece]) -> (Vec<Edit>, Vec<Edit>) {
+        let mut current = pieces.to_vec();
+        let mut history = Vec::new();
+        for ops in self.undo.into_iter().rev() {
+            let mut changes: Vec<_> = ops
+                .iter()
+                .flat_map(|op| op.apply(&mut current))
+                .map(Change::inverse)
+                .collect();
+            changes.reverse();
+            if !changes.is_empty() {
+                history.push(changes.into_iter().collect());
+            }
+        }
+        history.reverse();
+
+        let mut current = pieces.to_vec();
+        let mut redo = Vec::new();
+        for ops in self.redo {
+            let changes: Edit = ops.iter().flat_map(|op| op.apply(&mut current)).collect();
+            if !changes.is_empty() {
+                redo.push(changes);
+            }
+        }
+        redo.reverse();
+        (history, redo)
+    }
+}
+
+/// Returns the operations undoing `edit`, which turned the text into the one of `current`, and
+/// reverts `current` to the text from before the edit.
+fn undo_operations(current: &mut Vec<Piece>, edit: &Edit) -> Vec<PieceOperation> {
+    let mut ops = Vec::new();
+    for change in edit.changes().iter().rev() {
+        let len = pieces_len(current);
+        let splice = change_splice(current, change);
+        let restored = slice_pieces(
+            change.removed(),
+            splice.inserted.start..splice.inserted.start + splice.removed,
+        );
+        ops.push(
+            PieceOperation::new()
+                .retain(splice.at)
+                .insert(restored)
+                .delete(splice.inserted.len())
+                .retain(len - splice.at - splice.inserted.len()),
+        );
+        change.revert(current);
+    }
+    ops
+}
+
+impl PieceTable<'_> {
+    /// Turns the history moved past remote changes back into edits, once they are needed.
+    pub(crate) fn settle_history(&mut self) {
+        if let Some(rebase) = self.rebase.take() {
+            (self.history, self.redo) = rebase.into_history(&self.pieces);
+        }
+    }
+
+    /// Returns the undo and redo history as [`PieceTable::settle_history`] would make it, for
+    /// when the table can't be changed.
+    pub(crate) fn settled_history(&self) -> (Cow<'_, [Edit]>, Cow<'_, [Edit]>) {
+        match &self.rebase {
+            Some(rebase) => {
+                let (history, redo) = rebase.clone().into_history(&self.pieces);
+                (Cow::Owned(history), Cow::Owned(redo))
+            }
+            None => (Cow::Borrowed(&self.history), Cow::Borrowed(&self.redo)),
+        }
+    }
+}
+
+fn change_splice(pieces: &[Piece], change: &Change) -> TextSplice {
+    TextSplice::new(pieces, change.at(), change.removed(), change.inserted())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Source;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    #[test]
+    fn should_split_pieces_at_both_ends_of_replaced_text() {
+        init_logger();
+        // given
+        let pieces = vec![
+            Piece::new(0..4, Source::Original),
+            Piece::new(0..3, Source::Add),
+        ];
+
+        // when
+        let change = text_change(&pieces, 2, 3, vec![Piece::new(3..4, Source::Add)]);
+
+        // then
+        assert_eq!(
+            change,
+            Change::new(
+                0,
+                pieces.clone(),
+                vec![
+                    Piece::new(0..2, Source::Original),
+                    Piece::new(3..4, Source::Add),
+                    Piece::new(1..3, Source::Add),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn should_insert_between_pieces_without_splitting() {
+        init_logger();
+        // given
+        let pieces = vec![
+            Piece::new(0..4, Source::Original),
+            Piece::new(0..3, Source::Add),
+        ];
+
+        // when
+        let change = text_change(&pieces, 4, 0, vec![Piece::new(3..4, Source::Add)]);
+
+        // then
+        assert_eq!(
+            change,
+            Change::new(1, vec![], vec![Piece::new(3..4, Source::Add)])
+        );
+    }
+}
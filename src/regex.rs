@@ -0,0 +1,442 @@
+use crate::PieceTable;
+use regex_automata::dfa::{dense, Automaton, StartKind};
+use regex_automata::nfa::thompson::{self, pikevm::PikeVM};
+use regex_automata::util::captures::GroupInfo;
+use regex_automata::util::primitives::{PatternID, StateID};
+use regex_automata::util::start;
+use regex_automata::{Anchored, Input, MatchKind};
+use std::fmt;
+use std::ops::Range;
+
+/// Compiled regular expression which can be searched for in a [`PieceTable`] without
+/// projecting it.
+///
+/// Match boundaries are found by running DFAs over the pieces byte by byte, so a match can
+/// span any number of pieces. Only capture groups need the matched text in one place, so
+/// [`PieceTable::captures`] copies just the match (and one char of context on both sides).
+///
+/// Unicode word boundaries (`\b`) are not supported by the DFAs, use `(?-u:\b)` instead.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    forward: dense::DFA<Vec<u32>>,
+    reverse: dense::DFA<Vec<u32>>,
+    pikevm: PikeVM,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, RegexError> {
+        let forward = dense::Builder::new()
+            .configure(dense::Config::new().start_kind(StartKind::Unanchored))
+            .build(pattern)
+            .map_err(|e| RegexError::Dfa(Box::new(e)))?;
+        let reverse = dense::Builder::new()
+            .configure(
+                dense::Config::new()
+                    .start_kind(StartKind::Anchored)
+                    .match_kind(MatchKind::All),
+            )
+            .thompson(thompson::Config::new().reverse(true))
+            .build(pattern)
+            .map_err(|e| RegexError::Dfa(Box::new(e)))?;
+        let pikevm = PikeVM::new(pattern).map_err(|e| RegexError::Nfa(Box::new(e)))?;
+        Ok(Self {
+            forward,
+            reverse,
+            pikevm,
+        })
+    }
+
+    /// Returns the end of the leftmost-first match starting at or after `from`.
+    fn find_end(&self, table: &PieceTable, from: usize) -> Option<usize> {
+        let dfa = &self.forward;
+        let look_behind = table.char_indices(0..from).next_back().map(last_byte);
+        let config = start::Config::new()
+            .anchored(Anchored::No)
+            .look_behind(look_behind);
+        let mut sid = dfa.start_state(&config).ok()?;
+        let mut end = None;
+        for (idx, byte) in bytes(table, from..table.len()) {
+            sid = dfa.next_state(sid, byte);
+            match Self::classify(dfa, sid) {
+                State::Match => end = Some(idx),
+                State::Dead => return end,
+                State::Quit => return None,
+                State::Other => {}
+            }
+        }
+        sid = dfa.next_eoi_state(sid);
+        if dfa.is_match_state(sid) {
+            end = Some(table.len());
+        }
+        end
+    }
+
+    /// Walks back from the end of a match and returns its leftmost start, not earlier than `from`.
+    fn find_start(&self, table: &PieceTable, from: usize, end: usize) -> Option<usize> {
+        let dfa = &self.reverse;
+        let look_ahead = table.char_indices(end..table.len()).next().map(first_byte);
+        let config = start::Config::new()
+            .anchored(Anchored::Yes)
+            .look_behind(look_ahead);
+        let mut sid = dfa.start_state(&config).ok()?;
+        let mut start = None;
+        for (idx, byte) in bytes(table, from..end).rev() {
+            sid = dfa.next_state(sid, byte);
+            match Self::classify(dfa, sid) {
+                State::Match => start = Some(idx + 1),
+                State::Dead => return start,
+                State::Quit => return None,
+                State::Other => {}
+            }
+        }
+        sid = match table.char_indices(0..from).next_back() {
+            Some(before) => dfa.next_state(sid, last_byte(before)),
+            None => dfa.next_eoi_state(sid),
+        };
+        if dfa.is_match_state(sid) {
+            start = Some(from);
+        }
+        start
+    }
+
+    fn classify(dfa: &dense::DFA<Vec<u32>>, sid: StateID) -> State {
+        if !dfa.is_special_state(sid) {
+            State::Other
+        } else if dfa.is_match_state(sid) {
+            State::Match
+        } else if dfa.is_dead_state(sid) {
+            State::Dead
+        } else if dfa.is_quit_state(sid) {
+            State::Quit
+        } else {
+            State::Other
+        }
+    }
+}
+
+enum State {
+    Match,
+    Dead,
+    Quit,
+    Other,
+}
+
+/// Match of a [`Regex`] together with its capture groups, as byte ranges of the table's text.
+#[derive(Debug, Clone)]
+pub struct Captures {
+    found: Range<usize>,
+    groups: Vec<Option<Range<usize>>>,
+    group_info: GroupInfo,
+}
+
+impl Captures {
+    /// Range of the whole match, the same as `get(0)`.
+    #[must_use]
+    pub fn range(&self) -> Range<usize> {
+        self.found.clone()
+    }
+
+    #[must_use]
+    pub fn get(&self, idx: usize) -> Option<Range<usize>> {
+        self.groups.get(idx).cloned().flatten()
+    }
+
+    #[must_use]
+    pub fn name(&self, name: &str) -> Option<Range<usize>> {
+        let idx = self.group_info.to_index(PatternID::default(), name)?;
+        self.get(idx)
+    }
+
+    /// Number of groups, including the implicit group 0.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+}
+
+impl PieceTable<'_> {
+    /// Returns the byte range of the first match of `regex` starting at or after `from`.
+    #[must_use]
+    pub fn find_regex(&self, regex: &Regex, from: usize) -> Option<Range<usize>> {
+        let from = from.min(self.len());
+        let end = regex.find_end(self, from)?;
+        let start = regex.find_start(self, from, end)?;
+        Some(start..end)
+    }
+
+    /// Iterates over all non-overlapping matches of `regex`.
+    ///
+    /// Like in the `regex` crate, an empty match directly following the previous match is
+    /// skipped.
+    pub fn find_regex_iter<'t>(
+        &'t self,
+        regex: &'t Regex,
+    ) -> impl Iterator<Item = Range<usize>> + 't {
+        self.regex_matches_from(regex, 0)
+    }
+
+    pub(crate) fn regex_matches_from<'t>(
+        &'t self,
+        regex: &'t Regex,
+        from: usize,
+    ) -> impl Iterator<Item = Range<usize>> + 't {
+        let mut from = Some(from);
+        let mut last_end = None;
+        std::iter::from_fn(move || loop {
+            let at = from?;
+            let Some(found) = self.find_regex(regex, at) else {
+                from = None;
+                return None;
+            };
+            if found.is_empty() && Some(found.end) == last_end {
+                from = self.next_char_boundary(found.end);
+                continue;
+            }
+            from = Some(found.end);
+            last_end = Some(found.end);
+            return Some(found);
+        })
+    }
+
+    /// Returns the first match of `regex` starting at or after `from`, with its capture groups.
+    #[must_use]
+    pub fn captures(&self, regex: &Regex, from: usize) -> Option<Captures> {
+        let found = self.find_regex(regex, from)?;
+        Some(self.captures_of(regex, found))
+    }
+
+    pub fn captures_iter<'t>(&'t self, regex: &'t Regex) -> impl Iterator<Item = Captures> + 't {
+        self.find_regex_iter(regex)
+            .map(|found| self.captures_of(regex, found))
+    }
+
+    pub(crate) fn captures_of(&self, regex: &Regex, found: Range<usize>) -> Captures {
+        // one char on both sides is enough for look-around assertions like `^`, `$` or `\b`
+        let context_start = self
+            .char_indices(0..found.start)
+            .next_back()
+            .map_or(found.start, |(idx, _)| idx);
+        let context_end = self
+            .char_indices(found.end..self.len())
+            .next()
+            .map_or(found.end, |(idx, c)| idx + c.len_utf8());
+        let haystack: String = self
+            .chunks_in(context_start..context_end)
+            .map(|(_, txt)| txt)
+            .collect();
+        let input = Input::new(&haystack)
+            .range(found.start - context_start..found.end - context_start)
+            .anchored(Anchored::Yes);
+        let mut cache = regex.pikevm.create_cache();
+        let mut caps = regex.pikevm.create_captures();
+        regex.pikevm.search(&mut cache, &input, &mut caps);
+        let groups = (0..caps.group_len())
+            .map(|idx| {
+                caps.get_group(idx)
+                    .map(|span| span.start + context_start..span.end + context_start)
+            })
+            .collect();
+        Captures {
+            found,
+            groups,
+            group_info: caps.group_info().clone(),
+        }
+    }
+
+    fn next_char_boundary(&self, idx: usize) -> Option<usize> {
+        self.char_indices(idx..self.len())
+            .next()
+            .map(|(idx, c)| idx + c.len_utf8())
+    }
+}
+
+fn bytes<'t>(
+    table: &'t PieceTable,
+    range: Range<usize>,
+) -> impl DoubleEndedIterator<Item = (usize, u8)> + 't {
+    table.chunks_in(range).flat_map(|(start, txt)| {
+        txt.bytes()
+            .enumerate()
+            .map(move |(idx, byte)| (start + idx, byte))
+    })
+}
+
+fn last_byte((_, c): (usize, char)) -> u8 {
+    let mut buf = [0; 4];
+    *c.encode_utf8(&mut buf).as_bytes().last().unwrap()
+}
+
+fn first_byte((_, c): (usize, char)) -> u8 {
+    let mut buf = [0; 4];
+    c.encode_utf8(&mut buf).as_bytes()[0]
+}
+
+#[derive(Debug)]
+pub enum RegexError {
+    Dfa(Box<dense::BuildError>),
+    Nfa(Box<thompson::BuildError>),
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Dfa(e) => write!(f, "failed to build regex DFA: {e}"),
+            Self::Nfa(e) => write!(f, "failed to build regex NFA: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for RegexError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Dfa(e) => Some(e.as_ref()),
+            Self::Nfa(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    fn table_with_pieces<'a>(original: &'a str, added: &str) -> PieceTable<'a> {
+        let mut table = PieceTable::from_text(original);
+        for c in added.chars() {
+            table.insert_char(c, table.len());
+        }
+        table
+    }
+
+    mod find_regex {
+        use super::*;
+
+        #[test]
+        fn should_find_match_spanning_pieces() {
+            init_logger();
+            // given
+            let table = table_with_pieces("version 1", "2.3 released");
+            let regex = Regex::new(r"[0-9]+\.[0-9]+").unwrap();
+
+            // when
+            let found = table.find_regex(&regex, 0);
+
+            // then
+            assert_eq!(found, Some(8..12));
+        }
+
+        #[test]
+        fn should_respect_start_position() {
+            init_logger();
+            // given
+            let table = PieceTable::from_text("abc abc");
+            let regex = Regex::new("a.c").unwrap();
+
+            // when
+            let found = table.find_regex(&regex, 1);
+
+            // then
+            assert_eq!(found, Some(4..7));
+        }
+
+        #[test]
+        fn should_use_text_before_start_for_anchors() {
+            init_logger();
+            // given
+            let table = PieceTable::from_text("xab\nab");
+            let regex = Regex::new("(?m)^ab").unwrap();
+
+            // when
+            let found = table.find_regex(&regex, 1);
+
+            // then
+            assert_eq!(found, Some(4..6));
+        }
+
+        #[test]
+        fn should_find_iter_over_all_matches() {
+            init_logger();
+            // given
+            let table = table_with_pieces("foo1 bar22 b", "az333");
+            let regex = Regex::new(r"[a-z]+[0-9]+").unwrap();
+
+            // when
+            let found: Vec<_> = table.find_regex_iter(&regex).collect();
+
+            // then
+            assert_eq!(found, [0..4, 5..10, 11..17]);
+        }
+
+        #[test]
+        fn should_skip_empty_match_after_previous_match() {
+            init_logger();
+            // given
+            let table = PieceTable::from_text("aaba");
+            let regex = Regex::new("a*").unwrap();
+
+            // when
+            let found: Vec<_> = table.find_regex_iter(&regex).collect();
+
+            // then
+            assert_eq!(found, [0..2, 3..4]);
+        }
+
+        #[test]
+        fn should_fail_on_invalid_pattern() {
+            init_logger();
+            // given
+            let pattern = "(unclosed";
+
+            // when
+            let regex = Regex::new(pattern);
+
+            // then
+            assert!(regex.is_err());
+        }
+    }
+
+    mod captures {
+        use super::*;
+
+        #[test]
+        fn should_return_capture_groups() {
+            init_logger();
+            // given
+            let table = table_with_pieces("name: Jo", "hn, age: 42");
+            let regex = Regex::new(r"(?<key>\w+): (?<value>\w+)").unwrap();
+
+            // when
+            let caps = table.captures(&regex, 0).unwrap();
+
+            // then
+            assert_eq!(caps.range(), 0..10);
+            assert_eq!(caps.name("key"), Some(0..4));
+            assert_eq!(caps.get(2), Some(6..10));
+            assert_eq!(caps.len(), 3);
+        }
+
+        #[test]
+        fn should_report_missing_optional_groups() {
+            init_logger();
+            // given
+            let table = PieceTable::from_text("ab a");
+            let regex = Regex::new("a(b)?").unwrap();
+
+            // when
+            let all: Vec<_> = table.captures_iter(&regex).collect();
+
+            // then
+            assert_eq!(all.len(), 2);
+            assert_eq!(all[0].get(1), Some(1..2));
+            assert_eq!(all[1].get(1), None);
+        }
+    }
+}
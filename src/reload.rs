@@ -0,0 +1,110 @@
+use crate::diff::{myers, token_span};
+use crate::PieceTable;
+
+impl PieceTable<'_> {
+    /// Makes the text equal to `txt`, e.g. the file after an external tool rewrote it, through
+    /// the smallest edits found by diffing both, so that anchors in unchanged text stay where
+    /// they were.
+    ///
+    /// The reload is a single step in the undo history, and the new text is marked as saved.
+    pub fn reload_from(&mut self, txt: &str) {
+        let new = PieceTable::from_text(txt);
+        let mut edits = Vec::new();
+        // lines are compared first, so characters only have to be within changed lines
+        for lines in self.diff_lines(&new) {
+            let old: Vec<_> = self.char_indices(lines.old.clone()).collect();
+            let new: Vec<_> = txt[lines.new.clone()]
+                .char_indices()
+                .map(|(idx, c)| (lines.new.start + idx, c))
+                .collect();
+            for (a, b) in myers(old.len(), new.len(), |a, b| old[a].1 == new[b].1) {
+                let removed = token_span(&old, a, lines.old.end);
+                let inserted = token_span(&new, b, lines.new.end);
+                edits.push((removed, &txt[inserted]));
+            }
+        }
+        self.transaction(|table| {
+            for (removed, inserted) in edits.into_iter().rev() {
+                if !removed.is_empty() {
+                    table.remove(removed.clone());
+                }
+                if !inserted.is_empty() {
+                    table.insert_str(inserted, removed.start);
+                }
+            }
+        });
+        self.mark_saved();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Bias;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    #[test]
+    fn should_change_text_to_new_one() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("fn main() {\nprintln!(\"hi\");\n}\n");
+        table.insert_str("    ", 12);
+        let formatted = "fn main() {\n    println!(\"hello\");\n}\n";
+
+        // when
+        table.reload_from(formatted);
+
+        // then
+        assert_eq!(table.project(), formatted);
+        assert!(!table.is_dirty());
+    }
+
+    #[test]
+    fn should_keep_anchors_in_unchanged_text() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("let x=1;\nlet y=2;\n");
+        let cursor = table.anchor(16, Bias::Left).unwrap();
+
+        // when
+        table.reload_from("let x = 1;\nlet y = 2;\n");
+
+        // then
+        assert_eq!(table.anchor_position(cursor), Some(20));
+    }
+
+    #[test]
+    fn should_undo_reload_at_once() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("one\ntwo\nthree\n");
+        table.insert_char('!', 3);
+        let edited = table.project();
+
+        // when
+        table.reload_from("zero\none\nthree\nfour\n");
+        table.undo();
+
+        // then
+        assert_eq!(table.project(), edited);
+        assert!(table.is_dirty());
+    }
+
+    #[test]
+    fn should_not_record_anything_for_same_text() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("text");
+        table.insert_char('s', 4);
+
+        // when
+        table.reload_from("texts");
+        table.undo();
+
+        // then
+        assert_eq!(table.project(), "text");
+    }
+}
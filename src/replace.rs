@@ -0,0 +1,275 @@
+use crate::{Pattern, PieceTable};
+use std::ops::Range;
+
+#[cfg(feature = "regex")]
+use crate::{Captures, Regex};
+
+/// Something that can be searched for and replaced in a [`PieceTable`].
+///
+/// Literal patterns ([`Pattern`], `&str`, `String`) insert the replacement as is. A `Regex`
+/// (with the `regex` feature) expands `$1`, `${1}`, `$name` and `${name}` in the replacement to
+/// the text of the matching capture group, and `$$` to a single `$`.
+pub trait ReplacePattern {
+    /// Returns the matches inside `within` paired with the text that should replace them.
+    fn replacements(
+        &self,
+        table: &PieceTable,
+        within: Range<usize>,
+        replacement: &str,
+    ) -> Vec<(Range<usize>, String)>;
+}
+
+impl<T: ReplacePattern + ?Sized> ReplacePattern for &T {
+    fn replacements(
+        &self,
+        table: &PieceTable,
+        within: Range<usize>,
+        replacement: &str,
+    ) -> Vec<(Range<usize>, String)> {
+        (**self).replacements(table, within, replacement)
+    }
+}
+
+impl ReplacePattern for Pattern {
+    fn replacements(
+        &self,
+        table: &PieceTable,
+        within: Range<usize>,
+        replacement: &str,
+    ) -> Vec<(Range<usize>, String)> {
+        table
+            .matches_from(self, within.start)
+            .take_while(|found| found.end <= within.end)
+            .map(|found| (found, replacement.to_string()))
+            .collect()
+    }
+}
+
+impl ReplacePattern for str {
+    fn replacements(
+        &self,
+        table: &PieceTable,
+        within: Range<usize>,
+        replacement: &str,
+    ) -> Vec<(Range<usize>, String)> {
+        Pattern::new(self).replacements(table, within, replacement)
+    }
+}
+
+impl ReplacePattern for String {
+    fn replacements(
+        &self,
+        table: &PieceTable,
+        within: Range<usize>,
+        replacement: &str,
+    ) -> Vec<(Range<usize>, String)> {
+        self.as_str().replacements(table, within, replacement)
+    }
+}
+
+#[cfg(feature = "regex")]
+impl ReplacePattern for Regex {
+    fn replacements(
+        &self,
+        table: &PieceTable,
+        within: Range<usize>,
+        replacement: &str,
+    ) -> Vec<(Range<usize>, String)> {
+        table
+            .regex_matches_from(self, within.start)
+            .take_while(|found| found.end <= within.end)
+            .map(|found| {
+                let caps = table.captures_of(self, found.clone());
+                (found, expand(table, &caps, replacement))
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "regex")]
+fn expand(table: &PieceTable, caps: &Captures, template: &str) -> String {
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(idx) = rest.find('$') {
+        expanded.push_str(&rest[..idx]);
+        rest = &rest[idx + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            expanded.push('$');
+            rest = after;
+            continue;
+        }
+        let (name, after) = if let Some(braced) = rest.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => ("", rest),
+            }
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+        if name.is_empty() {
+            expanded.push('$');
+            continue;
+        }
+        rest = after;
+        let group = name
+            .parse::<usize>()
+            .map_or_else(|_| caps.name(name), |idx| caps.get(idx));
+        if let Some(range) = group {
+            expanded.extend(table.chunks_in(range).map(|(_, txt)| txt));
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+impl PieceTable<'_> {
+    /// Replaces all non-overlapping matches of `pattern` lying fully inside `within` (the whole
+    /// text when `None`) and returns the number of replacements.
+    ///
+    /// All the replacements are recorded as a single step in the undo history.
+    pub fn replace_all<P: ReplacePattern>(
+        &mut self,
+        pattern: P,
+        replacement: &str,
+        within: Option<Range<usize>>,
+    ) -> usize {
+        let len = self.len();
+        let within = within.map_or(0..len, |range| range.start.min(len)..range.end.min(len));
+        let replacements = pattern.replacements(self, within, replacement);
+        self.transaction(|table| {
+            // going from the back keeps the ranges of the earlier matches valid
+            for (range, txt) in replacements.iter().rev() {
+                table.remove(range.clone());
+                table.insert_str(txt, range.start);
+            }
+        });
+        replacements.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    mod replace_all {
+        use super::*;
+
+        #[test]
+        fn should_replace_all_literal_matches() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("one two one two one");
+
+            // when
+            let count = table.replace_all("one", "1", None);
+
+            // then
+            assert_eq!(count, 3);
+            assert_eq!(table.project(), "1 two 1 two 1");
+        }
+
+        #[test]
+        fn should_not_expand_captures_for_literal_pattern() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("price: X");
+
+            // when
+            let count = table.replace_all(Pattern::new("x").case_insensitive(), "$1", None);
+
+            // then
+            assert_eq!(count, 1);
+            assert_eq!(table.project(), "price: $1");
+        }
+
+        #[test]
+        fn should_only_replace_inside_range() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("aaa aaa aaa");
+
+            // when
+            let count = table.replace_all("aaa", "b", Some(2..11));
+
+            // then
+            assert_eq!(count, 2);
+            assert_eq!(table.project(), "aaa b b");
+        }
+
+        #[test]
+        fn should_undo_all_replacements_at_once() {
+            init_logger();
+            // given
+            let initial_txt = "cat dog cat dog";
+            let mut table = PieceTable::from_text(initial_txt);
+            table.insert_char('!', table.len());
+
+            // when
+            table.replace_all("dog", "bird", None);
+            let replaced = table.project();
+            table.undo();
+            let undone = table.project();
+            table.redo();
+
+            // then
+            assert_eq!(replaced, "cat bird cat bird!");
+            assert_eq!(undone, "cat dog cat dog!");
+            assert_eq!(table.project(), replaced);
+        }
+
+        #[test]
+        fn should_not_record_history_when_nothing_matches() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("text");
+            table.insert_char('s', 4);
+
+            // when
+            let count = table.replace_all("missing", "x", None);
+            table.undo();
+
+            // then
+            assert_eq!(count, 0);
+            assert_eq!(table.project(), "text");
+        }
+
+        #[cfg(feature = "regex")]
+        #[test]
+        fn should_expand_regex_captures() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("2024-01-31 and 1999-12-01");
+            let regex = Regex::new(r"(?<y>\d{4})-(\d{2})-(\d{2})").unwrap();
+
+            // when
+            let count = table.replace_all(&regex, "$3.$2.${y} ($$)", None);
+
+            // then
+            assert_eq!(count, 2);
+            assert_eq!(table.project(), "31.01.2024 ($) and 01.12.1999 ($)");
+        }
+
+        #[cfg(feature = "regex")]
+        #[test]
+        fn should_replace_missing_group_with_nothing() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("ab a");
+            let regex = Regex::new("a(b)?").unwrap();
+
+            // when
+            let count = table.replace_all(&regex, "[$1$missing]", None);
+
+            // then
+            assert_eq!(count, 2);
+            assert_eq!(table.project(), "[b] []");
+        }
+    }
+}
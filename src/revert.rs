@@ -0,0 +1,264 @@
+use crate::{Piece, PieceTable, Source};
+use std::ops::Range;
+
+/// Original piece and where it currently is in the text.
+struct Placed {
+    current: Range<usize>,
+    original: Range<usize>,
+}
+
+impl PieceTable<'_> {
+    /// Restores the original text of the changed region around `range` and returns where the
+    /// restored text is now. Returns `None` if `range` is out of bounds, or if the original text
+    /// on both sides of the region is out of order, e.g. after moving text around, in which case
+    /// the text is left as is.
+    ///
+    /// The region grows to the nearest original text on both sides, so reverting any part of an
+    /// insertion removes all of it, and an empty range where text was deleted brings the text
+    /// back. The revert is a single step in the undo history.
+    pub fn revert_range(&mut self, range: Range<usize>) -> Option<Range<usize>> {
+        if range.start > range.end || range.end > self.len() {
+            return None;
+        }
+        // positions on the boundary of an original piece count as inside of it, so the region
+        // grows only when `range` reaches into changed text
+        let placed = self.original_pieces();
+        let (current_start, original_start) = placed
+            .iter()
+            .find(|p| p.current.start <= range.start && range.start <= p.current.end)
+            .map(|p| {
+                (
+                    range.start,
+                    p.original.start + range.start - p.current.start,
+                )
+            })
+            .or_else(|| {
+                placed
+                    .iter()
+                    .rev()
+                    .find(|p| p.current.end <= range.start)
+                    .map(|p| (p.current.end, p.original.end))
+            })
+            .unwrap_or((0, 0));
+        let (current_end, original_end) = placed
+            .iter()
+            .rev()
+            .find(|p| p.current.start <= range.end && range.end <= p.current.end)
+            .map(|p| (range.end, p.original.start + range.end - p.current.start))
+            .or_else(|| {
+                placed
+                    .iter()
+                    .find(|p| p.current.start >= range.end)
+                    .map(|p| (p.current.start, p.original.start))
+            })
+            .unwrap_or((self.len(), self.original_buffer().len()));
+        if original_end < original_start {
+            return None;
+        }
+        let original = original_start..original_end;
+        let current = current_start..current_end;
+
+        if self.is_original_text(current.clone(), original.clone()) {
+            return Some(current);
+        }
+        self.transaction(|table| {
+            table.remove(current.clone());
+            if !original.is_empty() {
+                table.insert_at(
+                    current.start,
+                    vec![Piece::new(original.clone(), Source::Original)],
+                );
+            }
+        });
+        Some(current.start..current.start + original.len())
+    }
+
+    fn original_pieces(&self) -> Vec<Placed> {
+        let mut placed = Vec::new();
+        let mut start = 0;
+        for piece in &self.pieces {
+            if piece.source == Source::Original && piece.len() > 0 {
+                placed.push(Placed {
+                    current: start..start + piece.len(),
+                    original: piece.range.clone(),
+                });
+            }
+            start += piece.len();
+        }
+        placed
+    }
+
+    fn is_original_text(&self, current: Range<usize>, original: Range<usize>) -> bool {
+        if current.len() != original.len() {
+            return false;
+        }
+        let mut expected = &self.original_buffer()[original];
+        self.chunks_in(current).all(|(_, chunk)| {
+            let matches = expected.starts_with(chunk);
+            expected = &expected[chunk.len().min(expected.len())..];
+            matches
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::Buffer;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    const TXT: &str = "first\nsecond\nthird\n";
+
+    #[test]
+    fn should_leave_text_between_original_text_out_of_order() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_parts(
+            Buffer::Borrowed("one two"),
+            "!".to_string(),
+            vec![
+                Piece::new(4..7, Source::Original),
+                Piece::new(0..1, Source::Add),
+                Piece::new(0..3, Source::Original),
+            ],
+            Vec::new(),
+            Vec::new(),
+        );
+
+        // when
+        let reverted = table.revert_range(3..4);
+
+        // then
+        assert_eq!(reverted, None);
+        assert_eq!(table.project(), "two!one");
+    }
+
+    #[test]
+    fn should_remove_whole_insertion() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text(TXT);
+        table.insert_str("inserted\n", 6);
+
+        // when
+        let reverted = table.revert_range(8..9);
+
+        // then
+        assert_eq!(reverted, Some(6..6));
+        assert_eq!(table.project(), TXT);
+    }
+
+    #[test]
+    fn should_restore_modified_line() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text(TXT);
+        table.remove(6..12);
+        table.insert_str("2nd", 6);
+
+        // when
+        let reverted = table.revert_range(6..9);
+
+        // then
+        assert_eq!(reverted, Some(6..12));
+        assert_eq!(table.project(), TXT);
+    }
+
+    #[test]
+    fn should_restore_deleted_text_for_empty_range() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text(TXT);
+        table.remove(6..13);
+        table.insert_char('!', 0);
+
+        // when
+        let reverted = table.revert_range(7..7);
+
+        // then
+        assert_eq!(reverted, Some(7..14));
+        assert_eq!(table.project(), format!("!{TXT}"));
+    }
+
+    #[test]
+    fn should_only_revert_region_around_range() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text(TXT);
+        table.insert_char('1', 5);
+        table.insert_char('3', 19);
+
+        // when
+        table.revert_range(19..20);
+
+        // then
+        assert_eq!(table.project(), "first1\nsecond\nthird\n");
+    }
+
+    #[test]
+    fn should_not_revert_insertion_touching_range() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text(TXT);
+        table.insert_char('X', 6);
+        table.insert_char('Y', 10);
+
+        // when
+        let reverted = table.revert_range(7..10);
+
+        // then
+        assert_eq!(reverted, Some(7..10));
+        assert_eq!(table.project(), "first\nXsecYond\nthird\n");
+    }
+
+    #[test]
+    fn should_revert_in_one_undo_step() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text(TXT);
+        table.insert_str("abc", 3);
+        table.remove(7..10);
+        let edited = table.project();
+
+        // when
+        table.revert_range(3..8);
+        let reverted = table.project();
+        table.undo();
+
+        // then
+        assert_eq!(reverted, TXT);
+        assert_eq!(table.project(), edited);
+    }
+
+    #[test]
+    fn should_not_record_anything_for_unchanged_text() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text(TXT);
+        table.insert_char('!', 0);
+
+        // when
+        let reverted = table.revert_range(8..10);
+        table.undo();
+
+        // then
+        assert_eq!(reverted, Some(8..10));
+        assert_eq!(table.project(), TXT);
+    }
+
+    #[test]
+    fn should_reject_range_out_of_bounds() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text(TXT);
+
+        // when
+        let reverted = table.revert_range(0..100);
+
+        // then
+        assert_eq!(reverted, None);
+    }
+}
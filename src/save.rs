@@ -0,0 +1,352 @@
+use crate::PieceTable;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const MAX_TEMP_ATTEMPTS: u32 = 100;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SaveOptions {
+    backup: bool,
+}
+
+impl SaveOptions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps the previous content of the file next to it, with `~` appended to its name.
+    #[must_use]
+    pub fn backup(mut self) -> Self {
+        self.backup = true;
+        self
+    }
+}
+
+impl PieceTable<'_> {
+    /// Writes the text to `path` so that the file always holds either the old or the new text,
+    /// even if the program or the system crashes in the middle.
+    ///
+    /// The text is written to a temporary file in the same directory, synced to disk and then
+    /// renamed over `path`. Permissions of an existing file are kept.
+    pub fn save_to<P: AsRef<Path>>(&self, path: P, options: &SaveOptions) -> Result<(), SaveError> {
+        let path = path.as_ref();
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let (temp_path, temp_file) = create_temp(dir, path)?;
+        let result = self.save_through(&temp_path, temp_file, path, options);
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result?;
+        sync_dir(dir).map_err(|source| SaveError::Sync {
+            path: dir.to_path_buf(),
+            source,
+        })
+    }
+
+    fn save_through(
+        &self,
+        temp_path: &Path,
+        temp_file: File,
+        path: &Path,
+        options: &SaveOptions,
+    ) -> Result<(), SaveError> {
+        let write_error = |source| SaveError::Write {
+            path: temp_path.to_path_buf(),
+            source,
+        };
+        let mut writer = BufWriter::new(temp_file);
+        for chunk in self.chunks() {
+            writer.write_all(chunk.as_bytes()).map_err(write_error)?;
+        }
+        let temp_file = writer
+            .into_inner()
+            .map_err(|e| write_error(e.into_error()))?;
+        temp_file.sync_all().map_err(|source| SaveError::Sync {
+            path: temp_path.to_path_buf(),
+            source,
+        })?;
+
+        match fs::metadata(path) {
+            Ok(metadata) => {
+                fs::set_permissions(temp_path, metadata.permissions()).map_err(|source| {
+                    SaveError::Permissions {
+                        path: temp_path.to_path_buf(),
+                        source,
+                    }
+                })?;
+                if options.backup {
+                    let backup_path = backup_path(path);
+                    fs::copy(path, &backup_path).map_err(|source| SaveError::Backup {
+                        path: backup_path,
+                        source,
+                    })?;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(source) => {
+                return Err(SaveError::Permissions {
+                    path: path.to_path_buf(),
+                    source,
+                })
+            }
+        }
+
+        fs::rename(temp_path, path).map_err(|source| SaveError::Rename {
+            from: temp_path.to_path_buf(),
+            to: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
+fn create_temp(dir: &Path, path: &Path) -> Result<(PathBuf, File), SaveError> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut attempt = 0;
+    loop {
+        let temp_path = dir.join(format!(".{name}.{}.{attempt}.tmp", std::process::id()));
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+        {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < MAX_TEMP_ATTEMPTS => {
+                attempt += 1;
+            }
+            Err(source) => {
+                return Err(SaveError::CreateTemp {
+                    path: temp_path,
+                    source,
+                })
+            }
+        }
+    }
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push("~");
+    PathBuf::from(name)
+}
+
+/// Makes the rename itself durable. Directories can't be opened for syncing on Windows.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    if cfg!(unix) {
+        File::open(dir)?.sync_all()
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    CreateTemp {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Write {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Sync {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Permissions {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Backup {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+        source: io::Error,
+    },
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CreateTemp { path, source } => {
+                write!(
+                    f,
+                    "failed to create temporary file {}: {source}",
+                    path.display()
+                )
+            }
+            Self::Write { path, source } => {
+                write!(f, "failed to write to {}: {source}", path.display())
+            }
+            Self::Sync { path, source } => {
+                write!(f, "failed to sync {} to disk: {source}", path.display())
+            }
+            Self::Permissions { path, source } => {
+                write!(
+                    f,
+                    "failed to copy permissions for {}: {source}",
+                    path.display()
+                )
+            }
+            Self::Backup { path, source } => {
+                write!(f, "failed to create backup {}: {source}", path.display())
+            }
+            Self::Rename { from, to, source } => write!(
+                f,
+                "failed to move {} to {}: {source}",
+                from.display(),
+                to.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::CreateTemp { source, .. }
+            | Self::Write { source, .. }
+            | Self::Sync { source, .. }
+            | Self::Permissions { source, .. }
+            | Self::Backup { source, .. }
+            | Self::Rename { source, .. } => Some(source),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    fn edited_table() -> PieceTable<'static> {
+        let mut table = PieceTable::from_text("some text");
+        table.insert_str("new ", 5);
+        table
+    }
+
+    fn dir_entries(dir: &Path) -> Vec<String> {
+        let mut entries: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn should_write_text_to_new_file() {
+        init_logger();
+        // given
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+
+        // when
+        edited_table().save_to(&path, &SaveOptions::new()).unwrap();
+
+        // then
+        assert_eq!(fs::read_to_string(&path).unwrap(), "some new text");
+        assert_eq!(dir_entries(dir.path()), ["file.txt"]);
+    }
+
+    #[test]
+    fn should_replace_existing_file_and_keep_backup() {
+        init_logger();
+        // given
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "old text").unwrap();
+
+        // when
+        edited_table()
+            .save_to(&path, &SaveOptions::new().backup())
+            .unwrap();
+
+        // then
+        assert_eq!(fs::read_to_string(&path).unwrap(), "some new text");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("file.txt~")).unwrap(),
+            "old text"
+        );
+        assert_eq!(dir_entries(dir.path()), ["file.txt", "file.txt~"]);
+    }
+
+    #[test]
+    fn should_not_keep_backup_by_default() {
+        init_logger();
+        // given
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, "old text").unwrap();
+
+        // when
+        edited_table().save_to(&path, &SaveOptions::new()).unwrap();
+
+        // then
+        assert_eq!(dir_entries(dir.path()), ["file.txt"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_preserve_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        init_logger();
+        // given
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("script.sh");
+        fs::write(&path, "echo old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o750)).unwrap();
+
+        // when
+        edited_table().save_to(&path, &SaveOptions::new()).unwrap();
+
+        // then
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+    }
+
+    #[test]
+    fn should_fail_when_directory_is_missing() {
+        init_logger();
+        // given
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("missing").join("file.txt");
+
+        // when
+        let result = edited_table().save_to(&path, &SaveOptions::new());
+
+        // then
+        assert!(matches!(result, Err(SaveError::CreateTemp { .. })));
+    }
+
+    #[test]
+    fn should_clean_up_when_rename_fails() {
+        init_logger();
+        // given
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("inner"), "blocks rename").unwrap();
+
+        // when
+        let result = edited_table().save_to(&path, &SaveOptions::new());
+
+        // then
+        assert!(result.is_err());
+        assert_eq!(dir_entries(dir.path()), ["file.txt"]);
+    }
+}
@@ -0,0 +1,435 @@
+use crate::PieceTable;
+use std::collections::VecDeque;
+use std::ops::Range;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    text: String,
+    case_insensitive: bool,
+    whole_word: bool,
+}
+
+impl Pattern {
+    #[must_use]
+    pub fn new<S: Into<String>>(text: S) -> Self {
+        Self {
+            text: text.into(),
+            case_insensitive: false,
+            whole_word: false,
+        }
+    }
+
+    #[must_use]
+    pub fn case_insensitive(mut self) -> Self {
+        self.case_insensitive = true;
+        self
+    }
+
+    #[must_use]
+    pub fn whole_word(mut self) -> Self {
+        self.whole_word = true;
+        self
+    }
+
+    fn matcher(&self, direction: Direction) -> Matcher {
+        let mut needle: Vec<char> = self
+            .text
+            .chars()
+            .flat_map(|c| fold(c, self.case_insensitive))
+            .collect();
+        if direction == Direction::Backward {
+            needle.reverse();
+        }
+        Matcher::new(needle, self.case_insensitive, direction)
+    }
+}
+
+impl From<&str> for Pattern {
+    fn from(text: &str) -> Self {
+        Self::new(text)
+    }
+}
+
+impl From<String> for Pattern {
+    fn from(text: String) -> Self {
+        Self::new(text)
+    }
+}
+
+impl From<&Pattern> for Pattern {
+    fn from(pattern: &Pattern) -> Self {
+        pattern.clone()
+    }
+}
+
+impl PieceTable<'_> {
+    /// Returns the byte range of the first match of `pattern` starting at or after `from`. A
+    /// `from` inside of a char is rounded up to the next char.
+    ///
+    /// An empty pattern never matches.
+    pub fn find<P: Into<Pattern>>(&self, pattern: P, from: usize) -> Option<Range<usize>> {
+        self.matches_from(&pattern.into(), from).next()
+    }
+
+    /// Returns the byte range of the last match of `pattern` ending at or before `to`. A `to`
+    /// inside of a char is rounded down to the start of the char.
+    pub fn rfind<P: Into<Pattern>>(&self, pattern: P, to: usize) -> Option<Range<usize>> {
+        let pattern = pattern.into();
+        let mut matcher = pattern.matcher(Direction::Backward);
+        let mut to = to.min(self.len());
+        while !self.is_char_boundary(to) {
+            to -= 1;
+        }
+        for (idx, c) in self.char_indices(0..to).rev() {
+            let Some(candidate) = matcher.feed(idx, c) else {
+                continue;
+            };
+            if !pattern.whole_word || self.is_whole_word(&candidate) {
+                return Some(candidate);
+            }
+        }
+        None
+    }
+
+    /// Iterates over all non-overlapping matches of `pattern`, from the start of the text.
+    pub fn find_iter<P: Into<Pattern>>(
+        &self,
+        pattern: P,
+    ) -> impl Iterator<Item = Range<usize>> + '_ {
+        self.matches_from(&pattern.into(), 0)
+    }
+
+    pub(crate) fn matches_from(
+        &self,
+        pattern: &Pattern,
+        from: usize,
+    ) -> impl Iterator<Item = Range<usize>> + '_ {
+        let mut matcher = pattern.matcher(Direction::Forward);
+        let whole_word = pattern.whole_word;
+        let mut from = from.min(self.len());
+        while !self.is_char_boundary(from) {
+            from += 1;
+        }
+        self.char_indices(from..self.len())
+            .filter_map(move |(idx, c)| {
+                let candidate = matcher.feed(idx, c)?;
+                if whole_word && !self.is_whole_word(&candidate) {
+                    return None;
+                }
+                matcher.reset();
+                Some(candidate)
+            })
+    }
+
+    fn is_whole_word(&self, range: &Range<usize>) -> bool {
+        let before = self.char_indices(0..range.start).next_back();
+        let after = self.char_indices(range.end..self.len()).next();
+        !before.is_some_and(|(_, c)| is_word_char(c))
+            && !after.is_some_and(|(_, c)| is_word_char(c))
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn fold(c: char, case_insensitive: bool) -> Vec<char> {
+    if case_insensitive {
+        c.to_lowercase().collect()
+    } else {
+        vec![c]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Backward,
+}
+
+/// Position of a single folded char in the text. One char of the text can fold into several
+/// chars (e.g. 'İ'), so a match is only valid if it starts and ends on whole chars.
+#[derive(Debug, Clone)]
+struct Unit {
+    range: Range<usize>,
+    first: bool,
+    last: bool,
+}
+
+/// Knuth-Morris-Pratt matcher fed with one char at a time, so that it doesn't care about piece
+/// boundaries.
+#[derive(Debug)]
+struct Matcher {
+    needle: Vec<char>,
+    failure: Vec<usize>,
+    case_insensitive: bool,
+    direction: Direction,
+    state: usize,
+    window: VecDeque<Unit>,
+}
+
+impl Matcher {
+    fn new(needle: Vec<char>, case_insensitive: bool, direction: Direction) -> Self {
+        let mut failure = vec![0; needle.len()];
+        let mut k = 0;
+        for i in 1..needle.len() {
+            while k > 0 && needle[i] != needle[k] {
+                k = failure[k - 1];
+            }
+            if needle[i] == needle[k] {
+                k += 1;
+            }
+            failure[i] = k;
+        }
+        Self {
+            window: VecDeque::with_capacity(needle.len()),
+            needle,
+            failure,
+            case_insensitive,
+            direction,
+            state: 0,
+        }
+    }
+
+    fn feed(&mut self, idx: usize, c: char) -> Option<Range<usize>> {
+        if self.needle.is_empty() {
+            return None;
+        }
+        let mut units = fold(c, self.case_insensitive);
+        if self.direction == Direction::Backward {
+            units.reverse();
+        }
+        let count = units.len();
+        let mut found = None;
+        for (i, unit) in units.into_iter().enumerate() {
+            self.push_unit(Unit {
+                range: idx..idx + c.len_utf8(),
+                first: i == 0,
+                last: i == count - 1,
+            });
+            if let Some(candidate) = self.step(unit) {
+                found = Some(candidate);
+            }
+        }
+        found
+    }
+
+    fn push_unit(&mut self, unit: Unit) {
+        if self.window.len() == self.needle.len() {
+            self.window.pop_front();
+        }
+        self.window.push_back(unit);
+    }
+
+    fn step(&mut self, unit: char) -> Option<Range<usize>> {
+        while self.state > 0 && self.needle[self.state] != unit {
+            self.state = self.failure[self.state - 1];
+        }
+        if self.needle[self.state] == unit {
+            self.state += 1;
+        }
+        if self.state < self.needle.len() {
+            return None;
+        }
+        self.state = self.failure[self.state - 1];
+        let first = self.window.front()?;
+        let last = self.window.back()?;
+        if !first.first || !last.last {
+            return None;
+        }
+        let start = first.range.start.min(last.range.start);
+        let end = first.range.end.max(last.range.end);
+        Some(start..end)
+    }
+
+    fn reset(&mut self) {
+        self.state = 0;
+        self.window.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    fn table_with_pieces<'a>(original: &'a str, added: &str) -> PieceTable<'a> {
+        let mut table = PieceTable::from_text(original);
+        for c in added.chars() {
+            table.insert_char(c, table.len());
+        }
+        table
+    }
+
+    mod find {
+        use super::*;
+
+        #[test]
+        fn should_find_first_match_after_position() {
+            init_logger();
+            // given
+            let table = PieceTable::from_text("one two one two");
+
+            // when
+            let first = table.find("two", 0);
+            let second = table.find("two", 5);
+
+            // then
+            assert_eq!(first, Some(4..7));
+            assert_eq!(second, Some(12..15));
+        }
+
+        #[test]
+        fn should_round_positions_inside_chars() {
+            init_logger();
+            // given
+            let table = table_with_pieces("\u{17c}a\u{17c}", "a\u{17c}");
+
+            // when
+            let found = table.find("a", 1);
+            let rfound = table.rfind("a", 7);
+
+            // then
+            assert_eq!(found, Some(2..3));
+            assert_eq!(rfound, Some(5..6));
+        }
+
+        #[test]
+        fn should_find_match_spanning_pieces() {
+            init_logger();
+            // given
+            let table = table_with_pieces("some te", "xt here");
+            assert!(table.pieces.len() > 1);
+
+            // when
+            let found = table.find("text", 0);
+
+            // then
+            assert_eq!(found, Some(5..9));
+        }
+
+        #[test]
+        fn should_find_match_inserted_in_the_middle() {
+            init_logger();
+            // given
+            let mut table = PieceTable::from_text("ac ac");
+            table.insert_char('b', 4);
+
+            // when
+            let found = table.find("abc", 0);
+
+            // then
+            assert_eq!(table.project(), "ac abc");
+            assert_eq!(found, Some(3..6));
+        }
+
+        #[test]
+        fn should_not_find_missing_pattern() {
+            init_logger();
+            // given
+            let table = PieceTable::from_text("initial text");
+
+            // when
+            let found = table.find("missing", 0);
+            let empty = table.find("", 0);
+
+            // then
+            assert_eq!(found, None);
+            assert_eq!(empty, None);
+        }
+
+        #[test]
+        fn should_ignore_case_when_asked() {
+            init_logger();
+            // given
+            let table = table_with_pieces("Hello WO", "RLD");
+
+            // when
+            let sensitive = table.find("world", 0);
+            let insensitive = table.find(Pattern::new("world").case_insensitive(), 0);
+
+            // then
+            assert_eq!(sensitive, None);
+            assert_eq!(insensitive, Some(6..11));
+        }
+
+        #[test]
+        fn should_return_byte_ranges_for_multibyte_text() {
+            init_logger();
+            // given
+            let table = table_with_pieces("zażółć ", "gęślą jaźń");
+
+            // when
+            let found = table.find(Pattern::new("GĘŚLĄ").case_insensitive(), 0);
+
+            // then
+            assert_eq!(found, Some(11..19));
+            assert_eq!(&table.project()[11..19], "gęślą");
+        }
+
+        #[test]
+        fn should_only_match_whole_words_when_asked() {
+            init_logger();
+            // given
+            let table = PieceTable::from_text("cat concat cat_ cat.");
+
+            // when
+            let found: Vec<_> = table.find_iter(Pattern::new("cat").whole_word()).collect();
+
+            // then
+            assert_eq!(found, [0..3, 16..19]);
+        }
+    }
+
+    mod rfind {
+        use super::*;
+
+        #[test]
+        fn should_find_last_match_before_position() {
+            init_logger();
+            // given
+            let table = table_with_pieces("one two ", "one two");
+
+            // when
+            let last = table.rfind("one", table.len());
+            let before = table.rfind("one", 10);
+
+            // then
+            assert_eq!(last, Some(8..11));
+            assert_eq!(before, Some(0..3));
+        }
+
+        #[test]
+        fn should_respect_options_when_searching_backwards() {
+            init_logger();
+            // given
+            let table = PieceTable::from_text("Word words WORDS");
+
+            // when
+            let found = table.rfind(Pattern::new("word").case_insensitive().whole_word(), 16);
+
+            // then
+            assert_eq!(found, Some(0..4));
+        }
+    }
+
+    mod find_iter {
+        use super::*;
+
+        #[test]
+        fn should_return_non_overlapping_matches() {
+            init_logger();
+            // given
+            let table = table_with_pieces("aaa", "aa");
+
+            // when
+            let found: Vec<_> = table.find_iter("aa").collect();
+
+            // then
+            assert_eq!(found, [0..2, 2..4]);
+        }
+    }
+}
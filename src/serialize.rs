@@ -0,0 +1,221 @@
+//! Serialization of the whole editing session, so that undo history survives reopening a file.
+//!
+//! The table is stored as a versioned struct:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "original": "text of the file",
+//!   "addition": "everything ever inserted",
+//!   "buffers": ["text of buffers shared with other tables"],
+//!   "pieces": [{ "range": { "start": 0, "end": 4 }, "source": "Original" }],
+//!   "history": [...],
+//!   "redo": [...]
+//! }
+//! ```
+//!
+//! A loaded table owns its original text. On load, every piece (also those in the history) has
+//! to point at whole chars inside its buffer and the history has to be replayable on the pieces,
+//! otherwise deserialization fails.
+
+use crate::buffer::Buffer;
+use crate::history::Edit;
+use crate::{Piece, PieceTable};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct SessionRef<'t> {
+    version: u32,
+    original: &'t str,
+    addition: &'t str,
+    buffers: Vec<&'t str>,
+    pieces: &'t [Piece],
+    history: &'t [Edit],
+    redo: &'t [Edit],
+}
+
+#[derive(Deserialize)]
+struct Session {
+    version: u32,
+    original: String,
+    addition: String,
+    #[serde(default)]
+    buffers: Vec<String>,
+    pieces: Vec<Piece>,
+    history: Vec<Edit>,
+    redo: Vec<Edit>,
+}
+
+impl Serialize for PieceTable<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (history, redo) = self.settled_history();
+        SessionRef {
+            version: SCHEMA_VERSION,
+            original: self.original_buffer(),
+            addition: self.addition_buffer(),
+            buffers: self.buffers.iter().map(|buffer| &**buffer).collect(),
+            pieces: &self.pieces,
+            history: &history,
+            redo: &redo,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PieceTable<'_> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let session = Session::deserialize(deserializer)?;
+        if session.version != SCHEMA_VERSION {
+            return Err(D::Error::custom(format!(
+                "unsupported session version {} (expected {SCHEMA_VERSION})",
+                session.version
+            )));
+        }
+        let mut table = PieceTable::from_parts(
+            Buffer::Shared(session.original.into()),
+            session.addition,
+            session.pieces,
+            session.history,
+            session.redo,
+        );
+        table.buffers = session
+            .buffers
+            .into_iter()
+            .map(|buffer| Buffer::Shared(buffer.into()))
+            .collect();
+        table.validate().map_err(D::Error::custom)?;
+        Ok(table)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    fn edited_table(original: &str) -> PieceTable<'_> {
+        let mut table = PieceTable::from_text(original);
+        table.insert_str("very ", 5);
+        table.remove(0..1);
+        table.insert_char('S', 0);
+        table.insert_char('!', table.len());
+        table.undo();
+        table
+    }
+
+    #[test]
+    fn should_restore_text_and_history() {
+        init_logger();
+        // given
+        let table = edited_table("some \"quoted\" text");
+
+        // when
+        let json = serde_json::to_string(&table).unwrap();
+        let mut restored: PieceTable = serde_json::from_str(&json).unwrap();
+
+        // then
+        assert_eq!(restored, table);
+        assert_eq!(restored.pieces, table.pieces);
+        restored.redo();
+        assert_eq!(restored.project(), "Some very \"quoted\" text!");
+        restored.undo();
+        restored.undo();
+        restored.undo();
+        restored.undo();
+        assert_eq!(restored.project(), "some \"quoted\" text");
+    }
+
+    #[test]
+    fn should_restore_text_pasted_from_other_table() {
+        init_logger();
+        // given
+        let other = PieceTable::from_text("pasted ");
+        let mut table = PieceTable::from_text("text");
+        table.insert_from(&other, 0..7, 0);
+
+        // when
+        let json = serde_json::to_string(&table).unwrap();
+        let restored: PieceTable = serde_json::from_str(&json).unwrap();
+
+        // then
+        assert_eq!(restored.project(), "pasted text");
+        assert_eq!(restored.pieces, table.pieces);
+    }
+
+    #[test]
+    fn should_write_schema_version() {
+        init_logger();
+        // given
+        let table = PieceTable::from_text("text");
+
+        // when
+        let value = serde_json::to_value(&table).unwrap();
+
+        // then
+        assert_eq!(value["version"], json!(1));
+        assert_eq!(value["original"], json!("text"));
+    }
+
+    #[test]
+    fn should_reject_unknown_version() {
+        init_logger();
+        // given
+        let mut value = serde_json::to_value(PieceTable::from_text("text")).unwrap();
+        value["version"] = json!(2);
+
+        // when
+        let result = serde_json::from_value::<PieceTable>(value);
+
+        // then
+        assert!(result.unwrap_err().to_string().contains("version 2"));
+    }
+
+    #[test]
+    fn should_reject_piece_out_of_buffer() {
+        init_logger();
+        // given
+        let mut value = serde_json::to_value(PieceTable::from_text("text")).unwrap();
+        value["pieces"][0]["range"]["end"] = json!(5);
+
+        // when
+        let result = serde_json::from_value::<PieceTable>(value);
+
+        // then
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn should_reject_piece_splitting_char() {
+        init_logger();
+        // given
+        let mut value = serde_json::to_value(PieceTable::from_text("żółw")).unwrap();
+        value["pieces"][0]["range"]["end"] = json!(1);
+
+        // when
+        let result = serde_json::from_value::<PieceTable>(value);
+
+        // then
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn should_reject_history_not_matching_pieces() {
+        init_logger();
+        // given
+        let mut value = serde_json::to_value(edited_table("some text")).unwrap();
+        value["history"] = Value::Array(vec![value["history"][0].clone()]);
+
+        // when
+        let result = serde_json::from_value::<PieceTable>(value);
+
+        // then
+        assert!(result.unwrap_err().to_string().contains("undo history"));
+    }
+}
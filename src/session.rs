@@ -0,0 +1,472 @@
+//! Compact binary format of an editing session.
+//!
+//! The original text is not stored, only its hash, because it is the file the session was
+//! opened from. Numbers marked as varint are unsigned LEB128.
+//!
+//! ```text
+//! magic           4 bytes   b"POCS"
+//! version         1 byte    2
+//! original hash   8 bytes   FNV-1a 64 of the original text, little endian
+//! original len    varint
+//! addition len    varint    length of the uncompressed addition buffer
+//! addition        varint    length of the compressed data, followed by the data (raw DEFLATE)
+//! shared buffers  list of texts, varint length followed by the text
+//! pieces          list of pieces
+//! undo history    list of edits, oldest first
+//! redo history    list of edits, in the order they are popped from the end
+//!
+//! list            varint count, followed by the elements
+//! piece           1 byte source (0 - original, 1 - addition, 2 - shared buffer followed by its
+//!                 varint index), varint start, varint length
+//! edit            list of changes
+//! change          varint piece index, list of removed pieces, list of inserted pieces
+//! ```
+
+use crate::buffer::Buffer;
+use crate::history::{Change, Edit};
+use crate::{Piece, PieceTable, Source};
+use std::fmt;
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8; 4] = b"POCS";
+const VERSION: u8 = 2;
+const COMPRESSION_LEVEL: u8 = 6;
+
+impl<'a> PieceTable<'a> {
+    pub fn save_session<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.extend_from_slice(&hash(self.original_buffer().as_bytes()).to_le_bytes());
+        write_varint(&mut out, self.original_buffer().len());
+        let addition = self.addition_buffer().as_bytes();
+        let compressed = miniz_oxide::deflate::compress_to_vec(addition, COMPRESSION_LEVEL);
+        write_varint(&mut out, addition.len());
+        write_varint(&mut out, compressed.len());
+        out.extend_from_slice(&compressed);
+        write_varint(&mut out, self.buffers.len());
+        for buffer in &self.buffers {
+            write_str(&mut out, buffer);
+        }
+        write_pieces(&mut out, &self.pieces);
+        let (history, redo) = self.settled_history();
+        write_edits(&mut out, &history);
+        write_edits(&mut out, &redo);
+        writer.write_all(&out)?;
+        writer.flush()
+    }
+
+    /// Loads a session saved with [`PieceTable::save_session`] on top of `original`.
+    ///
+    /// Fails with [`SessionError::OriginalMismatch`] if `original` is not the text the session
+    /// was saved for, e.g. because the file was changed outside of the editor.
+    pub fn load_session<R: Read>(reader: R, original: &'a str) -> Result<Self, SessionError> {
+        let mut decoder = Decoder { reader };
+        let mut magic = [0; 4];
+        decoder.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(SessionError::NotASession);
+        }
+        let version = decoder.read_u8()?;
+        if version != VERSION {
+            return Err(SessionError::UnsupportedVersion(version));
+        }
+        let mut original_hash = [0; 8];
+        decoder.read_exact(&mut original_hash)?;
+        let original_len: usize = decoder.read_varint()?;
+        if u64::from_le_bytes(original_hash) != hash(original.as_bytes())
+            || original_len != original.len()
+        {
+            return Err(SessionError::OriginalMismatch);
+        }
+        let addition_len = decoder.read_varint()?;
+        let compressed_len: usize = decoder.read_varint()?;
+        let limit = u64::try_from(compressed_len)
+            .map_err(|_| SessionError::Corrupted("addition buffer is too big".into()))?;
+        let mut compressed = Vec::new();
+        (&mut decoder.reader)
+            .take(limit)
+            .read_to_end(&mut compressed)?;
+        if compressed.len() != compressed_len {
+            return Err(SessionError::Corrupted(
+                "addition buffer is truncated".into(),
+            ));
+        }
+        let addition =
+            miniz_oxide::inflate::decompress_to_vec_with_limit(&compressed, addition_len).map_err(
+                |e| SessionError::Corrupted(format!("can't decompress addition buffer: {e}")),
+            )?;
+        let addition = String::from_utf8(addition)
+            .map_err(|_| SessionError::Corrupted("addition buffer is not UTF-8".into()))?;
+        let mut buffers = Vec::new();
+        for _ in 0..decoder.read_varint()? {
+            buffers.push(Buffer::Shared(decoder.read_str()?.into()));
+        }
+        let mut table = Self::from_parts(
+            Buffer::Borrowed(original),
+            addition,
+            decoder.read_pieces()?,
+            decoder.read_edits()?,
+            decoder.read_edits()?,
+        );
+        table.buffers = buffers;
+        table.validate().map_err(SessionError::Corrupted)?;
+        Ok(table)
+    }
+}
+
+/// FNV-1a, which unlike the std hashers is guaranteed to stay the same between releases.
+pub(crate) fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+pub(crate) fn write_varint<T>(out: &mut Vec<u8>, value: T)
+where
+    u64: TryFrom<T>,
+{
+    let mut value = u64::try_from(value).ok().expect("numbers fit in u64");
+    loop {
+        let byte = u8::try_from(value & 0x7f).expect("seven bits fit in a byte");
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub(crate) fn write_str(out: &mut Vec<u8>, txt: &str) {
+    write_varint(out, txt.len());
+    out.extend_from_slice(txt.as_bytes());
+}
+
+pub(crate) fn write_pieces(out: &mut Vec<u8>, pieces: &[Piece]) {
+    write_varint(out, pieces.len());
+    for piece in pieces {
+        match piece.source {
+            Source::Original => out.push(0),
+            Source::Add => out.push(1),
+            Source::Shared(idx) => {
+                out.push(2);
+                write_varint(out, idx);
+            }
+        }
+        write_varint(out, piece.range.start);
+        write_varint(out, piece.len());
+    }
+}
+
+pub(crate) fn write_edits(out: &mut Vec<u8>, edits: &[Edit]) {
+    write_varint(out, edits.len());
+    for edit in edits {
+        write_edit(out, edit);
+    }
+}
+
+pub(crate) fn write_edit(out: &mut Vec<u8>, edit: &Edit) {
+    write_varint(out, edit.changes().len());
+    for change in edit.changes() {
+        write_varint(out, change.at());
+        write_pieces(out, change.removed());
+        write_pieces(out, change.inserted());
+    }
+}
+
+pub(crate) struct Decoder<R> {
+    pub(crate) reader: R,
+}
+
+impl<R: Read> Decoder<R> {
+    pub(crate) fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), SessionError> {
+        self.reader.read_exact(buf).map_err(|e| {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                SessionError::Corrupted("session is truncated".into())
+            } else {
+                SessionError::Io(e)
+            }
+        })
+    }
+
+    pub(crate) fn read_u8(&mut self) -> Result<u8, SessionError> {
+        let mut byte = [0];
+        self.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    pub(crate) fn read_varint<T: TryFrom<u64>>(&mut self) -> Result<T, SessionError> {
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_u8()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return T::try_from(value)
+                    .map_err(|_| SessionError::Corrupted("number is too big".into()));
+            }
+        }
+        Err(SessionError::Corrupted("number is too long".into()))
+    }
+
+    pub(crate) fn read_pieces(&mut self) -> Result<Vec<Piece>, SessionError> {
+        let count = self.read_varint()?;
+        // counts are not trusted for preallocation, a corrupted one could be huge
+        let mut pieces = Vec::new();
+        for _ in 0..count {
+            let source = match self.read_u8()? {
+                0 => Source::Original,
+                1 => Source::Add,
+                2 => Source::Shared(self.read_varint()?),
+                other => return Err(SessionError::Corrupted(format!("unknown source {other}"))),
+            };
+            let start: usize = self.read_varint()?;
+            let len = self.read_varint()?;
+            let end = start
+                .checked_add(len)
+                .ok_or_else(|| SessionError::Corrupted("piece is out of range".into()))?;
+            pieces.push(Piece::new(start..end, source));
+        }
+        Ok(pieces)
+    }
+
+    pub(crate) fn read_edits(&mut self) -> Result<Vec<Edit>, SessionError> {
+        let count = self.read_varint()?;
+        let mut edits = Vec::new();
+        for _ in 0..count {
+            edits.push(self.read_edit()?);
+        }
+        Ok(edits)
+    }
+
+    pub(crate) fn read_edit(&mut self) -> Result<Edit, SessionError> {
+        let count = self.read_varint()?;
+        let mut changes = Vec::new();
+        for _ in 0..count {
+            let at = self.read_varint()?;
+            let removed = self.read_pieces()?;
+            let inserted = self.read_pieces()?;
+            changes.push(Change::new(at, removed, inserted));
+        }
+        Ok(changes.into_iter().collect())
+    }
+
+    pub(crate) fn read_str(&mut self) -> Result<String, SessionError> {
+        let len: usize = self.read_varint()?;
+        let limit =
+            u64::try_from(len).map_err(|_| SessionError::Corrupted("text is too long".into()))?;
+        let mut bytes = Vec::new();
+        (&mut self.reader).take(limit).read_to_end(&mut bytes)?;
+        if bytes.len() != len {
+            return Err(SessionError::Corrupted("session is truncated".into()));
+        }
+        String::from_utf8(bytes).map_err(|_| SessionError::Corrupted("text is not UTF-8".into()))
+    }
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    Io(io::Error),
+    NotASession,
+    UnsupportedVersion(u8),
+    OriginalMismatch,
+    Corrupted(String),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "failed to read session: {e}"),
+            Self::NotASession => write!(f, "data is not a saved session or journal"),
+            Self::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "unsupported session version {version} (expected {VERSION})"
+                )
+            }
+            Self::OriginalMismatch => {
+                write!(
+                    f,
+                    "original text is different than the one the session was saved for"
+                )
+            }
+            Self::Corrupted(reason) => write!(f, "session is corrupted: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SessionError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    fn edited_table(original: &str) -> PieceTable<'_> {
+        let mut table = PieceTable::from_text(original);
+        table.insert_str("very ", 5);
+        table.replace_all("e", "ę", None);
+        table.insert_char('!', table.len());
+        table.undo();
+        table
+    }
+
+    fn saved(table: &PieceTable) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        table.save_session(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn should_restore_text_and_history() {
+        init_logger();
+        // given
+        let original = "some text here";
+        let table = edited_table(original);
+        let bytes = saved(&table);
+
+        // when
+        let mut loaded = PieceTable::load_session(bytes.as_slice(), original).unwrap();
+
+        // then
+        assert_eq!(loaded.pieces, table.pieces);
+        assert_eq!(loaded.project(), "somę vęry tęxt hęrę");
+        loaded.redo();
+        assert_eq!(loaded.project(), "somę vęry tęxt hęrę!");
+        loaded.undo();
+        loaded.undo();
+        loaded.undo();
+        assert_eq!(loaded.project(), original);
+    }
+
+    #[test]
+    fn should_restore_text_pasted_from_other_table() {
+        init_logger();
+        // given
+        let original = "text";
+        let other = PieceTable::from_text("pasted ");
+        let mut table = PieceTable::from_text(original);
+        table.insert_from(&other, 0..7, 0);
+        let bytes = saved(&table);
+
+        // when
+        let mut loaded = PieceTable::load_session(bytes.as_slice(), original).unwrap();
+
+        // then
+        assert_eq!(loaded.project(), "pasted text");
+        loaded.undo();
+        assert_eq!(loaded.project(), original);
+    }
+
+    #[test]
+    fn should_start_with_magic_and_version() {
+        init_logger();
+        // given
+        let table = PieceTable::from_text("text");
+
+        // when
+        let bytes = saved(&table);
+
+        // then
+        assert_eq!(&bytes[..5], b"POCS\x02");
+    }
+
+    #[test]
+    fn should_compress_addition_buffer() {
+        init_logger();
+        // given
+        let mut table = PieceTable::default();
+        table.insert_str(&"repeated text ".repeat(1000), 0);
+
+        // when
+        let bytes = saved(&table);
+
+        // then
+        assert!(bytes.len() < 500);
+    }
+
+    #[test]
+    fn should_refuse_different_original() {
+        init_logger();
+        // given
+        let table = edited_table("some text here");
+        let bytes = saved(&table);
+
+        // when
+        let result = PieceTable::load_session(bytes.as_slice(), "some text there");
+
+        // then
+        assert!(matches!(result, Err(SessionError::OriginalMismatch)));
+    }
+
+    #[test]
+    fn should_refuse_other_data() {
+        init_logger();
+        // given
+        let bytes = b"{\"version\": 1}";
+
+        // when
+        let result = PieceTable::load_session(bytes.as_slice(), "");
+
+        // then
+        assert!(matches!(result, Err(SessionError::NotASession)));
+    }
+
+    #[test]
+    fn should_refuse_unknown_version() {
+        init_logger();
+        // given
+        let mut bytes = saved(&PieceTable::from_text("text"));
+        bytes[4] = 3;
+
+        // when
+        let result = PieceTable::load_session(bytes.as_slice(), "text");
+
+        // then
+        assert!(matches!(result, Err(SessionError::UnsupportedVersion(3))));
+    }
+
+    #[test]
+    fn should_refuse_previous_version() {
+        init_logger();
+        // given
+        let mut bytes = saved(&PieceTable::from_text("text"));
+        bytes[4] = 1;
+
+        // when
+        let result = PieceTable::load_session(bytes.as_slice(), "text");
+
+        // then
+        assert!(matches!(result, Err(SessionError::UnsupportedVersion(1))));
+    }
+
+    #[test]
+    fn should_refuse_truncated_session() {
+        init_logger();
+        // given
+        let original = "some text here";
+        let bytes = saved(&edited_table(original));
+
+        // when
+        let result = PieceTable::load_session(&bytes[..bytes.len() - 3], original);
+
+        // then
+        assert!(matches!(result, Err(SessionError::Corrupted(_))));
+    }
+}
@@ -0,0 +1,284 @@
+use crate::buffer::Buffer;
+use crate::version::Versions;
+use crate::{slice_pieces, Piece, PieceTable, Source};
+
+impl<'a> PieceTable<'a> {
+    /// Moves the text of `other` to the end of this table, as a single edit.
+    ///
+    /// The pieces of `other` are moved over rather than its text: its original text and shared
+    /// buffers are shared with this table, and its addition buffer becomes a shared buffer too.
+    /// The history of `other` is dropped, as is the authorship of the text inserted into it.
+    pub fn append(&mut self, other: PieceTable<'a>) {
+        let pieces = self.adopt(other);
+        if !pieces.is_empty() {
+            let end = self.pieces.len();
+            self.splice_pieces(end..end, pieces);
+        }
+    }
+
+    /// Splits the text at byte index `at`, keeping `[0, at)` in this table, as a single edit, and
+    /// returning a new table with `[at, len)`.
+    ///
+    /// The new table has no history, but keeps the authorship of the text. It shares the original
+    /// text and the shared buffers with this one, and the addition buffer becomes a shared buffer
+    /// of the new table if some of the text was inserted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `at > len`.
+    #[must_use = "use `remove` to drop the end of the text"]
+    pub fn split_off(&mut self, at: usize) -> PieceTable<'a> {
+        let len = self.len();
+        assert!(
+            at <= len,
+            "split index (is {at}) should be <= len (is {len})"
+        );
+        let mut pieces = slice_pieces(&self.pieces, at..len);
+        let mut buffers = self.buffers.clone();
+        let idx = buffers.len();
+        if pieces.iter().any(|piece| piece.source == Source::Add) {
+            buffers.push(Buffer::Shared(self.addition_buffer.as_str().into()));
+            for piece in pieces
+                .iter_mut()
+                .filter(|piece| piece.source == Source::Add)
+            {
+                piece.source = Source::Shared(idx);
+            }
+        }
+        let mut tail = PieceTable::from_parts(
+            self.original_buffer.clone(),
+            String::new(),
+            pieces,
+            Vec::new(),
+            Vec::new(),
+        );
+        tail.buffers = buffers;
+        tail.authors = self.authors.shared_as(idx);
+        if at < len {
+            self.remove_pieces(at..len);
+        }
+        tail
+    }
+
+    /// Joins the texts of `tables` into a new table without history, moving their pieces like
+    /// [`append`](Self::append) does. The first table keeps its buffers and authorship.
+    #[must_use]
+    pub fn concat<I: IntoIterator<Item = PieceTable<'a>>>(tables: I) -> PieceTable<'a> {
+        let mut tables = tables.into_iter();
+        let Some(first) = tables.next() else {
+            return PieceTable::default();
+        };
+        let mut table = PieceTable::from_parts(
+            first.original_buffer,
+            first.addition_buffer,
+            first.pieces,
+            Vec::new(),
+            Vec::new(),
+        );
+        table.buffers = first.buffers;
+        table.authors = first.authors;
+        for other in tables {
+            let pieces = table.adopt(other);
+            table.pieces.extend(pieces);
+        }
+        table.versions = Versions::new(&table.pieces);
+        table
+    }
+
+    /// Returns the non-empty pieces of `other` pointing into the buffers of this table, to
+    /// which the buffers of `other` are added.
+    fn adopt(&mut self, other: PieceTable<'a>) -> Vec<Piece> {
+        let mut addition = None;
+        let mut pieces = Vec::new();
+        for piece in other.pieces.into_iter().filter(|piece| piece.len() > 0) {
+            let source = match piece.source {
+                Source::Original => self.share(&other.original_buffer),
+                Source::Shared(idx) => self.share(&other.buffers[idx]),
+                Source::Add => addition
+                    .get_or_insert_with(|| {
+                        self.share(&Buffer::Shared(other.addition_buffer.as_str().into()))
+                    })
+                    .clone(),
+            };
+            pieces.push(Piece::new(piece.range, source));
+        }
+        pieces
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    #[test]
+    fn should_append_pieces_of_other_table() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("first\n");
+        let mut other = PieceTable::from_text("second\n");
+        other.insert_str("the ", 0);
+
+        // when
+        table.append(other);
+
+        // then
+        assert_eq!(table.project(), "first\nthe second\n");
+        assert_eq!(table.addition_buffer, "");
+        assert_eq!(
+            table.pieces,
+            [
+                Piece::new(0..6, Source::Original),
+                Piece::new(0..4, Source::Shared(0)),
+                Piece::new(0..7, Source::Shared(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_undo_append_at_once() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("text");
+        let mut other = PieceTable::from_text(" more");
+        other.insert_str(" and", 0);
+
+        // when
+        table.append(other);
+        let appended = table.project();
+        table.undo();
+
+        // then
+        assert_eq!(appended, "text and more");
+        assert_eq!(table.project(), "text");
+    }
+
+    #[test]
+    fn should_split_text_in_two_tables() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("head\ntail\n");
+        table.insert_str("new ", 5);
+
+        // when
+        let mut tail = table.split_off(7);
+        tail.insert_char('!', 0);
+        table.insert_char('?', 7);
+
+        // then
+        assert_eq!(table.project(), "head\nne?");
+        assert_eq!(tail.project(), "!w tail\n");
+        tail.undo();
+        assert_eq!(tail.project(), "w tail\n");
+        table.undo();
+        table.undo();
+        assert_eq!(table.project(), "head\nnew tail\n");
+    }
+
+    #[test]
+    fn should_not_copy_addition_buffer_for_original_text() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("head tail");
+        table.insert_str("big ", 0);
+
+        // when
+        let tail = table.split_off(9);
+
+        // then
+        assert_eq!(tail.project(), "tail");
+        assert!(tail.buffers.is_empty());
+    }
+
+    #[test]
+    fn should_keep_authorship_of_split_off_text() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("head tail");
+        table.set_author(Some(7));
+        table.insert_str("new ", 5);
+
+        // when
+        let mut tail = table.split_off(7);
+        tail.set_author(Some(8));
+        tail.insert_char('!', 0);
+
+        // then
+        assert_eq!(tail.project(), "!w tail");
+        let blame: Vec<_> = tail
+            .blame(0..7)
+            .unwrap()
+            .into_iter()
+            .map(|part| (part.range, part.authorship.map(|a| a.author)))
+            .collect();
+        assert_eq!(blame, vec![(0..1, Some(8)), (1..3, Some(7)), (3..7, None)]);
+    }
+
+    #[test]
+    fn should_split_off_empty_end() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("text");
+
+        // when
+        let tail = table.split_off(4);
+
+        // then
+        assert!(tail.is_empty());
+        assert_eq!(table.project(), "text");
+        assert!(!table.is_dirty());
+    }
+
+    #[test]
+    fn should_concat_tables() {
+        init_logger();
+        // given
+        let mut first = PieceTable::from_text("one ");
+        first.insert_str("1 ", 4);
+        let second = PieceTable::from_text("two ");
+        let mut third = PieceTable::from_text("three");
+        third.remove(0..2);
+
+        // when
+        let mut table = PieceTable::concat([first, second, third]);
+
+        // then
+        assert_eq!(table.project(), "one 1 two ree");
+        assert_eq!(table.buffers.len(), 2);
+        table.undo();
+        assert_eq!(table.project(), "one 1 two ree");
+    }
+
+    #[test]
+    fn should_share_original_text_when_joining_split_table_back() {
+        init_logger();
+        // given
+        let original = "some text";
+        let mut table = PieceTable::from_text(original);
+        let tail = table.split_off(4);
+
+        // when
+        let joined = PieceTable::concat([table, tail]);
+
+        // then
+        assert_eq!(joined.project(), original);
+        assert_eq!(joined.buffers.len(), 1);
+        assert!(joined.buffers[0].is_same(&joined.original_buffer));
+    }
+
+    #[test]
+    fn should_concat_nothing_into_empty_table() {
+        init_logger();
+        // given
+        let tables: Vec<PieceTable> = Vec::new();
+
+        // when
+        let table = PieceTable::concat(tables);
+
+        // then
+        assert!(table.is_empty());
+    }
+}
@@ -0,0 +1,1182 @@
+//! Collaborative editing over TCP, exchanging the updates of [`PieceTable::enable_crdt`].
+//!
+//! The server keeps the authoritative table and a log of the updates it received. Every update
+//! of a replica gets the next sequence number of that replica, and a version vector tells how
+//! many updates of each replica were seen. A client connecting, or reconnecting after losing the
+//! connection, sends its version vector and gets the updates it missed, then resends its own
+//! updates the server didn't get.
+//!
+//! The log is compacted into a snapshot of the replicated text once it gets long. A client which
+//! missed updates of the snapshot gets the whole snapshot, which it merges with its own text.
+//!
+//! Messages are framed by their varint length:
+//!
+//! ```text
+//! hello (0)       replica, hash of the starting text, version vector
+//! welcome (1)     version vector of the server
+//! update (2)      replica, sequence number, update
+//! refused (3)     reason (varint length + text)
+//! snapshot (4)    version vector, count, updates
+//!
+//! version vector  count, then replica and number of updates for each replica
+//! update          0 (insert), id, left origin, right origin, text
+//!                 1 (delete), id, length
+//! id              replica, counter
+//! origin          0 if none, or 1 and id
+//! ```
+
+use crate::buffer::Buffer;
+use crate::crdt::{ItemId, Update};
+use crate::session::{self, Decoder, SessionError};
+use crate::{Piece, PieceTable, Source};
+use log::{debug, warn};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::num::NonZeroU64;
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const HELLO: u8 = 0;
+const WELCOME: u8 = 1;
+const UPDATE: u8 = 2;
+const REFUSED: u8 = 3;
+const SNAPSHOT: u8 = 4;
+
+/// Number of updates in the log of the server above which it is compacted into a snapshot.
+const LOG_LIMIT: usize = 1024;
+/// Number of messages waiting to be sent to a client above which it is dropped, to reconnect
+/// and catch up once it keeps up again.
+const QUEUE_LIMIT: usize = 1024;
+
+/// How long a replica which reconnects waits for its previous connection to be noticed closed.
+const RECONNECT_GRACE: Duration = Duration::from_millis(500);
+
+/// Number of updates seen from each replica.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VersionVector(BTreeMap<u64, u64>);
+
+impl VersionVector {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns how many updates of `replica` were seen, which is also the sequence number of the
+    /// next one.
+    #[must_use]
+    pub fn get(&self, replica: u64) -> u64 {
+        self.0.get(&replica).copied().unwrap_or(0)
+    }
+
+    fn increment(&mut self, replica: u64) {
+        *self.0.entry(replica).or_default() += 1;
+    }
+
+    /// Tells if all the updates seen by `other` were seen.
+    fn covers(&self, other: &VersionVector) -> bool {
+        other
+            .iter()
+            .all(|(replica, seen)| self.get(replica) >= seen)
+    }
+
+    fn merge(&mut self, other: &VersionVector) {
+        for (replica, seen) in other.iter() {
+            let entry = self.0.entry(replica).or_default();
+            *entry = (*entry).max(seen);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.0.iter().map(|(&replica, &seen)| (replica, seen))
+    }
+}
+
+#[derive(Debug)]
+pub enum SyncError {
+    Io(io::Error),
+    /// The connection was closed by the other side.
+    Disconnected,
+    /// The server started from a different text than the client.
+    Refused(String),
+    /// The other side sent something which isn't a valid message.
+    Protocol(String),
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "connection failed: {e}"),
+            Self::Disconnected => write!(f, "connection was closed"),
+            Self::Refused(reason) => write!(f, "server refused the connection: {reason}"),
+            Self::Protocol(reason) => write!(f, "invalid message: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for SyncError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SyncError {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            Self::Disconnected
+        } else {
+            Self::Io(e)
+        }
+    }
+}
+
+impl From<SessionError> for SyncError {
+    fn from(e: SessionError) -> Self {
+        match e {
+            SessionError::Io(e) => e.into(),
+            other => Self::Protocol(other.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Message {
+    Hello {
+        replica: u64,
+        base: u64,
+        version: VersionVector,
+    },
+    Welcome {
+        version: VersionVector,
+    },
+    Update {
+        replica: u64,
+        seq: u64,
+        update: Update,
+    },
+    Refused {
+        reason: String,
+    },
+    Snapshot {
+        version: VersionVector,
+        updates: Vec<Update>,
+    },
+}
+
+impl Message {
+    fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        match self {
+            Self::Hello {
+                replica,
+                base,
+                version,
+            } => {
+                payload.push(HELLO);
+                session::write_varint(&mut payload, *replica);
+                payload.extend_from_slice(&base.to_le_bytes());
+                write_version(&mut payload, version);
+            }
+            Self::Welcome { version } => {
+                payload.push(WELCOME);
+                write_version(&mut payload, version);
+            }
+            Self::Update {
+                replica,
+                seq,
+                update,
+            } => {
+                payload.push(UPDATE);
+                session::write_varint(&mut payload, *replica);
+                session::write_varint(&mut payload, *seq);
+                write_update(&mut payload, update);
+            }
+            Self::Refused { reason } => {
+                payload.push(REFUSED);
+                session::write_str(&mut payload, reason);
+            }
+            Self::Snapshot { version, updates } => {
+                payload.push(SNAPSHOT);
+                write_version(&mut payload, version);
+                session::write_varint(&mut payload, updates.len());
+                for update in updates {
+                    write_update(&mut payload, update);
+                }
+            }
+        }
+        let mut frame = Vec::with_capacity(payload.len() + 4);
+        session::write_varint(&mut frame, payload.len());
+        frame.extend_from_slice(&payload);
+        frame
+    }
+
+    fn decode(payload: &[u8]) -> Result<Self, SyncError> {
+        let mut decoder = Decoder { reader: payload };
+        let message = match decoder.read_u8()? {
+            HELLO => {
+                let replica = decoder.read_varint()?;
+                let mut base = [0; 8];
+                decoder.read_exact(&mut base)?;
+                Self::Hello {
+                    replica,
+                    base: u64::from_le_bytes(base),
+                    version: read_version(&mut decoder)?,
+                }
+            }
+            WELCOME => Self::Welcome {
+                version: read_version(&mut decoder)?,
+            },
+            UPDATE => Self::Update {
+                replica: decoder.read_varint()?,
+                seq: decoder.read_varint()?,
+                update: read_update(&mut decoder)?,
+            },
+            REFUSED => Self::Refused {
+                reason: decoder.read_str()?,
+            },
+            SNAPSHOT => {
+                let version = read_version(&mut decoder)?;
+                let count = decoder.read_varint()?;
+                let mut updates = Vec::new();
+                for _ in 0..count {
+                    updates.push(read_update(&mut decoder)?);
+                }
+                Self::Snapshot { version, updates }
+            }
+            kind => return Err(SyncError::Protocol(format!("unknown message kind {kind}"))),
+        };
+        if !decoder.reader.is_empty() {
+            return Err(SyncError::Protocol("message is too long".into()));
+        }
+        Ok(message)
+    }
+
+    fn read_from(reader: &mut impl Read) -> Result<Self, SyncError> {
+        let len: usize = Decoder {
+            reader: &mut *reader,
+        }
+        .read_varint()
+        .map_err(|e| match e {
+            // a truncated length means the connection was closed between messages
+            SessionError::Corrupted(_) => SyncError::Disconnected,
+            other => other.into(),
+        })?;
+        let limit =
+            u64::try_from(len).map_err(|_| SyncError::Protocol("message is too long".into()))?;
+        let mut payload = Vec::new();
+        reader.take(limit).read_to_end(&mut payload)?;
+        if payload.len() != len {
+            return Err(SyncError::Disconnected);
+        }
+        Self::decode(&payload)
+    }
+
+    fn write_to(&self, writer: &mut impl Write) -> Result<(), SyncError> {
+        writer.write_all(&self.encode())?;
+        Ok(())
+    }
+}
+
+fn write_version(out: &mut Vec<u8>, version: &VersionVector) {
+    session::write_varint(out, version.0.len());
+    for (replica, seen) in version.iter() {
+        session::write_varint(out, replica);
+        session::write_varint(out, seen);
+    }
+}
+
+fn read_version<R: Read>(decoder: &mut Decoder<R>) -> Result<VersionVector, SyncError> {
+    let count = decoder.read_varint()?;
+    let mut version = VersionVector::new();
+    for _ in 0..count {
+        let replica = decoder.read_varint()?;
+        version.0.insert(replica, decoder.read_varint()?);
+    }
+    Ok(version)
+}
+
+fn write_id(out: &mut Vec<u8>, id: ItemId) {
+    session::write_varint(out, id.replica);
+    session::write_varint(out, id.counter);
+}
+
+fn read_id<R: Read>(decoder: &mut Decoder<R>) -> Result<ItemId, SyncError> {
+    Ok(ItemId {
+        replica: decoder.read_varint()?,
+        counter: decoder.read_varint()?,
+    })
+}
+
+fn write_origin(out: &mut Vec<u8>, origin: Option<ItemId>) {
+    match origin {
+        Some(id) => {
+            out.push(1);
+            write_id(out, id);
+        }
+        None => out.push(0),
+    }
+}
+
+fn read_origin<R: Read>(decoder: &mut Decoder<R>) -> Result<Option<ItemId>, SyncError> {
+    match decoder.read_u8()? {
+        0 => Ok(None),
+        1 => Ok(Some(read_id(decoder)?)),
+        other => Err(SyncError::Protocol(format!("unknown origin flag {other}"))),
+    }
+}
+
+fn write_update(out: &mut Vec<u8>, update: &Update) {
+    match update {
+        Update::Insert {
+            id,
+            origin_left,
+            origin_right,
+            text,
+        } => {
+            out.push(0);
+            write_id(out, *id);
+            write_origin(out, *origin_left);
+            write_origin(out, *origin_right);
+            session::write_str(out, text);
+        }
+        Update::Delete { id, len } => {
+            out.push(1);
+            write_id(out, *id);
+            session::write_varint(out, *len);
+        }
+    }
+}
+
+fn read_update<R: Read>(decoder: &mut Decoder<R>) -> Result<Update, SyncError> {
+    match decoder.read_u8()? {
+        0 => Ok(Update::Insert {
+            id: read_id(decoder)?,
+            origin_left: read_origin(decoder)?,
+            origin_right: read_origin(decoder)?,
+            text: decoder.read_str()?,
+        }),
+        1 => Ok(Update::Delete {
+            id: read_id(decoder)?,
+            len: decoder.read_varint()?,
+        }),
+        other => Err(SyncError::Protocol(format!("unknown update kind {other}"))),
+    }
+}
+
+/// Server holding the authoritative version of the text.
+#[derive(Clone)]
+pub struct SyncServer {
+    state: Arc<Mutex<ServerState>>,
+}
+
+struct ServerState {
+    table: PieceTable<'static>,
+    base: u64,
+    /// Updates bringing a replica which starts from the text to `snapshot_version`.
+    snapshot: Vec<Update>,
+    snapshot_version: VersionVector,
+    /// Updates received since the snapshot, in the order they were applied.
+    log: Vec<(u64, u64, Update)>,
+    log_limit: usize,
+    version: VersionVector,
+    clients: Vec<Client>,
+    next_client: usize,
+}
+
+impl ServerState {
+    /// Returns the frames bringing a client which saw `version` up to date.
+    fn catch_up(&self, version: &VersionVector) -> Vec<u8> {
+        let mut frames = Message::Welcome {
+            version: self.version.clone(),
+        }
+        .encode();
+        if !version.covers(&self.snapshot_version) {
+            frames.extend(
+                Message::Snapshot {
+                    version: self.snapshot_version.clone(),
+                    updates: self.snapshot.clone(),
+                }
+                .encode(),
+            );
+        }
+        for (from, seq, update) in &self.log {
+            if *seq >= version.get(*from) {
+                frames.extend(
+                    Message::Update {
+                        replica: *from,
+                        seq: *seq,
+                        update: update.clone(),
+                    }
+                    .encode(),
+                );
+            }
+        }
+        frames
+    }
+
+    fn is_connected(&self, replica: u64) -> bool {
+        self.clients.iter().any(|client| client.replica == replica)
+    }
+
+    fn compact(&mut self) {
+        if self.log.len() > self.log_limit {
+            self.snapshot = self.table.crdt_snapshot();
+            self.snapshot_version = self.version.clone();
+            self.log.clear();
+        }
+    }
+}
+
+/// Connected client. Its messages are queued for a thread of its own to write them, so that a
+/// slow client doesn't hold up the others.
+struct Client {
+    id: usize,
+    replica: u64,
+    frames: SyncSender<Arc<[u8]>>,
+    stream: TcpStream,
+}
+
+impl Client {
+    /// Queues `frame`, or disconnects the client and returns `false` if it doesn't keep up.
+    fn send(&self, frame: &Arc<[u8]>) -> bool {
+        match self.frames.try_send(Arc::clone(frame)) {
+            Ok(()) => true,
+            Err(e) => {
+                let reason = match e {
+                    TrySendError::Full(_) => "it doesn't keep up",
+                    TrySendError::Disconnected(_) => "writing failed",
+                };
+                warn!("dropping client {}: {reason}", self.id);
+                let _ = self.stream.shutdown(Shutdown::Both);
+                false
+            }
+        }
+    }
+}
+
+impl SyncServer {
+    /// Creates a server for replicas starting from `txt`.
+    #[must_use]
+    pub fn new(txt: String) -> Self {
+        let base = session::hash(txt.as_bytes());
+        let pieces = vec![Piece::new(0..txt.len(), Source::Original)];
+        let mut table = PieceTable::from_parts(
+            Buffer::Shared(txt.into()),
+            String::new(),
+            pieces,
+            Vec::new(),
+            Vec::new(),
+        );
+        table.enable_crdt(NonZeroU64::MAX);
+        Self {
+            state: Arc::new(Mutex::new(ServerState {
+                table,
+                base,
+                snapshot: Vec::new(),
+                snapshot_version: VersionVector::new(),
+                log: Vec::new(),
+                log_limit: LOG_LIMIT,
+                version: VersionVector::new(),
+                clients: Vec::new(),
+                next_client: 0,
+            })),
+        }
+    }
+
+    /// Accepts clients on `listener`, each in its own thread, until accepting fails.
+    pub fn serve(&self, listener: &TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let server = self.clone();
+            thread::spawn(move || {
+                let peer = stream.peer_addr().ok();
+                if let Err(e) = server.handle(stream) {
+                    debug!("client {peer:?} disconnected: {e}");
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the current text.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a thread serving a client panicked while changing the text.
+    #[must_use]
+    pub fn text(&self) -> String {
+        self.lock().table.project()
+    }
+
+    /// # Panics
+    ///
+    /// Panics if a thread serving a client panicked while changing the text.
+    #[must_use]
+    pub fn version(&self) -> VersionVector {
+        self.lock().version.clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ServerState> {
+        // a panic may have left the table half changed, which must not be sent to the clients
+        self.state
+            .lock()
+            .expect("a thread serving a client should not panic")
+    }
+
+    fn is_connected(&self, replica: u64) -> bool {
+        self.lock().is_connected(replica)
+    }
+
+    fn handle(&self, mut stream: TcpStream) -> Result<(), SyncError> {
+        let Message::Hello {
+            replica,
+            base,
+            version,
+        } = Message::read_from(&mut stream)?
+        else {
+            return Err(SyncError::Protocol("expected hello".into()));
+        };
+        if base != self.lock().base {
+            return refuse(
+                &mut stream,
+                "client started from a different text",
+                format!("replica {replica} has another text"),
+            );
+        }
+        // replica 0 is the one of the base text, and the server edits as the last one
+        if replica == 0 || replica == u64::MAX {
+            return refuse(
+                &mut stream,
+                "replica is reserved",
+                format!("replica {replica} is reserved"),
+            );
+        }
+        let deadline = Instant::now() + RECONNECT_GRACE;
+        while self.is_connected(replica) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        let (frames, queue) = mpsc::sync_channel::<Arc<[u8]>>(QUEUE_LIMIT);
+        let mut output = stream.try_clone()?;
+        thread::spawn(move || {
+            for frame in queue {
+                if let Err(e) = output.write_all(&frame) {
+                    debug!("failed to write to client: {e}");
+                    let _ = output.shutdown(Shutdown::Both);
+                    return;
+                }
+            }
+        });
+        let writer = stream.try_clone()?;
+        let client = {
+            let mut state = self.lock();
+            if state.is_connected(replica) {
+                return refuse(
+                    &mut stream,
+                    "replica is already connected",
+                    format!("replica {replica} is already connected"),
+                );
+            }
+            let client = Client {
+                id: state.next_client,
+                replica,
+                frames,
+                stream: writer,
+            };
+            state.next_client += 1;
+            // queued under the lock, so that no update gets between the catch-up and broadcasts
+            if !client.send(&state.catch_up(&version).into()) {
+                return Err(SyncError::Disconnected);
+            }
+            let id = client.id;
+            state.clients.push(client);
+            id
+        };
+        debug!("replica {replica} connected as client {client}");
+
+        let result = self.receive(client, replica, &mut stream);
+        self.lock().clients.retain(|other| other.id != client);
+        result
+    }
+
+    /// Applies and broadcasts the updates sent by `client`, which is `replica`.
+    fn receive(
+        &self,
+        client: usize,
+        replica: u64,
+        stream: &mut TcpStream,
+    ) -> Result<(), SyncError> {
+        loop {
+            let message = Message::read_from(stream)?;
+            let Message::Update {
+                replica: from,
+                seq,
+                update,
+            } = &message
+            else {
+                return Err(SyncError::Protocol("expected update".into()));
+            };
+            if *from != replica
+                || matches!(update, Update::Insert { id, .. } if id.replica != replica)
+            {
+                return Err(SyncError::Protocol(format!(
+                    "replica {replica} sent an update of another replica"
+                )));
+            }
+            let mut state = self.lock();
+            let expected = state.version.get(replica);
+            if *seq < expected {
+                // already received before reconnecting
+                continue;
+            }
+            if *seq > expected {
+                return Err(SyncError::Protocol(format!(
+                    "update {seq} of replica {replica} sent before update {expected}"
+                )));
+            }
+            state
+                .table
+                .apply_update_now(update.clone())
+                .map_err(|reason| SyncError::Protocol(format!("invalid update: {reason}")))?;
+            state.log.push((replica, *seq, update.clone()));
+            state.version.increment(replica);
+            state.compact();
+            let frame: Arc<[u8]> = message.encode().into();
+            state
+                .clients
+                .retain(|other| other.id == client || other.send(&frame));
+        }
+    }
+}
+
+/// Tells the client why it is refused, failing with `error`.
+fn refuse(stream: &mut TcpStream, reason: &str, error: String) -> Result<(), SyncError> {
+    Message::Refused {
+        reason: reason.into(),
+    }
+    .write_to(stream)?;
+    Err(SyncError::Refused(error))
+}
+
+/// Table edited together with other clients of a [`SyncServer`].
+pub struct SyncClient<'a> {
+    table: PieceTable<'a>,
+    replica: u64,
+    base: u64,
+    addr: SocketAddr,
+    connection: Option<Connection>,
+    version: VersionVector,
+    /// Own updates the server may not have, with their sequence numbers.
+    unacked: Vec<(u64, Update)>,
+    /// Sequence number of the first own update not sent on the current connection.
+    sent_seq: u64,
+}
+
+struct Connection {
+    stream: TcpStream,
+    received: Receiver<Result<Message, SyncError>>,
+}
+
+impl<'a> SyncClient<'a> {
+    /// Creates a client editing `table` as `replica`. The text of `table` has to be the one the
+    /// server started from. Nothing is sent until [`SyncClient::sync`].
+    #[must_use]
+    pub fn new(addr: SocketAddr, mut table: PieceTable<'a>, replica: NonZeroU64) -> Self {
+        let base = session::hash(table.project().as_bytes());
+        table.enable_crdt(replica);
+        Self {
+            table,
+            replica: replica.get(),
+            base,
+            addr,
+            connection: None,
+            version: VersionVector::new(),
+            unacked: Vec::new(),
+            sent_seq: 0,
+        }
+    }
+
+    #[must_use]
+    pub fn table(&self) -> &PieceTable<'a> {
+        &self.table
+    }
+
+    /// Gives access to the table for editing; the edits are sent by the next
+    /// [`SyncClient::sync`].
+    pub fn table_mut(&mut self) -> &mut PieceTable<'a> {
+        &mut self.table
+    }
+
+    /// Returns how many updates of each replica, including its own, the table contains.
+    #[must_use]
+    pub fn version(&self) -> &VersionVector {
+        &self.version
+    }
+
+    #[must_use]
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    /// Sends the local edits and applies the updates received since the last call, without
+    /// waiting for more.
+    ///
+    /// Connects first if there is no connection, e.g. because the previous one failed. The edits
+    /// made in the meantime are kept, and exchanged once connected again.
+    pub fn sync(&mut self) -> Result<(), SyncError> {
+        for update in self.table.take_updates() {
+            self.unacked.push((self.version.get(self.replica), update));
+            self.version.increment(self.replica);
+        }
+        let result = self.exchange();
+        if result.is_err() {
+            self.disconnect();
+        }
+        result
+    }
+
+    /// Closes the connection; the next [`SyncClient::sync`] reconnects and catches up.
+    pub fn disconnect(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            let _ = connection.stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    fn exchange(&mut self) -> Result<(), SyncError> {
+        if self.connection.is_none() {
+            self.connect()?;
+        }
+        let Some(connection) = &mut self.connection else {
+            return Ok(());
+        };
+        let mut frames = Vec::new();
+        for (seq, update) in self.unacked.iter().filter(|(seq, _)| *seq >= self.sent_seq) {
+            frames.extend(
+                Message::Update {
+                    replica: self.replica,
+                    seq: *seq,
+                    update: update.clone(),
+                }
+                .encode(),
+            );
+        }
+        connection.stream.write_all(&frames)?;
+        self.sent_seq = self.version.get(self.replica);
+
+        loop {
+            let message = match self.connection.as_ref().map(|c| c.received.try_recv()) {
+                Some(Ok(message)) => message?,
+                Some(Err(TryRecvError::Empty)) | None => return Ok(()),
+                Some(Err(TryRecvError::Disconnected)) => return Err(SyncError::Disconnected),
+            };
+            match message {
+                Message::Update {
+                    replica,
+                    seq,
+                    update,
+                } => {
+                    // own updates and the ones received before reconnecting are skipped
+                    if seq == self.version.get(replica) {
+                        self.table.apply_update(update);
+                        self.version.increment(replica);
+                    }
+                }
+                Message::Snapshot { version, updates } => {
+                    // what the table already has is skipped
+                    for update in updates {
+                        self.table.apply_update(update);
+                    }
+                    self.version.merge(&version);
+                }
+                other => return Err(SyncError::Protocol(format!("unexpected message {other:?}"))),
+            }
+        }
+    }
+
+    /// Connects, forgetting the own updates the server already has.
+    fn connect(&mut self) -> Result<(), SyncError> {
+        let mut stream = TcpStream::connect(self.addr)?;
+        stream.set_nodelay(true)?;
+        Message::Hello {
+            replica: self.replica,
+            base: self.base,
+            version: self.version.clone(),
+        }
+        .write_to(&mut stream)?;
+        let server_version = match Message::read_from(&mut stream)? {
+            Message::Welcome { version } => version,
+            Message::Refused { reason } => return Err(SyncError::Refused(reason)),
+            other => {
+                return Err(SyncError::Protocol(format!(
+                    "expected welcome, got {other:?}"
+                )))
+            }
+        };
+        let acked = server_version.get(self.replica);
+        self.unacked.retain(|(seq, _)| *seq >= acked);
+        self.sent_seq = acked;
+
+        let mut reader = stream.try_clone()?;
+        let (sender, received) = mpsc::channel();
+        thread::spawn(move || loop {
+            let message = Message::read_from(&mut reader);
+            let failed = message.is_err();
+            if sender.send(message).is_err() || failed {
+                return;
+            }
+        });
+        self.connection = Some(Connection { stream, received });
+        Ok(())
+    }
+}
+
+impl Drop for SyncClient<'_> {
+    fn drop(&mut self) {
+        self.disconnect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    fn start_server(txt: &str) -> (SyncServer, SocketAddr) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = SyncServer::new(txt.to_string());
+        let serving = server.clone();
+        thread::spawn(move || serving.serve(&listener));
+        (server, addr)
+    }
+
+    fn client(addr: SocketAddr, txt: &str, replica: u64) -> SyncClient<'_> {
+        SyncClient::new(
+            addr,
+            PieceTable::from_text(txt),
+            NonZeroU64::new(replica).unwrap(),
+        )
+    }
+
+    /// Syncs `clients` until all of them and `server` have the same text.
+    fn sync_all(server: &SyncServer, clients: &mut [&mut SyncClient]) -> String {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            for client in clients.iter_mut() {
+                client.sync().unwrap();
+            }
+            let txt = server.text();
+            if clients.iter().all(|client| client.table().project() == txt) {
+                return txt;
+            }
+            assert!(Instant::now() < deadline, "clients didn't converge");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn should_share_edits_between_clients() {
+        init_logger();
+        // given
+        let (server, addr) = start_server("hello world");
+        let mut a = client(addr, "hello world", 1);
+        let mut b = client(addr, "hello world", 2);
+        a.sync().unwrap();
+        b.sync().unwrap();
+
+        // when
+        a.table_mut().insert_str("big ", 6);
+        b.table_mut().insert_char('!', 11);
+        let txt = sync_all(&server, &mut [&mut a, &mut b]);
+
+        // then
+        assert_eq!(txt, "hello big world!");
+        assert_eq!(a.version(), b.version());
+    }
+
+    #[test]
+    fn should_catch_up_after_reconnecting() {
+        init_logger();
+        // given
+        let (server, addr) = start_server("one two");
+        let mut a = client(addr, "one two", 1);
+        let mut b = client(addr, "one two", 2);
+        sync_all(&server, &mut [&mut a, &mut b]);
+        a.table_mut().insert_str("zero ", 0);
+        a.sync().unwrap();
+
+        // when
+        a.disconnect();
+        a.table_mut().insert_str(" three", 12);
+        b.table_mut().remove(0..4);
+        b.sync().unwrap();
+        let reconnected = a.sync();
+        let txt = sync_all(&server, &mut [&mut a, &mut b]);
+
+        // then
+        assert!(reconnected.is_ok());
+        assert_eq!(txt, "zero two three");
+        assert_eq!(server.version(), *a.version());
+    }
+
+    #[test]
+    fn should_bring_new_client_up_to_date() {
+        init_logger();
+        // given
+        let (server, addr) = start_server("text");
+        let mut a = client(addr, "text", 1);
+        a.table_mut().insert_str("some ", 0);
+        a.table_mut().remove(5..6);
+        sync_all(&server, &mut [&mut a]);
+
+        // when
+        let mut b = client(addr, "text", 2);
+        let txt = sync_all(&server, &mut [&mut a, &mut b]);
+
+        // then
+        assert_eq!(txt, "some ext");
+        assert_eq!(b.version().get(1), 2);
+    }
+
+    #[test]
+    fn should_refuse_client_starting_from_other_text() {
+        init_logger();
+        // given
+        let (_server, addr) = start_server("text");
+        let mut other = client(addr, "other text", 1);
+
+        // when
+        let result = other.sync();
+
+        // then
+        assert!(matches!(result, Err(SyncError::Refused(_))));
+        assert!(!other.is_connected());
+    }
+
+    #[test]
+    fn should_refuse_reserved_and_connected_replicas() {
+        init_logger();
+        // given
+        let (server, addr) = start_server("text");
+        let mut a = client(addr, "text", 1);
+        sync_all(&server, &mut [&mut a]);
+        let mut same = client(addr, "text", 1);
+        let mut server_replica = client(addr, "text", u64::MAX);
+
+        // when
+        let results = (same.sync(), server_replica.sync());
+
+        // then
+        assert!(matches!(results.0, Err(SyncError::Refused(_))));
+        assert!(matches!(results.1, Err(SyncError::Refused(_))));
+        assert!(a.sync().is_ok());
+    }
+
+    #[test]
+    fn should_refuse_replica_of_base_text() {
+        init_logger();
+        // given
+        let (_server, addr) = start_server("text");
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        // when
+        Message::Hello {
+            replica: 0,
+            base: session::hash(b"text"),
+            version: VersionVector::new(),
+        }
+        .write_to(&mut stream)
+        .unwrap();
+
+        // then
+        assert!(matches!(
+            Message::read_from(&mut stream).unwrap(),
+            Message::Refused { .. }
+        ));
+    }
+
+    #[test]
+    fn should_drop_client_sending_invalid_update() {
+        init_logger();
+        // given
+        let (server, addr) = start_server("\u{17c}x");
+        let mut stream = TcpStream::connect(addr).unwrap();
+        Message::Hello {
+            replica: 1,
+            base: session::hash("\u{17c}x".as_bytes()),
+            version: VersionVector::new(),
+        }
+        .write_to(&mut stream)
+        .unwrap();
+        Message::read_from(&mut stream).unwrap();
+
+        // when
+        Message::Update {
+            replica: 1,
+            seq: 0,
+            update: Update::Delete {
+                id: ItemId {
+                    replica: 0,
+                    counter: 1,
+                },
+                len: 1,
+            },
+        }
+        .write_to(&mut stream)
+        .unwrap();
+        let closed = Message::read_from(&mut stream);
+
+        // then
+        assert!(closed.is_err());
+        assert_eq!(server.text(), "\u{17c}x");
+        assert_eq!(server.version(), VersionVector::new());
+    }
+
+    #[test]
+    fn should_drop_client_sending_updates_of_other_replica() {
+        init_logger();
+        // given
+        let (server, addr) = start_server("text");
+        let mut a = client(addr, "text", 1);
+        sync_all(&server, &mut [&mut a]);
+        let mut stream = TcpStream::connect(addr).unwrap();
+        Message::Hello {
+            replica: 2,
+            base: session::hash(b"text"),
+            version: VersionVector::new(),
+        }
+        .write_to(&mut stream)
+        .unwrap();
+        Message::read_from(&mut stream).unwrap();
+
+        // when
+        Message::Update {
+            replica: 1,
+            seq: 0,
+            update: Update::Delete {
+                id: ItemId {
+                    replica: 0,
+                    counter: 0,
+                },
+                len: 1,
+            },
+        }
+        .write_to(&mut stream)
+        .unwrap();
+        let closed = Message::read_from(&mut stream);
+
+        // then
+        assert!(closed.is_err());
+        assert_eq!(server.text(), "text");
+    }
+
+    #[test]
+    fn should_catch_up_from_snapshot_of_compacted_log() {
+        init_logger();
+        // given
+        let (server, addr) = start_server("text");
+        server.lock().log_limit = 2;
+        let mut a = client(addr, "text", 1);
+        let mut b = client(addr, "text", 2);
+        sync_all(&server, &mut [&mut a, &mut b]);
+        b.disconnect();
+        b.table_mut().insert_str("old ", 0);
+
+        // when
+        for txt in ["one ", "two ", "three "] {
+            let table = a.table_mut();
+            table.insert_str(txt, table.len());
+            table.remove(0..1);
+            a.sync().unwrap();
+        }
+        let mut c = client(addr, "text", 3);
+        let txt = sync_all(&server, &mut [&mut a, &mut b, &mut c]);
+
+        // then
+        assert!(server.lock().log.len() <= 2);
+        assert_eq!(txt, "old tone two three ");
+        assert_eq!(*c.version(), server.version());
+    }
+
+    #[test]
+    fn should_decode_encoded_messages() {
+        init_logger();
+        // given
+        let mut version = VersionVector::new();
+        version.increment(7);
+        version.increment(u64::MAX);
+        let messages = vec![
+            Message::Hello {
+                replica: 3,
+                base: 42,
+                version: version.clone(),
+            },
+            Message::Welcome { version },
+            Message::Update {
+                replica: 1,
+                seq: 300,
+                update: Update::Insert {
+                    id: ItemId {
+                        replica: 1,
+                        counter: 9,
+                    },
+                    origin_left: None,
+                    origin_right: Some(ItemId {
+                        replica: 0,
+                        counter: 2,
+                    }),
+                    text: "zażółć".to_string(),
+                },
+            },
+            Message::Update {
+                replica: 2,
+                seq: 0,
+                update: Update::Delete {
+                    id: ItemId {
+                        replica: 1,
+                        counter: 4,
+                    },
+                    len: 3,
+                },
+            },
+            Message::Refused {
+                reason: "no".to_string(),
+            },
+            Message::Snapshot {
+                version: VersionVector::new(),
+                updates: vec![Update::Delete {
+                    id: ItemId {
+                        replica: 0,
+                        counter: 0,
+                    },
+                    len: 1,
+                }],
+            },
+        ];
+        let bytes: Vec<u8> = messages.iter().flat_map(Message::encode).collect();
+
+        // when
+        let mut reader = bytes.as_slice();
+        let decoded: Vec<_> = messages
+            .iter()
+            .map(|_| Message::read_from(&mut reader).unwrap())
+            .collect();
+
+        // then
+        assert_eq!(decoded, messages);
+        assert!(matches!(
+            Message::read_from(&mut reader),
+            Err(SyncError::Disconnected)
+        ));
+    }
+}
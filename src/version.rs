@@ -0,0 +1,468 @@
+use crate::history::Edit;
+use crate::{Operation, Piece, PieceTable};
+use std::time::SystemTime;
+
+/// Steps between checkpoints, which bounds how many steps are replayed to reach a version.
+const CHECKPOINT_INTERVAL: usize = 64;
+
+/// Versions kept by default, see [`PieceTable::set_version_limit`].
+const VERSION_LIMIT: usize = 4096;
+
+/// State of the table after a number of edits, undos and redos, see [`PieceTable::version`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version(usize);
+
+#[derive(Debug)]
+enum Step {
+    Apply(Edit),
+    Revert(Edit),
+}
+
+/// Every step the table went through since the oldest version kept, with copies of the pieces at
+/// some of them. Pieces only point into buffers which are never truncated, so they describe old
+/// versions as long as they are kept.
+#[derive(Debug)]
+pub(crate) struct Versions {
+    /// Oldest version kept, the ones before it were forgotten.
+    first: usize,
+    /// When the oldest version kept was made.
+    start: SystemTime,
+    /// Steps from the oldest version kept, with when they were made.
+    steps: Vec<(SystemTime, Step)>,
+    /// Pieces at some versions, in order of the versions, the first one at the oldest version.
+    checkpoints: Vec<(usize, Vec<Piece>)>,
+    /// Steps kept, older ones are forgotten once there are a checkpoint interval more.
+    limit: usize,
+}
+
+impl Versions {
+    pub(crate) fn new(pieces: &[Piece]) -> Self {
+        Self {
+            first: 0,
+            start: SystemTime::now(),
+            steps: Vec::new(),
+            checkpoints: vec![(0, pieces.to_vec())],
+            limit: VERSION_LIMIT,
+        }
+    }
+
+    pub(crate) fn applied(&mut self, edit: &Edit, pieces: &[Piece]) {
+        self.push(Step::Apply(edit.clone()), pieces);
+    }
+
+    pub(crate) fn reverted(&mut self, edit: &Edit, pieces: &[Piece]) {
+        self.push(Step::Revert(edit.clone()), pieces);
+    }
+
+    fn push(&mut self, step: Step, pieces: &[Piece]) {
+        // the clock may go back, while versions are searched by time
+        let last = self.steps.last().map_or(self.start, |(made, _)| *made);
+        self.steps.push((SystemTime::now().max(last), step));
+        let version = self.current().0;
+        if version.is_multiple_of(CHECKPOINT_INTERVAL) {
+            self.checkpoints.push((version, pieces.to_vec()));
+        }
+        // forgetting in batches keeps the cost of draining the steps low
+        if self.steps.len() >= self.limit.saturating_add(CHECKPOINT_INTERVAL) {
+            self.forget_before(Version(version - self.limit));
+        }
+    }
+
+    fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        let current = self.current().0;
+        if self.steps.len() > limit {
+            self.forget_before(Version(current - limit));
+        }
+    }
+
+    fn current(&self) -> Version {
+        Version(self.first + self.steps.len())
+    }
+
+    fn at_time(&self, time: SystemTime) -> Option<Version> {
+        if time < self.start {
+            return None;
+        }
+        Some(Version(
+            self.first + self.steps.partition_point(|(made, _)| *made <= time),
+        ))
+    }
+
+    fn pieces_at(&self, version: Version) -> Option<Vec<Piece>> {
+        if version.0 < self.first || version.0 > self.current().0 {
+            return None;
+        }
+        let checkpoint = self.checkpoints.partition_point(|(at, _)| *at <= version.0) - 1;
+        let (at, pieces) = &self.checkpoints[checkpoint];
+        let mut pieces = pieces.clone();
+        for (_, step) in &self.steps[at - self.first..version.0 - self.first] {
+            match step {
+                Step::Apply(edit) => {
+                    for change in edit.changes() {
+                        change.apply(&mut pieces);
+                    }
+                }
+                Step::Revert(edit) => {
+                    for change in edit.changes().iter().rev() {
+                        change.revert(&mut pieces);
+                    }
+                }
+            }
+        }
+        Some(pieces)
+    }
+
+    /// Forgets the versions before `version`, returning `false` if there is no such version.
+    fn forget_before(&mut self, version: Version) -> bool {
+        let Some(pieces) = self.pieces_at(version) else {
+            return false;
+        };
+        let forgotten = version.0 - self.first;
+        if forgotten > 0 {
+            self.start = self.steps[forgotten - 1].0;
+        }
+        self.steps.drain(..forgotten);
+        self.checkpoints.retain(|(at, _)| *at > version.0);
+        self.checkpoints.insert(0, (version.0, pieces));
+        self.first = version.0;
+        true
+    }
+}
+
+impl PieceTable<'_> {
+    /// Returns the current version. Every edit, undo and redo makes a new one, including ones
+    /// going back to the text of an older version.
+    #[must_use]
+    pub fn version(&self) -> Version {
+        self.versions.current()
+    }
+
+    /// Returns the version current at `time`, or `None` if the table didn't exist yet or the
+    /// version was forgotten.
+    #[must_use]
+    pub fn version_at(&self, time: SystemTime) -> Option<Version> {
+        self.versions.at_time(time)
+    }
+
+    /// Returns the text at `version`, or `None` if there is no such version.
+    #[must_use]
+    pub fn text_at(&self, version: Version) -> Option<String> {
+        let pieces = self.versions.pieces_at(version)?;
+        Some(self.text_of(&pieces))
+    }
+
+    /// Forgets the versions older than `version` to free the memory they take, after which
+    /// [`PieceTable::version_at`] and [`PieceTable::text_at`] don't find them anymore, and
+    /// branches forked before can't be merged. Returns `false` if there is no such version.
+    pub fn forget_versions_before(&mut self, version: Version) -> bool {
+        self.versions.forget_before(version)
+    }
+
+    /// Sets how many versions before the current one are kept, 4096 by default. Older versions
+    /// are forgotten as edits are made, like with [`PieceTable::forget_versions_before`], though
+    /// up to 64 more may be kept for a while. `usize::MAX` keeps all of them.
+    pub fn set_version_limit(&mut self, limit: usize) {
+        self.versions.set_limit(limit);
+    }
+
+    /// Returns the operation turning the text at `version` into the current one, made of the
+    /// recorded steps, or `None` if there is no such version.
+    pub(crate) fn operation_since(&self, version: Version) -> Option<Operation> {
+        let mut pieces = self.versions.pieces_at(version)?;
+        let mut operation = Operation::new().retain(pieces.iter().map(Piece::len).sum());
+        for (_, step) in &self.versions.steps[version.0 - self.versions.first..] {
+            let mut steps = Vec::new();
+            match step {
+                Step::Apply(edit) => {
+                    for change in edit.changes() {
+                        steps.push(self.change_operation(
+                            &pieces,
+                            change.at(),
+                            change.removed(),
+                            change.inserted(),
+                        ));
+                        change.apply(&mut pieces);
+                    }
+                }
+                Step::Revert(edit) => {
+                    for change in edit.changes().iter().rev() {
+                        steps.push(self.change_operation(
+                            &pieces,
+                            change.at(),
+                            change.inserted(),
+                            change.removed(),
+                        ));
+                        change.revert(&mut pieces);
+                    }
+                }
+            }
+            for step in steps {
+                operation = operation.compose(&step).expect("steps follow each other");
+            }
+        }
+        Some(operation)
+    }
+
+    fn text_of(&self, pieces: &[Piece]) -> String {
+        pieces.iter().map(|piece| self.piece_text(piece)).collect()
+    }
+
+    /// Returns a table with the text at `version`, or `None` if there is no such version. The
+    /// table has no history, and shares the original text and the shared buffers with this one,
+    /// but gets a copy of the whole addition buffer and its authorship, like [`PieceTable::fork`]
+    /// does.
+    #[must_use]
+    pub fn snapshot_at(&self, version: Version) -> Option<PieceTable<'_>> {
+        let pieces = self.versions.pieces_at(version)?;
+        let mut snapshot = PieceTable::from_parts(
+            self.original_buffer.clone(),
+            self.addition_buffer.clone(),
+            pieces,
+            Vec::new(),
+            Vec::new(),
+        );
+        snapshot.buffers.clone_from(&self.buffers);
+        snapshot.authors = self.authors.clone();
+        Some(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroU64;
+    use std::time::Duration;
+
+    fn init_logger() {
+        let _ = env_logger::try_init();
+    }
+
+    fn letter(idx: usize) -> char {
+        char::from(b'a' + u8::try_from(idx % 26).unwrap())
+    }
+
+    #[test]
+    fn should_make_version_per_edit_undo_and_redo() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("text");
+        let first = table.version();
+
+        // when
+        table.insert_str("more ", 0);
+        table.undo();
+        table.redo();
+
+        // then
+        assert_eq!(first, Version(0));
+        assert_eq!(table.version(), Version(3));
+    }
+
+    #[test]
+    fn should_give_text_at_older_versions() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("hello");
+        table.insert_str(" world", 5);
+        let with_world = table.version();
+        table.remove(0..6);
+        table.undo();
+        table.insert_char('!', 11);
+
+        // when
+        let texts: Vec<_> = (0..=5).map(|v| table.text_at(Version(v))).collect();
+
+        // then
+        assert_eq!(table.text_at(with_world).unwrap(), "hello world");
+        assert_eq!(
+            texts,
+            vec![
+                Some("hello".to_string()),
+                Some("hello world".to_string()),
+                Some("world".to_string()),
+                Some("hello world".to_string()),
+                Some("hello world!".to_string()),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn should_replay_from_checkpoints() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("");
+        for idx in 0..200 {
+            table.insert_char(letter(idx), idx);
+        }
+        table.remove(0..100);
+
+        // when
+        let text = table.text_at(Version(150));
+
+        // then
+        let expected: String = (0..150).map(letter).collect();
+        assert_eq!(text.unwrap(), expected);
+        assert_eq!(table.text_at(table.version()).unwrap(), table.project());
+    }
+
+    #[test]
+    fn should_give_snapshot_to_edit_further() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("one two");
+        let base = table.version();
+        table.remove(3..7);
+
+        // when
+        let mut snapshot = table.snapshot_at(base).unwrap();
+        snapshot.insert_str(" three", 7);
+
+        // then
+        assert_eq!(snapshot.project(), "one two three");
+        assert_eq!(table.project(), "one");
+    }
+
+    #[test]
+    fn should_keep_authorship_in_snapshot() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("text");
+        table.set_author(Some(5));
+        table.insert_str("old ", 0);
+        let base = table.version();
+        table.remove(0..4);
+
+        // when
+        let snapshot = table.snapshot_at(base).unwrap();
+
+        // then
+        let blame = snapshot.blame(0..8).unwrap();
+        assert_eq!(blame[0].range, 0..4);
+        assert_eq!(blame[0].authorship.map(|a| a.author), Some(5));
+        assert_eq!(blame[1].authorship, None);
+    }
+
+    #[test]
+    fn should_find_version_at_time() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("text");
+        let before = SystemTime::now();
+        table.insert_char('s', 4);
+        let after = SystemTime::now();
+
+        // when
+        let versions = (
+            table.version_at(before - Duration::from_hours(1)),
+            table.version_at(after),
+        );
+
+        // then
+        assert_eq!(versions, (None, Some(Version(1))));
+    }
+
+    #[test]
+    fn should_keep_versions_of_remote_changes() {
+        init_logger();
+        // given
+        let mut other = PieceTable::from_text("text");
+        other.enable_crdt(NonZeroU64::new(2).unwrap());
+        other.insert_str("remote ", 0);
+        let mut table = PieceTable::from_text("text");
+        table.enable_crdt(NonZeroU64::new(1).unwrap());
+        table.insert_char('!', 4);
+
+        // when
+        for update in other.take_updates() {
+            table.apply_update(update);
+        }
+        table.remove(0..7);
+
+        // then
+        assert_eq!(table.text_at(Version(1)).unwrap(), "text!");
+        assert_eq!(table.text_at(Version(2)).unwrap(), "remote text!");
+        assert_eq!(table.text_at(Version(3)).unwrap(), "text!");
+        assert_eq!(table.versions.checkpoints.len(), 1);
+    }
+
+    #[test]
+    fn should_forget_older_versions() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("");
+        for idx in 0..100 {
+            table.insert_char('a', idx);
+        }
+        let kept = Version(70);
+
+        // when
+        let forgotten = table.forget_versions_before(kept);
+
+        // then
+        assert!(forgotten);
+        assert_eq!(table.text_at(Version(69)), None);
+        assert_eq!(table.text_at(kept).unwrap(), "a".repeat(70));
+        assert_eq!(table.text_at(Version(100)).unwrap(), table.project());
+        assert_eq!(table.versions.checkpoints[0].0, 70);
+        assert_eq!(table.versions.steps.len(), 30);
+        assert!(!table.forget_versions_before(Version(101)));
+    }
+
+    #[test]
+    fn should_not_find_forgotten_versions_by_time() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("text");
+        let before = SystemTime::now();
+        table.insert_char('s', 4);
+        table.insert_char('!', 5);
+
+        // when
+        table.forget_versions_before(Version(1));
+
+        // then
+        assert_eq!(table.version_at(before), None);
+        assert_eq!(table.version_at(SystemTime::now()), Some(Version(2)));
+    }
+
+    #[test]
+    fn should_forget_versions_beyond_limit() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("");
+        table.set_version_limit(10);
+
+        // when
+        for idx in 0..100 {
+            table.insert_char(letter(idx), idx);
+        }
+
+        // then
+        assert!(table.versions.steps.len() < 10 + CHECKPOINT_INTERVAL);
+        assert_eq!(table.text_at(Version(63)), None);
+        let expected: String = (0..64).map(letter).collect();
+        assert_eq!(table.text_at(Version(64)).unwrap(), expected);
+        table.set_version_limit(5);
+        assert_eq!(table.versions.steps.len(), 5);
+        assert_eq!(table.text_at(Version(95)).unwrap(), &table.project()[..95]);
+    }
+
+    #[test]
+    fn should_keep_step_times_in_order_when_clock_goes_back() {
+        init_logger();
+        // given
+        let mut table = PieceTable::from_text("text");
+        table.insert_char('s', 4);
+        let late = SystemTime::now() + Duration::from_hours(1);
+        table.versions.steps[0].0 = late;
+
+        // when
+        table.insert_char('!', 5);
+
+        // then
+        assert_eq!(table.versions.steps[1].0, late);
+        assert_eq!(table.version_at(late), Some(Version(2)));
+    }
+}